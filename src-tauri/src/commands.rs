@@ -2,7 +2,12 @@
 
 use serde::Serialize;
 use specta::Type;
-use std::{env, path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tauri::async_runtime;
 use tokio::fs;
 
@@ -10,20 +15,46 @@ use tokio::fs;
 pub use crate::{
     errors::{TauriError, TauriResult},
     services::{
-        self, aria2c, ffmpeg,
+        self, aria2c,
+        audio::resolve_audio,
+        bili::{get_video_info, get_video_stats, submit_verification},
+        control::{self, import_url_list},
+        ffmpeg,
+        interactive::get_interactive_graph,
+        launch,
         login::{
-            self, exit, pwd_login, refresh_cookie, scan_login, sms_login, stop_login, switch_cookie,
+            self, exit, import_cookies, logout, pwd_login, refresh_cookie, scan_login, sms_login,
+            stop_login, switch_cookie,
         },
+        naming::preview_template,
+        power::system_sleep,
+        presets::{apply_preset, delete_preset, list_presets, save_preset},
         queue::{
             self,
+            export::export_commands,
+            preflight::{batch_access_report, preflight},
             runtime::{
-                ctrl_event, open_folder, process_queue, submit_task, update_max_conc, update_select,
+                archive_delete, cancel_matching, ctrl_event, duplicate_task, find_by_labels,
+                get_select_children, get_select_plan, get_task_detail, get_tasks, label_add,
+                label_counts, label_remove, open_folder, prefetch_resolution, process_queue,
+                queue_export, queue_import, queue_stats, relink_file, relink_orphans,
+                relocate_storage, repair_archive, reveal_file, select_audio, select_resolution,
+                submit_task, update_max_conc, update_post_conc, update_resolve_conc, update_select,
             },
         },
+        subscribe::{
+            cancel_enumeration, subscription_add, subscription_clear_cursor, subscription_list,
+            subscription_refresh, subscription_remove,
+        },
+        updater::check_update_now,
+    },
+    shared::{
+        self, get_app_handle, get_window_state, set_window, set_window_state, InitStatus, HEADERS,
+        INIT_STATUS, READY,
     },
-    shared::{self, get_app_handle, set_window, HEADERS, READY},
     storage::{
         self, archive,
+        bundle::{self, DataCategory},
         config::{self, CacheKey},
         cookies, db,
     },
@@ -34,6 +65,110 @@ pub struct InitData {
     version: String,
     hash: String,
     config: Arc<config::Settings>,
+    safe_mode: bool,
+    diagnostics: Diagnostics,
+}
+
+/// Environment details for the about screen and bug reports; holds no credentials.
+#[derive(Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    os: String,
+    os_version: String,
+    arch: String,
+    webview: Option<String>,
+    tauri: String,
+    ffmpeg: Option<String>,
+    ffmpeg_path: Option<PathBuf>,
+    schema: BTreeMap<String, i32>,
+    uid: Option<String>,
+    storage: PathBuf,
+}
+
+async fn diagnostics() -> TauriResult<Diagnostics> {
+    let ffmpeg = ffmpeg::version()
+        .await
+        .map_err(|e| log::warn!("Failed to get FFmpeg version: {e}"))
+        .ok();
+    // `meta` may run before storage is initialized, so database lookups are best-effort
+    let uid = cookies::load()
+        .await
+        .ok()
+        .and_then(|v| v.get("DedeUserID").cloned());
+    Ok(Diagnostics {
+        os: tauri_plugin_os::platform().into(),
+        os_version: tauri_plugin_os::version().to_string(),
+        arch: tauri_plugin_os::arch().into(),
+        webview: tauri::webview_version().ok(),
+        tauri: tauri::VERSION.into(),
+        ffmpeg,
+        ffmpeg_path: ffmpeg::path(),
+        schema: db::versions().await.unwrap_or_default(),
+        uid,
+        storage: config::read().get_cache(&CacheKey::Database)?,
+    })
+}
+
+#[derive(Serialize, Type)]
+pub struct LogFile {
+    name: String,
+    size: u64,
+    ts: i64,
+    active: bool,
+}
+
+/// Files still being written to by the log plugin or aria2c.
+fn active_logs(app: &tauri::AppHandle) -> [String; 2] {
+    [
+        format!("{}.log", app.package_info().name),
+        "aria2.log".into(),
+    ]
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn list_logs(app: tauri::AppHandle) -> TauriResult<Vec<LogFile>> {
+    let path = config::read().get_cache(&CacheKey::Log)?;
+    let active = active_logs(&app);
+    let mut list = vec![];
+    let mut entries = fs::read_dir(&path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let meta = entry.metadata().await?;
+        if !meta.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let ts = meta
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|v| v.as_secs() as i64)
+            .unwrap_or(0);
+        list.push(LogFile {
+            active: active.contains(&name),
+            name,
+            size: meta.len(),
+            ts,
+        });
+    }
+    list.sort_by(|a, b| b.ts.cmp(&a.ts));
+    Ok(list)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn delete_logs(app: tauri::AppHandle, before: i64) -> TauriResult<u64> {
+    let path = config::read().get_cache(&CacheKey::Log)?;
+    let mut freed = 0u64;
+    for log in list_logs(app).await? {
+        if log.active || log.ts >= before {
+            continue;
+        }
+        match fs::remove_file(path.join(&log.name)).await {
+            Ok(_) => freed += log.size,
+            Err(e) => log::warn!("Failed to delete log {}: {e}", log.name),
+        }
+    }
+    Ok(freed)
 }
 
 #[tauri::command(async)]
@@ -89,6 +224,113 @@ pub async fn clean_cache(key: CacheKey) -> TauriResult<()> {
     Ok(())
 }
 
+#[derive(Serialize, Type)]
+pub struct CacheSize {
+    key: CacheKey,
+    size: u64,
+}
+
+/// A top-level entry of the temp folder that no queued task owns.
+#[derive(Serialize, Type)]
+pub struct OrphanFile {
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(Serialize, Type)]
+pub struct CacheBreakdown {
+    sizes: Vec<CacheSize>,
+    orphans: Vec<OrphanFile>,
+}
+
+/// Walked off the async runtime, a large temp folder takes a while.
+async fn tree_size(path: PathBuf) -> TauriResult<u64> {
+    Ok(async_runtime::spawn_blocking(move || {
+        walkdir::WalkDir::new(path)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter_map(|v| v.metadata().ok())
+            .filter(|v| v.is_file())
+            .map(|v| v.len())
+            .sum()
+    })
+    .await?)
+}
+
+/// Temp entries are named after the task or subtask writing them, storyboards with a
+/// `_storyboard` suffix. Whatever matches none of the queued ones is left over from a
+/// task that was removed or a run that crashed. Safe mode doesn't load the queue, so
+/// nothing is reported then.
+async fn find_orphans() -> TauriResult<Vec<OrphanFile>> {
+    if storage::safe_mode() {
+        return Ok(vec![]);
+    }
+    let root = config::read().temp_dir();
+    let mut live = std::collections::HashSet::new();
+    for lock in queue::runtime::TASK_MANAGER.tasks.read().await.values() {
+        let task = lock.read().await;
+        live.insert(task.id.to_string());
+        live.extend(task.subtasks.iter().map(|v| v.id.to_string()));
+    }
+    let mut list = vec![];
+    let Ok(mut entries) = fs::read_dir(&root).await else {
+        return Ok(list);
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if live.contains(name.trim_end_matches("_storyboard")) {
+            continue;
+        }
+        let path = entry.path();
+        let size = tree_size(path.clone()).await?;
+        list.push(OrphanFile { path, size });
+    }
+    Ok(list)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn analyze_cache() -> TauriResult<CacheBreakdown> {
+    let cfg = config::read();
+    let mut sizes = vec![];
+    for key in [
+        CacheKey::Log,
+        CacheKey::Temp,
+        CacheKey::Webview,
+        CacheKey::Database,
+    ] {
+        let size = tree_size(cfg.get_cache(&key)?).await?;
+        sizes.push(CacheSize { key, size });
+    }
+    Ok(CacheBreakdown {
+        sizes,
+        orphans: find_orphans().await?,
+    })
+}
+
+/// Deletes only the temp entries [`analyze_cache`] reports as orphaned, unlike
+/// `clean_cache` which also takes those of paused tasks. Returns the bytes freed.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn clean_orphans() -> TauriResult<u64> {
+    if storage::safe_mode() {
+        return Err(anyhow::anyhow!("Orphans can't be told apart in safe mode").into());
+    }
+    let mut freed = 0;
+    for orphan in find_orphans().await? {
+        let result = if orphan.path.is_dir() {
+            fs::remove_dir_all(&orphan.path).await
+        } else {
+            fs::remove_file(&orphan.path).await
+        };
+        match result {
+            Ok(_) => freed += orphan.size,
+            Err(e) => log::warn!("Failed to delete {}: {e}", orphan.path.display()),
+        }
+    }
+    Ok(freed)
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn open_cache(key: CacheKey) -> TauriResult<()> {
@@ -100,14 +342,46 @@ pub async fn open_cache(key: CacheKey) -> TauriResult<()> {
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn config_write(settings: serde_json::Map<String, serde_json::Value>) -> TauriResult<()> {
+    for key in ["down_dir", "temp_dir"] {
+        if let Some(path) = settings.get(key).and_then(|v| v.as_str()) {
+            shared::check_dir(Path::new(path), key, 0).await?;
+        }
+    }
+    if let Some(size) = settings.get("write_buffer").and_then(|v| v.as_u64()) {
+        if size > aria2c::MAX_WRITE_BUFFER {
+            return Err(anyhow::anyhow!(
+                "write_buffer must be at most {} MiB, got {size}",
+                aria2c::MAX_WRITE_BUFFER
+            )
+            .into());
+        }
+    }
+    if let Some(hosts) = settings.get("api_hosts") {
+        shared::check_api_hosts(&serde_json::from_value(hosts.clone())?)?;
+    }
+    let scheme = settings.contains_key("url_scheme");
+    let control = settings.contains_key("control_port") || settings.contains_key("control_token");
     config::write(settings).await?;
+    if scheme {
+        launch::register()?;
+    }
+    if control {
+        control::restart().await;
+    }
     Ok(())
 }
 
+/// The settings in effect after defaults were filled in, with where each key came from.
 #[tauri::command(async)]
 #[specta::specta]
-pub async fn db_export(output: PathBuf) -> TauriResult<()> {
-    db::export(output).await?;
+pub async fn get_effective_settings() -> TauriResult<config::EffectiveSettings> {
+    Ok(config::effective()?)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn db_export(output: PathBuf, compression: Option<db::Compression>) -> TauriResult<()> {
+    db::export(output, compression).await?;
     Ok(())
 }
 
@@ -118,6 +392,13 @@ pub async fn db_import(input: PathBuf) -> TauriResult<()> {
     Ok(())
 }
 
+/// Connections the database pool holds, for diagnosing busy or lock timeouts.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn db_pool_stats() -> TauriResult<db::PoolStats> {
+    Ok(db::pool_stats()?)
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn export_data(output: PathBuf, data: serde_json::Value) -> TauriResult<()> {
@@ -126,6 +407,31 @@ pub async fn export_data(output: PathBuf, data: serde_json::Value) -> TauriResul
     Ok(())
 }
 
+/// Exports settings, login and download history as a shareable bundle. Cookies are
+/// credentials, so they also need `include_cookies`.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn export_user_data(
+    app: tauri::AppHandle,
+    output: PathBuf,
+    categories: Vec<DataCategory>,
+    include_cookies: bool,
+) -> TauriResult<()> {
+    let version = app.package_info().version.to_string();
+    bundle::export(output, categories, include_cookies, version).await?;
+    Ok(())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn import_user_data(input: PathBuf) -> TauriResult<Vec<DataCategory>> {
+    let categories = bundle::import(input).await?;
+    if categories.contains(&DataCategory::Cookies) {
+        HEADERS.refresh().await?;
+    }
+    Ok(categories)
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn meta(app: tauri::AppHandle) -> TauriResult<InitData> {
@@ -136,9 +442,32 @@ pub async fn meta(app: tauri::AppHandle) -> TauriResult<InitData> {
         version,
         hash,
         config,
+        safe_mode: storage::safe_mode(),
+        diagnostics: diagnostics().await?,
     })
 }
 
+/// Headers of the active account as sent with requests, for handing a download to an
+/// external tool. `mask_cookie` keeps the cookie names but hides their values.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_headers(mask_cookie: bool) -> TauriResult<BTreeMap<String, String>> {
+    let mut map = HEADERS.snapshot().await;
+    if mask_cookie {
+        if let Some(cookie) = map.get_mut("Cookie") {
+            *cookie = queue::export::mask_cookie(cookie);
+        }
+    }
+    Ok(map)
+}
+
+/// Whether the startup in `setup` has finished, for windows that missed `InitStatus`.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn is_ready() -> TauriResult<InitStatus> {
+    Ok((**INIT_STATUS.load()).clone())
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn init() -> TauriResult<()> {
@@ -146,11 +475,17 @@ pub async fn init() -> TauriResult<()> {
         #[cfg(not(debug_assertions))]
         return Err(anyhow::anyhow!("403 Forbidden").into());
     }
-    queue::runtime::TASK_MANAGER.snapshot(true).await?;
-    login::stop_login();
-    login::get_buvid().await?;
-    login::get_bili_ticket().await?;
-    login::get_uuid().await?;
-    HEADERS.refresh().await?;
-    Ok(())
+    let result = async {
+        queue::runtime::TASK_MANAGER.snapshot(true).await?;
+        login::stop_login();
+        login::get_buvid().await?;
+        login::get_bili_ticket().await?;
+        login::get_uuid().await?;
+        HEADERS.refresh().await?;
+        TauriResult::Ok(())
+    }
+    .await;
+    // READY is set either way, so links held until now would otherwise stay held
+    launch::flush().await;
+    result
 }
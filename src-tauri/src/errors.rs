@@ -1,10 +1,14 @@
 use serde::{ser::SerializeStruct, Deserialize, Serialize};
 use specta::Type;
-use std::fmt;
+use std::{collections::BTreeMap, fmt};
 use tauri::http::StatusCode;
+use tauri_plugin_http::reqwest;
 
 pub type TauriResult<T> = Result<T, TauriError>;
 
+/// Interpolation values for the frontend's localized template of an [`ErrorKind`].
+pub type ErrorParams = BTreeMap<String, String>;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Copy, Type)]
 #[serde(untagged)]
 pub enum AnyInt {
@@ -60,10 +64,64 @@ impl AnyInt {
     }
 }
 
+/// Backend-side classification of an error, independent from the numeric `code`
+/// which usually carries Bilibili's or a process' own status code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    RegionLocked,
+    DirUnavailable,
+    DirNotWritable,
+    InsufficientSpace,
+    Network,
+    ProcessExited,
+    AuthExpired,
+    RiskControl,
+    QualityUnavailable,
+    /// Charge-exclusive (充电专属) content the account hasn't charged for.
+    NotEntitled,
+    /// Ran past `max_task_duration`.
+    Timeout,
+}
+
+// Bilibili's answers for a video that was deleted, is under review or hidden by its
+// uploader
+pub const GONE_CODES: [isize; 4] = [-404, 62002, 62004, 62012];
+
+// Bilibili's answer when the cookie is missing or no longer accepted
+pub const AUTH_EXPIRED_CODE: isize = -101;
+
+// Shared with region locks, which `TauriError::classify` tells apart by the message
+pub const VIP_ONLY_CODE: isize = -10403;
+
+// Bilibili answers geo-restricted requests with these codes, but -10403 is also
+// used for VIP-only content, so the message has to be checked too.
+pub const REGION_LOCKED_CODES: [isize; 2] = [VIP_ONLY_CODE, 6002003];
+
+pub const RISK_CONTROL_CODE: isize = -352;
+
+// Play url answers for charge-exclusive videos the account hasn't charged for
+pub const NOT_ENTITLED_CODES: [isize; 2] = [87007, 87008];
+
+// The HTTP status and the code Bilibili's JSON answers carry when requests are throttled
+pub const RATE_LIMIT_CODES: [isize; 2] = [412, -412];
+
+/// What it takes to get past a failure, so the frontend can offer a retry, the action
+/// `kind` points to (logging in again, a proxy, a purchase), or nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum Recovery {
+    Retry,
+    Action,
+    Permanent,
+}
+
 #[derive(Debug, Type)]
 pub struct TauriError {
     pub code: Option<AnyInt>,
     pub message: String,
+    pub kind: Option<ErrorKind>,
+    pub params: ErrorParams,
 }
 
 impl TauriError {
@@ -74,6 +132,64 @@ impl TauriError {
         Self {
             code: code.map(Into::into),
             message: message.into(),
+            kind: None,
+            params: ErrorParams::new(),
+        }
+    }
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+    pub fn with_param(mut self, key: &str, value: impl ToString) -> Self {
+        self.params.insert(key.into(), value.to_string());
+        self
+    }
+    pub fn is_rate_limited(&self) -> bool {
+        self.code
+            .is_some_and(|v| RATE_LIMIT_CODES.contains(&v.saturating_isize()))
+    }
+    /// Sets `kind` from Bilibili's code and message when it isn't set yet, the same
+    /// way for answers `bili::get` fetched and ones the frontend passed on.
+    pub fn classify(mut self) -> Self {
+        let Some(code) = self.code.map(|v| v.saturating_isize()) else {
+            return self;
+        };
+        self.kind = self.kind.or(
+            if REGION_LOCKED_CODES.contains(&code) && self.message.contains("地区") {
+                Some(ErrorKind::RegionLocked)
+            } else if code == AUTH_EXPIRED_CODE {
+                Some(ErrorKind::AuthExpired)
+            } else if NOT_ENTITLED_CODES.contains(&code) {
+                Some(ErrorKind::NotEntitled)
+            } else if code == RISK_CONTROL_CODE {
+                Some(ErrorKind::RiskControl)
+            } else {
+                None
+            },
+        );
+        self
+    }
+    /// Anything not known to need the user, such as timeouts, rate limits or expired
+    /// stream urls, is worth a retry.
+    pub fn recovery(&self) -> Recovery {
+        let code = self.code.map(|v| v.saturating_isize());
+        match self.kind {
+            Some(ErrorKind::NotEntitled) => Recovery::Permanent,
+            Some(
+                ErrorKind::AuthExpired
+                | ErrorKind::RegionLocked
+                | ErrorKind::RiskControl
+                | ErrorKind::QualityUnavailable
+                | ErrorKind::DirUnavailable
+                | ErrorKind::DirNotWritable
+                | ErrorKind::InsufficientSpace,
+            ) => Recovery::Action,
+            Some(ErrorKind::Network | ErrorKind::ProcessExited | ErrorKind::Timeout) => {
+                Recovery::Retry
+            }
+            None if code.is_some_and(|v| GONE_CODES.contains(&v)) => Recovery::Permanent,
+            None if code == Some(VIP_ONLY_CODE) => Recovery::Action,
+            None => Recovery::Retry,
         }
     }
 }
@@ -83,9 +199,11 @@ impl Serialize for TauriError {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("TauriError", 2)?;
+        let mut state = serializer.serialize_struct("TauriError", 4)?;
         state.serialize_field("code", &self.code)?;
         state.serialize_field("message", &self.message)?;
+        state.serialize_field("kind", &self.kind)?;
+        state.serialize_field("params", &self.params)?;
         state.end()
     }
 }
@@ -104,7 +222,17 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self::new(err.into().to_string_chain(), Option::<isize>::None)
+        let err = err.into();
+        // Lets a dropped connection be told apart from errors a retry won't fix
+        let network = err.chain().any(|e| {
+            e.downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_connect() || e.is_timeout())
+        });
+        let result = Self::new(err.to_string_chain(), Option::<isize>::None);
+        match network {
+            true => result.with_kind(ErrorKind::Network),
+            false => result,
+        }
     }
 }
 
@@ -128,3 +256,94 @@ impl AnyhowErrorToStringChain for anyhow::Error {
             .join("\n")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coded(code: isize) -> TauriError {
+        TauriError::new("", Some(code))
+    }
+
+    fn kinded(kind: ErrorKind) -> TauriError {
+        TauriError::new("", Option::<isize>::None).with_kind(kind)
+    }
+
+    #[test]
+    fn kinds_map_to_recovery() {
+        use ErrorKind::*;
+        for kind in [Network, ProcessExited, Timeout] {
+            assert_eq!(kinded(kind).recovery(), Recovery::Retry, "{kind:?}");
+        }
+        for kind in [
+            AuthExpired,
+            RegionLocked,
+            RiskControl,
+            QualityUnavailable,
+            DirUnavailable,
+            DirNotWritable,
+            InsufficientSpace,
+        ] {
+            assert_eq!(kinded(kind).recovery(), Recovery::Action, "{kind:?}");
+        }
+        assert_eq!(kinded(NotEntitled).recovery(), Recovery::Permanent);
+    }
+
+    #[test]
+    fn kind_wins_over_code() {
+        let err = coded(VIP_ONLY_CODE).with_kind(ErrorKind::NotEntitled);
+        assert_eq!(err.recovery(), Recovery::Permanent);
+        let err = coded(-404).with_kind(ErrorKind::Network);
+        assert_eq!(err.recovery(), Recovery::Retry);
+    }
+
+    #[test]
+    fn bare_codes_map_to_recovery() {
+        for code in GONE_CODES {
+            assert_eq!(coded(code).recovery(), Recovery::Permanent, "{code}");
+        }
+        assert_eq!(coded(VIP_ONLY_CODE).recovery(), Recovery::Action);
+        // Rate limits, expired stream urls and anything unknown are worth a retry
+        for code in [412, -412, 403, -500, 0] {
+            assert_eq!(coded(code).recovery(), Recovery::Retry, "{code}");
+        }
+        let err = TauriError::new("", Option::<isize>::None);
+        assert_eq!(err.recovery(), Recovery::Retry);
+    }
+
+    #[test]
+    fn rate_limits_are_told_apart() {
+        assert!(coded(412).is_rate_limited());
+        assert!(coded(-412).is_rate_limited());
+        assert!(TauriError::new("", Some(StatusCode::PRECONDITION_FAILED)).is_rate_limited());
+        assert!(!coded(-404).is_rate_limited());
+        assert!(!TauriError::new("", Option::<isize>::None).is_rate_limited());
+    }
+
+    #[test]
+    fn codes_are_classified() {
+        let kind = |code, message| TauriError::new(message, Some(code)).classify().kind;
+        assert_eq!(
+            kind(-10403, "抱歉您所在地区不可观看！"),
+            Some(ErrorKind::RegionLocked)
+        );
+        assert_eq!(kind(6002003, "地区限制"), Some(ErrorKind::RegionLocked));
+        // Without the message it's the VIP-only answer, left to `recovery`
+        assert_eq!(kind(-10403, "大会员专享限制"), None);
+        assert_eq!(kind(AUTH_EXPIRED_CODE, ""), Some(ErrorKind::AuthExpired));
+        assert_eq!(kind(87007, ""), Some(ErrorKind::NotEntitled));
+        assert_eq!(kind(87008, ""), Some(ErrorKind::NotEntitled));
+        assert_eq!(kind(RISK_CONTROL_CODE, ""), Some(ErrorKind::RiskControl));
+        assert_eq!(kind(-404, ""), None);
+    }
+
+    #[test]
+    fn classify_keeps_a_set_kind() {
+        let err = coded(AUTH_EXPIRED_CODE)
+            .with_kind(ErrorKind::Network)
+            .classify();
+        assert_eq!(err.kind, Some(ErrorKind::Network));
+        let err = TauriError::new("地区", Option::<isize>::None).classify();
+        assert_eq!(err.kind, None);
+    }
+}
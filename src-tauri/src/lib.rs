@@ -25,22 +25,54 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         .commands(collect_commands![
             // 分组命令，提高可读性
             // 基础功能
-            meta, init, set_window, config_write, 
-            open_cache, get_size, clean_cache,
-            db_import, db_export, export_data,
+            meta, init, is_ready, set_window, config_write, get_effective_settings,
+            get_window_state, set_window_state, get_headers,
+            open_cache, get_size, clean_cache, analyze_cache, clean_orphans,
+            list_logs, delete_logs,
+            db_import, db_export, db_pool_stats, export_data,
+            export_user_data, import_user_data, check_update_now,
             
             // 登录相关
             stop_login, exit, sms_login, pwd_login,
-            switch_cookie, scan_login, refresh_cookie,
+            switch_cookie, scan_login, refresh_cookie, import_cookies,
+            logout,
             
             // 队列相关
-            submit_task, process_queue, open_folder,
-            ctrl_event, update_max_conc, update_select
+            submit_task, process_queue, open_folder, reveal_file,
+            relink_file, relink_orphans,
+            get_task_detail, get_tasks,
+            ctrl_event, cancel_matching, update_max_conc, update_select,
+            get_select_plan, get_select_children, update_resolve_conc, update_post_conc,
+            archive_delete, export_commands, select_audio, duplicate_task,
+            select_resolution, queue_stats, relocate_storage,
+            get_interactive_graph, system_sleep, repair_archive,
+            preflight, prefetch_resolution, import_url_list,
+            batch_access_report, preview_template,
+            queue_export, queue_import,
+            label_add, label_remove, find_by_labels, label_counts,
+            save_preset, list_presets, apply_preset, delete_preset,
+
+            // 元数据
+            get_video_info, get_video_stats, submit_verification,
+            resolve_audio,
+
+            // 订阅
+            subscription_add, subscription_list,
+            subscription_refresh, subscription_remove,
+            subscription_clear_cursor, cancel_enumeration
         ])
         .events(collect_events![
             shared::HeadersData,
+            shared::InitStatus,
             shared::ProcessError,
-            queue::runtime::QueueEvent
+            queue::runtime::QueueEvent,
+            services::subscribe::SubscriptionEvent,
+            services::subscribe::EnumerationProgress,
+            services::control::ControlSubmit,
+            services::launch::LinkOpened,
+            services::bili::RiskControlEvent,
+            services::login::AccountChanged,
+            services::login::LoginCookies
         ]);
 
     // 2. 开发环境专用配置
@@ -83,6 +115,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         // 核心插件
         .plugin(log_builder.build())
         .plugin(tauri_plugin_clipboard_manager::init())
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_opener::init())
@@ -90,16 +123,25 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_shell::init())
         // 单实例处理
-        .plugin(tauri_plugin_single_instance::init(|app, _, _| {
+        .plugin(tauri_plugin_single_instance::init(|app, argv, _| {
             app.get_webview_window("main")
                 .expect("No main window found")
                 .set_focus()
                 .expect("Failed to focus window");
+            services::launch::second_launch(argv);
         }))
         // 自动更新
         .plugin(tauri_plugin_updater::Builder::new().build())
         // 命令处理器
         .invoke_handler(builder.invoke_handler())
+        // 关闭前保存窗口位置
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { .. } = event {
+                if window.label() == "main" {
+                    shared::persist_window(window);
+                }
+            }
+        })
         // 应用初始化
         .setup(move |app| {
             // 记录版本信息
@@ -119,9 +161,14 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
             
             // 异步初始化
             tauri::async_runtime::spawn(async move {
-                storage::init().await?;
-                services::init().await?;
-                Ok::<(), crate::TauriError>(())
+                let res = async {
+                    storage::init().await?;
+                    shared::restore_window();
+                    services::init().await?;
+                    Ok::<(), crate::TauriError>(())
+                };
+                shared::finish_init(res.await);
+                services::launch::flush().await;
             });
             
             Ok(())
@@ -3,7 +3,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::{
     net::TcpListener,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, LazyLock},
     time::Duration,
 };
@@ -21,12 +21,25 @@ use tokio::{fs, sync::RwLock, time::sleep};
 
 use crate::{
     config,
-    errors::TauriError,
-    queue::runtime::Progress,
-    shared::{get_app_handle, random_string, ProcessError, USER_AGENT, WORKING_PATH},
+    errors::{ErrorKind, ErrorParams, Recovery, TauriError},
+    queue::{preflight, runtime::Progress},
+    services::network::CONNECTIONS,
+    shared::{get_app_handle, part_path, random_string, ProcessError, USER_AGENT, WORKING_PATH},
     TauriResult,
 };
 
+// aria2's own default for `split`
+const SPLIT: usize = 5;
+
+/// Upper bound of `write_buffer` in MiB, each running aria2c may hold this much in memory.
+pub const MAX_WRITE_BUFFER: u64 = 256;
+
+// Attempts to fetch the missing tail of a download that finished short
+const MAX_TRUNCATED_RETRIES: usize = 3;
+
+// Exit statuses for a timeout, a network problem and a failed name resolution
+const NETWORK_ERRORS: [isize; 3] = [2, 6, 19];
+
 static ARIA2_RPC: LazyLock<Arc<Aria2Rpc>> = LazyLock::new(|| Arc::new(Aria2Rpc::new()));
 
 #[cfg(not(target_os = "linux"))]
@@ -191,7 +204,10 @@ async fn daemon(name: String, child: &CommandChild, rx: &mut Receiver<CommandEve
             if let Err(e) = ARIA2_RPC.request::<Value>("getGlobalStat", vec![]).await {
                 let _ = ProcessError {
                     name: name_clone.clone(),
+                    recovery: Some(e.recovery()),
                     error: e.message,
+                    kind: e.kind,
+                    params: e.params,
                 }
                 .emit(app);
             }
@@ -219,7 +235,14 @@ async fn daemon(name: String, child: &CommandChild, rx: &mut Receiver<CommandEve
                 CommandEvent::Terminated(msg) => {
                     let code = msg.code.unwrap_or(0);
                     let _ = ProcessError {
-                        name: name.clone(), error: format!("Process {name} ({pid}) exited ({code})\nSee logs for more infos.")
+                        name: name.clone(), error: format!("Process {name} ({pid}) exited ({code})\nSee logs for more infos."),
+                        kind: Some(ErrorKind::ProcessExited),
+                        params: ErrorParams::from([
+                            ("name".into(), name.clone()),
+                            ("pid".into(), pid.to_string()),
+                            ("code".into(), code.to_string()),
+                        ]),
+                        recovery: Some(Recovery::Retry),
                     }.emit(app);
                     log::error!("{name} exited with following STDERR:\n {}", stderr.join("\n"));
                     break;
@@ -250,6 +273,7 @@ pub async fn init() -> Result<()> {
 
     let log_file = app.path().app_log_dir()?.join("aria2.log");
     let log_file = log_file.to_string_lossy();
+    let write_buffer = config::read().write_buffer.min(MAX_WRITE_BUFFER);
     let (mut rx, child) = app
         .shell()
         .sidecar(EXEC)?
@@ -268,6 +292,7 @@ pub async fn init() -> Result<()> {
             format!("--rpc-listen-port={port}"),
             format!("--rpc-secret={secret}"),
             format!("--log={log_file}"),
+            format!("--disk-cache={write_buffer}M"),
         ])
         .spawn()?;
     async_runtime::spawn(async move {
@@ -290,8 +315,9 @@ pub async fn cancel(gid: Arc<String>) -> TauriResult<()> {
 }
 
 pub async fn pause(gid: Arc<String>) -> TauriResult<()> {
+    // forcePause drops connections right away instead of waiting for them to wind down
     ARIA2_RPC
-        .request::<Value>("pause", vec![json!(gid)])
+        .request::<Value>("forcePause", vec![json!(gid)])
         .await?;
     Ok(())
 }
@@ -303,8 +329,99 @@ pub async fn resume(gid: Arc<String>) -> TauriResult<()> {
     Ok(())
 }
 
-pub async fn download(gid: Arc<String>, tx: &Progress, urls: Vec<String>) -> TauriResult<PathBuf> {
-    let temp_root = config::read().temp_dir();
+/// Proxy options read when each download is added, so a changed proxy applies to the
+/// next download without restarting aria2c. aria2 only speaks HTTP proxies.
+fn proxy_options() -> Vec<(&'static str, String)> {
+    let proxy = config::read().proxy.clone();
+    if proxy.address.is_empty() {
+        return vec![];
+    }
+    if !proxy.address.starts_with("http") {
+        log::warn!(
+            "aria2c can't use proxy {}, downloading directly",
+            proxy.address
+        );
+        return vec![];
+    }
+    let mut options = vec![("all-proxy", proxy.address)];
+    if !proxy.username.is_empty() {
+        options.push(("all-proxy-user", proxy.username));
+        options.push(("all-proxy-passwd", proxy.password));
+    }
+    options
+}
+
+async fn add(
+    gid: &Arc<String>,
+    dir: &Path,
+    part_name: &str,
+    urls: &[String],
+    split: usize,
+    resume: bool,
+) -> TauriResult<()> {
+    let mut options = json!({
+        "dir": dir,
+        "out": part_name,
+        "gid": gid,
+        "split": split.to_string(),
+    });
+    for (key, value) in config::read().write_mode.aria2_options() {
+        options[key] = json!(value);
+    }
+    for (key, value) in proxy_options() {
+        options[key] = json!(value);
+    }
+    if resume {
+        // Without a control file aria2 continues from the end of the existing file
+        options["continue"] = json!("true");
+        options["file-allocation"] = json!("none");
+    }
+    ARIA2_RPC
+        .request::<Value>("addUri", vec![json!(urls), options])
+        .await?;
+    Ok(())
+}
+
+/// Bytes actually on disk for a download aria2 reports complete, which a CDN closing
+/// the connection early can leave short of `total`.
+async fn written(part: &Path, completed: u64) -> u64 {
+    let size = fs::metadata(part).await.map(|v| v.len()).unwrap_or(0);
+    // Preallocated files are full length from the start
+    size.min(completed)
+}
+
+/// Size the CDN declares for the stream. aria2's `totalLength` can't stand in for it,
+/// the connection that closed early is where aria2 learned it from.
+async fn declared_size(url: &str) -> Option<u64> {
+    preflight::probe("download", url).await.ok()?.content_length
+}
+
+/// What a download reported complete with `written` of `content` bytes on disk calls for,
+/// after `retries` resumes.
+#[derive(Debug, PartialEq, Eq)]
+enum Finished {
+    Whole,
+    Resume,
+    Truncated,
+}
+
+fn finished(content: u64, written: u64, retries: usize) -> Finished {
+    // No total is known for live streams, nothing to compare against
+    if content == 0 || written >= content {
+        Finished::Whole
+    } else if retries < MAX_TRUNCATED_RETRIES {
+        Finished::Resume
+    } else {
+        Finished::Truncated
+    }
+}
+
+pub async fn download(
+    gid: Arc<String>,
+    temp_root: &Path,
+    tx: &Progress,
+    urls: Vec<String>,
+) -> TauriResult<PathBuf> {
     let dir = temp_root.join(&*gid);
     fs::create_dir_all(&dir)
         .await
@@ -317,6 +434,13 @@ pub async fn download(gid: Arc<String>, tx: &Progress, urls: Vec<String>) -> Tau
         .next_back()
         .ok_or(anyhow!("Failed to get file name: {url:?}"))?;
     let output = dir.join(name);
+    let part = part_path(&output);
+    let part_name = format!("{name}.part");
+    // Each uri can get a connection of its own, aria2 opens one per server by default
+    let split = urls.len().clamp(1, SPLIT);
+    // Probed before taking the connections, the probe needs one of its own
+    let declared = declared_size(&urls[0]).await;
+    let _conn = CONNECTIONS.acquire(split).await?;
     let result = ARIA2_RPC
         .request::<Aria2TellStatus>("tellStatus", vec![json!(gid)])
         .await;
@@ -325,8 +449,21 @@ pub async fn download(gid: Arc<String>, tx: &Progress, urls: Vec<String>) -> Tau
         Ok(v) => match v.status.as_str() {
             "complete" => {
                 let total = v.total_length.parse::<u64>()?;
-                tx.send(total, total).await?;
-                return Ok(output);
+                let completed = v.completed_length.parse::<u64>()?;
+                if !fs::try_exists(&part).await? {
+                    tx.send(total, total).await?;
+                    return Ok(output);
+                }
+                let written = written(&part, completed).await;
+                if finished(declared.unwrap_or(total), written, 0) == Finished::Whole {
+                    tx.send(total, total).await?;
+                    fs::rename(&part, &output).await?;
+                    return Ok(output);
+                }
+                let _ = ARIA2_RPC
+                    .request::<Value>("removeDownloadResult", vec![json!(gid)])
+                    .await;
+                add(&gid, &dir, &part_name, &urls, split, true).await?;
             }
             "paused" => {
                 ARIA2_RPC
@@ -335,39 +472,114 @@ pub async fn download(gid: Arc<String>, tx: &Progress, urls: Vec<String>) -> Tau
             }
             _ => (),
         },
-        Err(_) => {
-            ARIA2_RPC
-                .request::<Value>(
-                    "addUri",
-                    vec![json!(urls), json!({"dir": dir, "out": name, "gid": gid})],
-                )
-                .await?;
-        }
+        Err(_) => add(&gid, &dir, &part_name, &urls, split, false).await?,
     };
+    let token = tx.token().await;
+    let mut retries = 0;
     loop {
+        if token.is_cancelled() {
+            let _ = cancel(gid.clone()).await;
+            return Err(anyhow!("Download {gid} cancelled").into());
+        }
         let data = ARIA2_RPC
             .request::<Aria2TellStatus>("tellStatus", vec![json!(gid)])
             .await?;
         if let Some(code) = data.error_code {
             let code = code.parse::<isize>()?;
             if code != 0 {
-                return Err(TauriError::new(
-                    data.error_message.unwrap_or(String::new()),
-                    Some(code),
-                ));
+                let err = TauriError::new(data.error_message.unwrap_or(String::new()), Some(code));
+                return Err(match NETWORK_ERRORS.contains(&code) {
+                    true => err.with_kind(ErrorKind::Network),
+                    false => err,
+                });
             }
         }
         let content = data.total_length.parse::<u64>()?;
         let chunk = data.completed_length.parse::<u64>()?;
         if chunk == 0 {
+            tokio::select! {
+                _ = sleep(Duration::from_millis(500)) => (),
+                _ = token.cancelled() => (),
+            }
             continue;
         }
         tx.send(content, chunk).await?;
+        let speed = data.download_speed.parse::<u64>().unwrap_or(0);
+        tx.transfer(speed, content.saturating_sub(chunk)).await;
         if data.status.as_str() == "complete" {
+            let written = written(&part, chunk).await;
+            let content = declared.unwrap_or(content);
+            match finished(content, written, retries) {
+                Finished::Whole => (),
+                Finished::Truncated => {
+                    return Err(TauriError::new(
+                        format!("Download {gid} ended at {written} of {content} bytes"),
+                        None::<isize>,
+                    )
+                    .with_kind(ErrorKind::Network));
+                }
+                Finished::Resume => {
+                    retries += 1;
+                    log::warn!(
+                        "Download {gid} ended at {written} of {content} bytes, resuming ({retries}/{MAX_TRUNCATED_RETRIES})"
+                    );
+                    let _ = ARIA2_RPC
+                        .request::<Value>("removeDownloadResult", vec![json!(gid)])
+                        .await;
+                    add(&gid, &dir, &part_name, &urls, split, true).await?;
+                    continue;
+                }
+            }
             tx.send(1, 1).await?;
+            tx.transfer(0, 0).await;
             break;
         }
-        sleep(Duration::from_millis(500)).await;
+        tokio::select! {
+            _ = sleep(Duration::from_millis(500)) => (),
+            _ = token.cancelled() => (),
+        }
     }
+    fs::rename(&part, &output).await?;
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn short_download_is_resumed() {
+        let part = std::env::temp_dir().join(format!("bilitools-{}.part", random_string(8)));
+        fs::write(&part, vec![0u8; 600]).await.unwrap();
+        // aria2 counted the whole file while only 600 bytes made it to disk
+        let written = written(&part, 1000).await;
+        fs::remove_file(&part).await.unwrap();
+        assert_eq!(written, 600);
+        assert_eq!(finished(1000, written, 0), Finished::Resume);
+        assert_eq!(
+            finished(1000, written, MAX_TRUNCATED_RETRIES - 1),
+            Finished::Resume
+        );
+        assert_eq!(
+            finished(1000, written, MAX_TRUNCATED_RETRIES),
+            Finished::Truncated
+        );
+    }
+
+    #[tokio::test]
+    async fn preallocated_download_counts_completed_bytes() {
+        let part = std::env::temp_dir().join(format!("bilitools-{}.part", random_string(8)));
+        fs::write(&part, vec![0u8; 1000]).await.unwrap();
+        let written = written(&part, 400).await;
+        fs::remove_file(&part).await.unwrap();
+        assert_eq!(written, 400);
+        assert_eq!(finished(1000, written, 0), Finished::Resume);
+    }
+
+    #[test]
+    fn whole_or_unknown_length_is_done() {
+        assert_eq!(finished(1000, 1000, 0), Finished::Whole);
+        assert_eq!(finished(0, 0, 0), Finished::Whole);
+        assert_eq!(finished(0, 1000, MAX_TRUNCATED_RETRIES), Finished::Whole);
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::anyhow;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+use tauri::http::StatusCode;
+use tokio::{fs, sync::RwLock};
+
+use crate::{
+    services::{bili, network::CONNECTIONS},
+    shared::init_client,
+    TauriError, TauriResult,
+};
+
+const SONG_URL: &str = "https://www.bilibili.com/audio/music-service-c/web/song/info";
+const MENU_URL: &str = "https://www.bilibili.com/audio/music-service-c/web/menu/info";
+const MENU_SONGS_URL: &str = "https://www.bilibili.com/audio/music-service-c/web/song/of-menu";
+
+const MENU_PAGE_SIZE: usize = 100;
+// Menus are capped well below this, it only guards against a page count that never ends
+const MAX_MENU_PAGES: usize = 50;
+
+const SONG_TTL: Duration = Duration::from_secs(300);
+
+static SONG_CACHE: LazyLock<RwLock<HashMap<u64, (Instant, Arc<AudioTrack>)>>> =
+    LazyLock::new(Default::default);
+
+/// An id of the audio zone (音频区): `au` for a song, `am` for a menu of songs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioId {
+    Song(u64),
+    Menu(u64),
+}
+
+/// Accepts `au`/`am` ids and the `/audio/au…` and `/audio/am…` page urls.
+pub fn parse(input: &str) -> Option<AudioId> {
+    static ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)(?:^|/)(au|am)(\d+)").unwrap());
+    let caps = ID.captures(input.trim())?;
+    let id = caps[2].parse().ok()?;
+    match caps[1].to_ascii_lowercase().as_str() {
+        "au" => Some(AudioId::Song(id)),
+        _ => Some(AudioId::Menu(id)),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioTrack {
+    #[serde(alias = "id")]
+    pub sid: u64,
+    pub title: String,
+    /// Performer as credited, which isn't always the uploader.
+    #[serde(default)]
+    pub author: String,
+    #[serde(alias = "uname")]
+    pub uploader: String,
+    #[serde(alias = "uid")]
+    pub uploader_mid: u64,
+    pub cover: String,
+    #[serde(default)]
+    pub intro: String,
+    /// Url of the `.lrc` lyrics, empty when the song has none.
+    #[serde(default)]
+    pub lyric: String,
+    pub duration: u64,
+    #[serde(alias = "passtime")]
+    pub pubtime: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioResolution {
+    /// `au…` or `am…`.
+    pub id: String,
+    pub title: String,
+    pub cover: String,
+    /// One for a song, in menu order for a menu.
+    pub tracks: Vec<Arc<AudioTrack>>,
+}
+
+#[derive(Deserialize)]
+struct MenuInfo {
+    title: String,
+    cover: String,
+}
+
+#[derive(Deserialize)]
+struct MenuPage {
+    #[serde(rename = "pageCount")]
+    page_count: usize,
+    data: Vec<AudioTrack>,
+}
+
+pub async fn song(sid: u64) -> TauriResult<Arc<AudioTrack>> {
+    if let Some((ts, track)) = SONG_CACHE.read().await.get(&sid) {
+        if ts.elapsed() < SONG_TTL {
+            return Ok(track.clone());
+        }
+    }
+    let track: Arc<AudioTrack> = Arc::new(bili::get(SONG_URL, &[("sid", sid.to_string())]).await?);
+    let mut cache = SONG_CACHE.write().await;
+    cache.retain(|_, (ts, _)| ts.elapsed() < SONG_TTL);
+    cache.insert(sid, (Instant::now(), track.clone()));
+    Ok(track)
+}
+
+/// Every song of menu `amid`, page by page.
+async fn menu(amid: u64) -> TauriResult<AudioResolution> {
+    let info: MenuInfo = bili::get(MENU_URL, &[("sid", amid.to_string())]).await?;
+    let mut tracks = vec![];
+    for pn in 1..=MAX_MENU_PAGES {
+        let page: MenuPage = bili::get(
+            MENU_SONGS_URL,
+            &[
+                ("sid", amid.to_string()),
+                ("pn", pn.to_string()),
+                ("ps", MENU_PAGE_SIZE.to_string()),
+            ],
+        )
+        .await?;
+        let last = page.data.is_empty() || pn >= page.page_count;
+        tracks.extend(page.data.into_iter().map(Arc::new));
+        if last {
+            break;
+        }
+    }
+    Ok(AudioResolution {
+        id: format!("am{amid}"),
+        title: info.title,
+        cover: info.cover,
+        tracks,
+    })
+}
+
+/// Writes the lyrics of song `sid` next to `output` as `.lrc`. Returns whether it had
+/// any.
+pub async fn save_lyrics(sid: u64, output: &Path) -> TauriResult<bool> {
+    let track = song(sid).await?;
+    if track.lyric.is_empty() {
+        return Ok(false);
+    }
+    let client = init_client().await?;
+    let _conn = CONNECTIONS.acquire(1).await?;
+    let response = client.get(&track.lyric).send().await?;
+    if response.status() != StatusCode::OK {
+        return Err(TauriError::new(
+            format!("Error while fetching lyrics of au{sid}"),
+            Some(response.status()),
+        ));
+    }
+    fs::write(output.with_extension("lrc"), response.bytes().await?).await?;
+    Ok(true)
+}
+
+/// Title, performer and lyrics of an audio zone song, or the songs of a menu, which
+/// the frontend queues like the parts of a collection.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn resolve_audio(input: String) -> TauriResult<AudioResolution> {
+    match parse(&input).ok_or(anyhow!("Unrecognized audio id: {input}"))? {
+        AudioId::Song(sid) => {
+            let track = song(sid).await?;
+            Ok(AudioResolution {
+                id: format!("au{sid}"),
+                title: track.title.clone(),
+                cover: track.cover.clone(),
+                tracks: vec![track],
+            })
+        }
+        AudioId::Menu(amid) => menu(amid).await,
+    }
+}
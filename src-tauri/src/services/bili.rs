@@ -0,0 +1,739 @@
+use anyhow::{anyhow, Context};
+use md5::{Digest, Md5};
+use regex::Regex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+use tauri::http::StatusCode;
+use tauri_plugin_http::reqwest::Client;
+use tauri_specta::Event;
+use tokio::{
+    sync::{broadcast, Mutex, RwLock},
+    time::timeout,
+};
+
+use crate::{
+    config,
+    errors::ErrorKind,
+    services::network::CONNECTIONS,
+    shared::{
+        api_url, get_app_handle, get_ts, init_client, init_client_no_proxy, process_tauri_err,
+        HEADERS,
+    },
+    storage::cookies,
+    TauriError, TauriResult,
+};
+
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Pending risk-control verification, shared by every request that ran into it.
+static VERIFICATION: LazyLock<Mutex<Option<broadcast::Sender<Option<String>>>>> =
+    LazyLock::new(Default::default);
+
+const INFO_TTL: Duration = Duration::from_secs(300);
+
+static INFO_CACHE: LazyLock<RwLock<HashMap<String, (Instant, Arc<VideoInfo>)>>> =
+    LazyLock::new(Default::default);
+
+// Counts keep moving, so they are kept for less time than the rest of the info
+const STAT_TTL: Duration = Duration::from_secs(60);
+
+static STAT_CACHE: LazyLock<RwLock<HashMap<String, (Instant, Arc<VideoStats>)>>> =
+    LazyLock::new(Default::default);
+
+static TIER_CACHE: LazyLock<RwLock<Option<(Instant, MemberTier)>>> =
+    LazyLock::new(Default::default);
+
+// The WBI keys rotate daily
+const WBI_TTL: Duration = Duration::from_secs(3600);
+
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+
+static WBI_KEY: LazyLock<RwLock<Option<(Instant, String)>>> = LazyLock::new(Default::default);
+
+#[derive(Deserialize)]
+struct BiliResp {
+    code: isize,
+    // The audio zone's endpoints call it `msg`
+    #[serde(default, alias = "msg")]
+    message: String,
+    data: Option<Value>,
+    // PGC endpoints use `result` instead of `data`
+    result: Option<Value>,
+}
+
+/// Asks the frontend to run Bilibili's gaia verification for `voucher`.
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskControlEvent {
+    pub url: String,
+    pub voucher: Option<String>,
+}
+
+async fn fetch<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    query: &[(&str, String)],
+) -> TauriResult<T> {
+    let _conn = CONNECTIONS.acquire(1).await?;
+    let response = client.get(api_url(url)).query(query).send().await?;
+    if response.status() != StatusCode::OK {
+        return Err(TauriError::new(
+            format!("Error while fetching {url}"),
+            Some(response.status()),
+        ));
+    }
+    let body: BiliResp = response
+        .json()
+        .await
+        .with_context(|| format!("Failed to decode response of {url}"))?;
+    if body.code != 0 {
+        let err = TauriError::new(body.message, Some(body.code)).classify();
+        return Err(match err.kind {
+            Some(ErrorKind::RegionLocked | ErrorKind::NotEntitled) => err.with_param("url", url),
+            Some(ErrorKind::RiskControl) => {
+                let voucher = body
+                    .data
+                    .as_ref()
+                    .and_then(|v| v.get("v_voucher"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                err.with_param("url", url).with_param("voucher", voucher)
+            }
+            _ => err,
+        });
+    }
+    let data = body
+        .data
+        .or(body.result)
+        .ok_or(anyhow!("No data found in response of {url}"))?;
+    Ok(serde_json::from_value(data).with_context(|| format!("Failed to decode data of {url}"))?)
+}
+
+/// Waits until `submit_verification` is called, emitting the event only for the first
+/// request so the user sees a single verification prompt.
+async fn wait_verification(e: &TauriError) -> TauriResult<()> {
+    let (own, mut rx) = {
+        let mut guard = VERIFICATION.lock().await;
+        match &*guard {
+            Some(tx) => (tx.clone(), tx.subscribe()),
+            None => {
+                let (tx, rx) = broadcast::channel(1);
+                *guard = Some(tx.clone());
+                RiskControlEvent {
+                    url: e.params.get("url").cloned().unwrap_or_default(),
+                    voucher: e.params.get("voucher").filter(|v| !v.is_empty()).cloned(),
+                }
+                .emit(get_app_handle())?;
+                (tx, rx)
+            }
+        }
+    };
+    log::info!("Request paused for risk control verification");
+    match timeout(VERIFY_TIMEOUT, rx.recv()).await {
+        Ok(Ok(Some(_))) => Ok(()),
+        Ok(_) => Err(anyhow!("Risk control verification was cancelled").into()),
+        Err(_) => {
+            // A newer verification may have taken over since, leave that one pending
+            let mut guard = VERIFICATION.lock().await;
+            if guard.as_ref().is_some_and(|tx| tx.same_channel(&own)) {
+                guard.take();
+            }
+            Err(anyhow!("Risk control verification timed out").into())
+        }
+    }
+}
+
+async fn get_inner<T: DeserializeOwned>(url: &str, query: &[(&str, String)]) -> TauriResult<T> {
+    let cfg = config::read();
+    let direct = cfg.region_direct && !cfg.proxy.address.is_empty();
+    let retry = direct && cfg.region_retry;
+    let client = if direct {
+        init_client_no_proxy().await?
+    } else {
+        init_client().await?
+    };
+    match fetch(&client, url, query).await {
+        Err(e) if retry && e.kind == Some(ErrorKind::RegionLocked) => {
+            log::info!("{url} is region locked, retrying through proxy");
+            fetch(&init_client().await?, url, query).await
+        }
+        result => result,
+    }
+}
+
+pub async fn get<T: DeserializeOwned>(url: &str, query: &[(&str, String)]) -> TauriResult<T> {
+    let result = match get_inner(url, query).await {
+        Err(e) if e.kind == Some(ErrorKind::RiskControl) => {
+            wait_verification(&e).await?;
+            get_inner(url, query).await
+        }
+        result => result,
+    };
+    result.map_err(|e| match e.kind {
+        Some(ErrorKind::RegionLocked) => process_tauri_err(e, "RegionLocked"),
+        Some(ErrorKind::NotEntitled) => process_tauri_err(e, "NotEntitled"),
+        _ => e,
+    })
+}
+
+// WBI signing
+
+async fn mixin_key() -> TauriResult<String> {
+    if let Some((ts, key)) = &*WBI_KEY.read().await {
+        if ts.elapsed() < WBI_TTL {
+            return Ok(key.clone());
+        }
+    }
+    // Not through `fetch`, nav answers -101 to guests but still carries the keys
+    let url = "https://api.bilibili.com/x/web-interface/nav";
+    let conn = CONNECTIONS.acquire(1).await?;
+    let body: Value = init_client()
+        .await?
+        .get(api_url(url))
+        .send()
+        .await?
+        .json()
+        .await
+        .with_context(|| format!("Failed to decode response of {url}"))?;
+    drop(conn);
+    let stem = |key: &str| {
+        body.pointer(&format!("/data/wbi_img/{key}"))
+            .and_then(Value::as_str)
+            .and_then(|v| v.rsplit('/').next())
+            .and_then(|v| v.split('.').next())
+            .map(String::from)
+            .ok_or(anyhow!("No {key} found in nav response"))
+    };
+    let raw: Vec<char> = (stem("img_url")? + &stem("sub_url")?).chars().collect();
+    let key: String = MIXIN_KEY_ENC_TAB
+        .iter()
+        .filter_map(|i| raw.get(*i))
+        .take(32)
+        .collect();
+    *WBI_KEY.write().await = Some((Instant::now(), key.clone()));
+    Ok(key)
+}
+
+// Same as JavaScript's encodeURIComponent, which the signature is computed over
+fn encode_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Returns `query` with `wts` and `w_rid` added, as WBI endpoints (`/wbi/` in the path)
+/// expect.
+pub async fn sign_wbi(query: &[(&str, String)]) -> TauriResult<Vec<(String, String)>> {
+    let key = mixin_key().await?;
+    let mut params: Vec<(String, String)> = query
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.replace(['!', '\'', '(', ')', '*'], "")))
+        .collect();
+    params.push(("wts".into(), get_ts(true).to_string()));
+    params.sort_by(|a, b| a.0.cmp(&b.0));
+    let joined = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", encode_component(k), encode_component(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let w_rid = format!("{:x}", Md5::digest(format!("{joined}{key}")));
+    params.push(("w_rid".into(), w_rid));
+    Ok(params)
+}
+
+pub async fn get_wbi<T: DeserializeOwned>(url: &str, query: &[(&str, String)]) -> TauriResult<T> {
+    let signed = sign_wbi(query).await?;
+    let query: Vec<(&str, String)> = signed
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.clone()))
+        .collect();
+    get(url, &query).await
+}
+
+// Normalization
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaId {
+    pub aid: Option<u64>,
+    pub bvid: Option<String>,
+    pub page: Option<usize>,
+}
+
+impl MediaId {
+    pub fn key(&self) -> String {
+        match (&self.bvid, self.aid) {
+            (Some(bvid), _) => bvid.clone(),
+            (None, Some(aid)) => format!("av{aid}"),
+            _ => String::new(),
+        }
+    }
+    fn query(&self) -> Vec<(&'static str, String)> {
+        match (&self.bvid, self.aid) {
+            (Some(bvid), _) => vec![("bvid", bvid.clone())],
+            (None, Some(aid)) => vec![("aid", aid.to_string())],
+            _ => vec![],
+        }
+    }
+}
+
+/// Accepts BV/av ids, full video URLs and b23.tv short links, with an optional `?p=` part index.
+pub async fn normalize(input: &str) -> TauriResult<MediaId> {
+    static BVID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"BV[0-9A-Za-z]{10}").unwrap());
+    static AID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)(?:^|av)(\d+)").unwrap());
+    static PAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[?&]p=(\d+)").unwrap());
+
+    let input = input.trim();
+    let resolved = if input.contains("b23.tv") {
+        let url = if input.starts_with("http") {
+            input.to_string()
+        } else {
+            format!("https://{input}")
+        };
+        let _conn = CONNECTIONS.acquire(1).await?;
+        let response = init_client().await?.get(&url).send().await?;
+        response.url().to_string()
+    } else {
+        input.to_string()
+    };
+
+    let page = PAGE
+        .captures(&resolved)
+        .and_then(|c| c[1].parse::<usize>().ok());
+    if let Some(m) = BVID.find(&resolved) {
+        return Ok(MediaId {
+            bvid: Some(m.as_str().to_string()),
+            page,
+            ..Default::default()
+        });
+    }
+    let path = resolved.split('?').next().unwrap_or_default();
+    if let Some(aid) = AID.captures(path).and_then(|c| c[1].parse::<u64>().ok()) {
+        return Ok(MediaId {
+            aid: Some(aid),
+            page,
+            ..Default::default()
+        });
+    }
+    Err(anyhow!("Unrecognized video id: {input}").into())
+}
+
+// Video info
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct VideoOwner {
+    pub mid: u64,
+    pub name: String,
+    pub face: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct VideoStat {
+    pub view: u64,
+    pub danmaku: u64,
+    pub reply: u64,
+    pub favorite: u64,
+    pub coin: u64,
+    pub share: u64,
+    pub like: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct VideoPage {
+    pub cid: u64,
+    pub page: usize,
+    pub part: String,
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ViewRights {
+    #[serde(default)]
+    pay: u8,
+    #[serde(default)]
+    ugc_pay: u8,
+    #[serde(default)]
+    arc_pay: u8,
+    #[serde(default)]
+    is_stein_gate: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ViewData {
+    aid: u64,
+    bvid: String,
+    cid: u64,
+    title: String,
+    desc: String,
+    duration: u64,
+    pic: String,
+    pubdate: i64,
+    owner: VideoOwner,
+    stat: VideoStat,
+    #[serde(default)]
+    pages: Vec<VideoPage>,
+    #[serde(default)]
+    rights: ViewRights,
+    #[serde(default)]
+    is_upower_exclusive: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ViewTag {
+    tag_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoInfo {
+    pub aid: u64,
+    pub bvid: String,
+    pub cid: u64,
+    pub page: Option<usize>,
+    pub title: String,
+    pub desc: String,
+    pub duration: u64,
+    pub cover: String,
+    pub pubdate: i64,
+    pub tags: Vec<String>,
+    pub owner: VideoOwner,
+    pub stat: VideoStat,
+    pub pages: Vec<VideoPage>,
+    pub paid: bool,
+    pub charge_only: bool,
+    /// Interactive video with a branching graph, see `get_interactive_graph`.
+    pub interactive: bool,
+}
+
+/// Charge-exclusive videos only play for the uploader's chargers. Requests already carry
+/// the login cookies, so a charger goes on as usual; anyone else gets a `NotEntitled`
+/// error naming the uploader instead of a failing play url later on.
+async fn check_charge(view: &ViewData) -> TauriResult<()> {
+    let entitled = match get_member_tier().await? {
+        MemberTier::Guest => false,
+        // The player offers the charge prompt only to accounts that haven't charged
+        _ => get_player_info(&view.aid.to_string(), view.cid)
+            .await?
+            .elec_high_level
+            .is_none(),
+    };
+    if entitled {
+        return Ok(());
+    }
+    Err(TauriError::new(
+        format!(
+            "av{} is exclusive to chargers of {}",
+            view.aid, view.owner.name
+        ),
+        None::<isize>,
+    )
+    .with_kind(ErrorKind::NotEntitled)
+    .with_param("mid", view.owner.mid)
+    .with_param("uploader", &view.owner.name)
+    .with_param("aid", view.aid))
+}
+
+async fn fetch_video_info(id: &MediaId) -> TauriResult<VideoInfo> {
+    let view: ViewData = get("https://api.bilibili.com/x/web-interface/view", &id.query()).await?;
+    if view.is_upower_exclusive {
+        check_charge(&view).await?;
+    }
+    let tags = get::<Vec<ViewTag>>(
+        "https://api.bilibili.com/x/tag/archive/tags",
+        &[("aid", view.aid.to_string())],
+    )
+    .await
+    .map(|v| v.into_iter().map(|t| t.tag_name).collect())
+    .unwrap_or_else(|e| {
+        log::warn!("Failed to fetch tags of av{}: {e}", view.aid);
+        vec![]
+    });
+    let cid = id
+        .page
+        .and_then(|p| view.pages.iter().find(|v| v.page == p))
+        .map(|v| v.cid)
+        .unwrap_or(view.cid);
+    Ok(VideoInfo {
+        aid: view.aid,
+        bvid: view.bvid,
+        cid,
+        page: id.page,
+        title: view.title,
+        desc: view.desc,
+        duration: view.duration,
+        cover: view.pic,
+        pubdate: view.pubdate,
+        tags,
+        owner: view.owner,
+        stat: view.stat,
+        pages: view.pages,
+        paid: view.rights.pay != 0 || view.rights.ugc_pay != 0 || view.rights.arc_pay != 0,
+        charge_only: view.is_upower_exclusive,
+        interactive: view.rights.is_stein_gate != 0,
+    })
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_video_info(id: String) -> TauriResult<Arc<VideoInfo>> {
+    let id = normalize(&id).await?;
+    video_info(&id).await.map_err(|e| match e.kind {
+        Some(ErrorKind::NotEntitled) => process_tauri_err(e, "NotEntitled"),
+        _ => e,
+    })
+}
+
+/// `get_video_info` for an id already normalized, leaving a charge-exclusive video
+/// unreported to the frontend.
+pub async fn video_info(id: &MediaId) -> TauriResult<Arc<VideoInfo>> {
+    let key = format!("{}:{}", id.key(), id.page.unwrap_or(0));
+    if let Some((ts, info)) = INFO_CACHE.read().await.get(&key) {
+        if ts.elapsed() < INFO_TTL {
+            return Ok(info.clone());
+        }
+    }
+    let info = Arc::new(fetch_video_info(id).await?);
+    let mut cache = INFO_CACHE.write().await;
+    cache.retain(|_, (ts, _)| ts.elapsed() < INFO_TTL);
+    cache.insert(key, (Instant::now(), info.clone()));
+    Ok(info)
+}
+
+/// Counts and length of a video, without the tags and parts `get_video_info` gathers.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStats {
+    pub aid: u64,
+    pub bvid: String,
+    pub duration: u64,
+    #[serde(flatten)]
+    pub stat: VideoStat,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StatView {
+    aid: u64,
+    bvid: String,
+    duration: u64,
+    stat: VideoStat,
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_video_stats(id: String) -> TauriResult<Arc<VideoStats>> {
+    let id = normalize(&id).await?;
+    let key = id.key();
+    if let Some((ts, stats)) = STAT_CACHE.read().await.get(&key) {
+        if ts.elapsed() < STAT_TTL {
+            return Ok(stats.clone());
+        }
+    }
+    let view: StatView = get("https://api.bilibili.com/x/web-interface/view", &id.query()).await?;
+    let stats = Arc::new(VideoStats {
+        aid: view.aid,
+        bvid: view.bvid,
+        duration: view.duration,
+        stat: view.stat,
+    });
+    let mut cache = STAT_CACHE.write().await;
+    cache.retain(|_, (ts, _)| ts.elapsed() < STAT_TTL);
+    cache.insert(key, (Instant::now(), stats.clone()));
+    Ok(stats)
+}
+
+// Chapters
+
+/// A chapter the uploader marked on the progress bar, in seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Chapter {
+    pub from: u64,
+    pub to: u64,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PlayerInfo {
+    #[serde(default)]
+    view_points: Option<Vec<Chapter>>,
+    /// Prompt to charge, set on charge-exclusive videos the account can't play.
+    #[serde(default)]
+    elec_high_level: Option<Value>,
+}
+
+async fn get_player_info(aid: &str, cid: impl ToString) -> TauriResult<PlayerInfo> {
+    get_wbi(
+        "https://api.bilibili.com/x/player/wbi/v2",
+        &[("aid", aid.into()), ("cid", cid.to_string())],
+    )
+    .await
+}
+
+/// Chapters of a part, empty when the uploader set none.
+pub async fn get_chapters(aid: &str, cid: usize) -> TauriResult<Vec<Chapter>> {
+    let info = get_player_info(aid, cid).await?;
+    Ok(info.view_points.unwrap_or_default())
+}
+
+// Qualities
+
+/// Stream qualities a part is offered in to the account asking.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+pub struct Qualities {
+    /// Video quality ids (`qn`), best first.
+    pub res: Vec<usize>,
+    /// Audio stream ids, see `AudioQuality`.
+    pub abr: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DashAudio {
+    id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DashDolby {
+    #[serde(default)]
+    audio: Option<Vec<DashAudio>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DashFlac {
+    #[serde(default)]
+    audio: Option<DashAudio>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Dash {
+    #[serde(default)]
+    audio: Option<Vec<DashAudio>>,
+    #[serde(default)]
+    dolby: Option<DashDolby>,
+    #[serde(default)]
+    flac: Option<DashFlac>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PlayUrl {
+    #[serde(default)]
+    accept_quality: Vec<usize>,
+    #[serde(default)]
+    dash: Option<Dash>,
+}
+
+/// Asks for the DASH play url of a part only to read which qualities it lists.
+pub async fn get_qualities(bvid: &str, cid: usize) -> TauriResult<Qualities> {
+    let play: PlayUrl = get_wbi(
+        "https://api.bilibili.com/x/player/wbi/playurl",
+        &[
+            ("bvid", bvid.into()),
+            ("cid", cid.to_string()),
+            // DASH with every optional stream (HDR, 4K, 8K, Dolby, Hi-Res)
+            ("fnval", "4048".into()),
+            ("fourk", "1".into()),
+        ],
+    )
+    .await?;
+    let mut abr = vec![];
+    if let Some(dash) = play.dash {
+        abr.extend(dash.audio.unwrap_or_default().into_iter().map(|v| v.id));
+        abr.extend(
+            dash.dolby
+                .and_then(|v| v.audio)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|v| v.id),
+        );
+        abr.extend(dash.flac.and_then(|v| v.audio).map(|v| v.id));
+    }
+    Ok(Qualities {
+        res: play.accept_quality,
+        abr,
+    })
+}
+
+// Membership
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberTier {
+    Guest,
+    Member,
+    Vip,
+}
+
+impl MemberTier {
+    /// Highest video quality id (`qn`) the tier is allowed to play.
+    pub fn max_res(&self) -> usize {
+        match self {
+            MemberTier::Guest => 32,
+            MemberTier::Member => 80,
+            MemberTier::Vip => usize::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NavData {
+    is_login: bool,
+    #[serde(default)]
+    vip_status: u8,
+}
+
+pub async fn get_member_tier() -> TauriResult<MemberTier> {
+    if let Some((ts, tier)) = *TIER_CACHE.read().await {
+        if ts.elapsed() < INFO_TTL {
+            return Ok(tier);
+        }
+    }
+    let tier = match get::<NavData>("https://api.bilibili.com/x/web-interface/nav", &[]).await {
+        Ok(nav) if nav.is_login && nav.vip_status == 1 => MemberTier::Vip,
+        Ok(nav) if nav.is_login => MemberTier::Member,
+        Ok(_) => MemberTier::Guest,
+        Err(e) if e.kind == Some(ErrorKind::AuthExpired) => MemberTier::Guest,
+        Err(e) => return Err(e),
+    };
+    *TIER_CACHE.write().await = Some((Instant::now(), tier));
+    Ok(tier)
+}
+
+pub async fn clear_cache() {
+    INFO_CACHE.write().await.clear();
+    STAT_CACHE.write().await.clear();
+    *TIER_CACHE.write().await = None;
+    if let Some(tx) = VERIFICATION.lock().await.take() {
+        let _ = tx.send(None);
+    }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn submit_verification(token: Option<String>) -> TauriResult<()> {
+    if let Some(token) = &token {
+        cookies::insert(format!(
+            "x-bili-gaia-vtoken={token}; Domain=.bilibili.com; Path=/"
+        ))
+        .await?;
+        HEADERS.refresh().await?;
+    }
+    if let Some(tx) = VERIFICATION.lock().await.take() {
+        let _ = tx.send(token);
+    }
+    Ok(())
+}
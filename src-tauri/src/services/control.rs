@@ -0,0 +1,388 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use specta::Type;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+use tauri::async_runtime::JoinHandle;
+use tauri_specta::Event;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    time::timeout,
+};
+
+use crate::{
+    config,
+    queue::{
+        runtime::{ctrl_event, queue_stats, CtrlEvent, TASK_MANAGER},
+        types::{TaskDetail, TaskState},
+    },
+    services::bili::{self, MediaId},
+    shared::{get_app_handle, process_err, random_string},
+    TauriResult,
+};
+
+static LISTENER: LazyLock<Mutex<Option<JoinHandle<()>>>> = LazyLock::new(|| Mutex::new(None));
+
+const MAX_REQUEST: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// Out of file descriptors and the like, which accepting again right away won't fix
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Asks the frontend to resolve and enqueue `url`, as if it had been pasted into search.
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+pub struct ControlSubmit {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Queued,
+    /// Already in the queue, or earlier in the same list.
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct ImportLine {
+    /// 1-based, as an editor shows it.
+    pub line: usize,
+    pub input: String,
+    pub status: ImportStatus,
+    #[specta(optional)]
+    pub error: Option<String>,
+}
+
+/// Keys of the videos already queued, in the form `MediaId::key` gives.
+pub async fn queued_keys() -> HashSet<String> {
+    let mut keys = HashSet::new();
+    for lock in TASK_MANAGER.tasks.read().await.values() {
+        let task = lock.read().await;
+        if let Some(bvid) = &task.item.bvid {
+            keys.insert(bvid.clone());
+        }
+        if let Some(aid) = &task.item.aid {
+            keys.insert(format!("av{aid}"));
+        }
+    }
+    keys
+}
+
+/// Normalizes `inputs` side by side through the resolution pool, so a long list
+/// of short links resolves at most `resolve_conc` at a time. Results keep the order of
+/// `inputs`.
+pub async fn normalize_all(inputs: Vec<String>) -> TauriResult<Vec<TauriResult<MediaId>>> {
+    let handles: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            tauri::async_runtime::spawn(async move {
+                TASK_MANAGER.resolve.run(bili::normalize(&input)).await
+            })
+        })
+        .collect();
+    let mut results = vec![];
+    for handle in handles {
+        results.push(handle.await?);
+    }
+    Ok(results)
+}
+
+/// Reads a list of links, one per line with `#` starting a comment, as exported by
+/// aria2 or IDM, and submits the recognizable ones through [`ControlSubmit`]. Every line
+/// is checked before anything is submitted, so a file that can't be read queues nothing.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn import_url_list(path: PathBuf) -> TauriResult<Vec<ImportLine>> {
+    let text = tokio::fs::read_to_string(&path).await?;
+    let queued = queued_keys().await;
+    let mut seen = HashSet::new();
+    let mut report = vec![];
+    let mut urls = vec![];
+    let mut lines = vec![];
+    for (index, raw) in text.lines().enumerate() {
+        let input = raw.split('#').next().unwrap_or_default().trim();
+        // aria2 input files indent the options of the uri above
+        if input.is_empty() || raw.starts_with([' ', '\t']) {
+            continue;
+        }
+        lines.push((index, input.to_string()));
+    }
+    let ids = normalize_all(lines.iter().map(|(_, v)| v.clone()).collect()).await?;
+    for ((index, input), id) in lines.into_iter().zip(ids) {
+        let (status, error) = match id {
+            Ok(id) if queued.contains(&id.key()) || !seen.insert((id.key(), id.page)) => {
+                (ImportStatus::Duplicate, None)
+            }
+            Ok(_) => {
+                urls.push(input.clone());
+                (ImportStatus::Queued, None)
+            }
+            Err(e) => (ImportStatus::Invalid, Some(e.message)),
+        };
+        report.push(ImportLine {
+            line: index + 1,
+            input,
+            status,
+            error,
+        });
+    }
+    for url in urls {
+        ControlSubmit { url }.emit(get_app_handle())?;
+    }
+    Ok(report)
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: RpcParams,
+}
+
+#[derive(Default, Deserialize)]
+struct RpcParams {
+    url: Option<String>,
+    /// Tasks to pause or resume, every running or paused one when left out.
+    ids: Option<Vec<Arc<String>>>,
+}
+
+/// Sends `event` to `ids`, or to every task in `state` without them, through the same
+/// `ctrl_event` the GUI uses.
+async fn control(
+    event: CtrlEvent,
+    state: TaskState,
+    ids: Option<Vec<Arc<String>>>,
+) -> TauriResult<Vec<Arc<String>>> {
+    let ids = match ids {
+        Some(v) => v,
+        None => {
+            let mut list = vec![];
+            for (id, lock) in TASK_MANAGER.tasks.read().await.iter() {
+                if lock.read().await.state == state {
+                    list.push(id.clone());
+                }
+            }
+            list
+        }
+    };
+    let mut groups: HashMap<Arc<String>, Vec<Arc<String>>> = HashMap::new();
+    for id in &ids {
+        if let Some(sch) = TASK_MANAGER.find_scheduler(id).await {
+            groups.entry(sch.sid.clone()).or_default().push(id.clone());
+        }
+    }
+    for (sid, list) in groups {
+        ctrl_event(event.clone(), sid, list).await?;
+    }
+    Ok(ids)
+}
+
+/// Resolves `url` and forwards it through [`ControlSubmit`] unless it's queued already.
+/// The frontend does the enqueueing, so `queued` is always false: callers poll `tasks`
+/// to see it land.
+async fn submit(url: String) -> TauriResult<Value> {
+    let id = TASK_MANAGER.resolve.run(bili::normalize(&url)).await?;
+    let status = if queued_keys().await.contains(&id.key()) {
+        "duplicate"
+    } else {
+        ControlSubmit { url }.emit(get_app_handle())?;
+        "forwarded"
+    };
+    Ok(json!({"id": id, "status": status, "queued": false}))
+}
+
+async fn dispatch(method: &str, params: RpcParams) -> TauriResult<Value> {
+    Ok(match method {
+        "submit" => submit(params.url.ok_or(anyhow!("submit needs a url"))?).await?,
+        "stats" => serde_json::to_value(queue_stats().await?)?,
+        "tasks" => {
+            let mut list = vec![];
+            for lock in TASK_MANAGER.tasks.read().await.values() {
+                list.push(TaskDetail::from(&*lock.read().await));
+            }
+            serde_json::to_value(list)?
+        }
+        "pause" => json!(control(CtrlEvent::Pause, TaskState::Active, params.ids).await?),
+        "resume" => json!(control(CtrlEvent::Resume, TaskState::Paused, params.ids).await?),
+        _ => return Err(anyhow!("Unknown method {method}").into()),
+    })
+}
+
+fn authorized(headers: &[httparse::Header], token: &str) -> bool {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|v| v.name.eq_ignore_ascii_case(name))
+            .and_then(|v| std::str::from_utf8(v.value).ok())
+    };
+    // A page on another site resolving its name to 127.0.0.1 still sends its own Host
+    let local = header("host")
+        .and_then(|v| v.rsplit_once(':').map_or(Some(v), |(host, _)| Some(host)))
+        .is_some_and(|v| v == "127.0.0.1" || v == "localhost");
+    let given = header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Compared in full so the time taken doesn't tell how much of it matched
+    let matches = given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0;
+    local && matches
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: Value) -> TauriResult<()> {
+    let body = serde_json::to_vec(&body)?;
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads one request, headers first and then as much body as `Content-Length` says.
+async fn read_request(stream: &mut TcpStream) -> TauriResult<Vec<u8>> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(anyhow!("Connection closed mid-request").into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST {
+            return Err(anyhow!("Request too large").into());
+        }
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut req = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(offset) = req.parse(&buf)? else {
+            continue;
+        };
+        let length = req
+            .headers
+            .iter()
+            .find(|v| v.name.eq_ignore_ascii_case("content-length"))
+            .and_then(|v| std::str::from_utf8(v.value).ok())
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        if offset + length > MAX_REQUEST {
+            return Err(anyhow!("Request too large").into());
+        }
+        if buf.len() >= offset + length {
+            return Ok(buf);
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, token: Arc<String>) -> TauriResult<()> {
+    let buf = match timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            return respond(&mut stream, "400 Bad Request", json!({"error": e.message})).await
+        }
+        Err(_) => return Ok(()),
+    };
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut req = httparse::Request::new(&mut headers);
+    let httparse::Status::Complete(offset) = req.parse(&buf)? else {
+        return Ok(());
+    };
+    if !authorized(req.headers, &token) {
+        return respond(
+            &mut stream,
+            "401 Unauthorized",
+            json!({"error": "Unauthorized"}),
+        )
+        .await;
+    }
+    if req.method != Some("POST") || req.path != Some("/rpc") {
+        return respond(
+            &mut stream,
+            "404 Not Found",
+            json!({"error": "POST /rpc only"}),
+        )
+        .await;
+    }
+    let rpc: RpcRequest = match serde_json::from_slice(&buf[offset..]) {
+        Ok(v) => v,
+        Err(e) => {
+            let error = json!({"code": -32700, "message": e.to_string()});
+            let body = json!({"jsonrpc": "2.0", "id": null, "error": error});
+            return respond(&mut stream, "200 OK", body).await;
+        }
+    };
+    let body = match dispatch(&rpc.method, rpc.params).await {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": rpc.id, "result": result}),
+        Err(e) => {
+            let error = json!({"code": -32000, "message": e.message});
+            json!({"jsonrpc": "2.0", "id": rpc.id, "error": error})
+        }
+    };
+    respond(&mut stream, "200 OK", body).await
+}
+
+/// Stops the listener if one is running and starts it again with the current
+/// `control_port` and `control_token`, for startup and for `config_write`.
+pub async fn restart() {
+    let mut listener = LISTENER.lock().await;
+    if let Some(handle) = listener.take() {
+        handle.abort();
+    }
+    *listener = Some(tauri::async_runtime::spawn(async {
+        let _ = serve().await.map_err(|e| process_err(e, "control"));
+    }));
+}
+
+/// Serves JSON-RPC 2.0 on `127.0.0.1:{control_port}/rpc` for scripts and browser
+/// extensions, sharing the queue and storage with the GUI. Every request needs
+/// `Authorization: Bearer {control_token}`.
+async fn serve() -> TauriResult<()> {
+    let cfg = config::read();
+    if cfg.control_port == 0 {
+        return Ok(());
+    }
+    let token = if cfg.control_token.is_empty() {
+        let token = random_string(32);
+        let mut settings = serde_json::Map::new();
+        settings.insert("control_token".into(), token.clone().into());
+        config::write(settings).await?;
+        token
+    } else {
+        cfg.control_token.clone()
+    };
+    let token = Arc::new(token);
+    let listener = TcpListener::bind(("127.0.0.1", cfg.control_port)).await?;
+    log::info!(
+        "Control endpoint listening on 127.0.0.1:{}",
+        cfg.control_port
+    );
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept a control connection: {e}");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let token = token.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = handle(stream, token).await {
+                log::warn!("Control request failed: {}", e.message);
+            }
+        });
+    }
+}
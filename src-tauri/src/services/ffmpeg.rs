@@ -1,21 +1,34 @@
 use anyhow::{anyhow, Context, Result};
 use regex::Regex;
 use std::{
+    future::Future,
     path::{Path, PathBuf},
     pin::pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
-use tauri_plugin_shell::{process::CommandEvent, ShellExt};
+use tauri_plugin_shell::{
+    process::{Command, CommandChild, CommandEvent},
+    ShellExt,
+};
+use tauri_specta::Event;
 use time::{macros::format_description, OffsetDateTime, UtcOffset};
 use tokio::{
     fs,
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, Semaphore},
 };
 
 use crate::{
     config,
-    queue::{runtime::Progress, types::Task},
-    shared::{get_app_handle, get_image},
+    queue::{
+        export,
+        runtime::{ProcessPhase, Progress, QueueEvent},
+        types::{Clip, SubTaskStatus, Task},
+    },
+    services::{audio, bili::Chapter, naming},
+    shared::{get_app_handle, get_image, get_unique_path, ChapterSplit},
     TauriError, TauriResult,
 };
 
@@ -33,7 +46,65 @@ fn clean_log(raw_log: &[u8]) -> String {
         .join("\n")
 }
 
+/// Kills the process if dropped before `disarm`, so a post-processing step dropped on
+/// cancel doesn't leave FFmpeg running.
+struct KillOnDrop(Option<CommandChild>);
+
+impl KillOnDrop {
+    fn disarm(mut self) {
+        self.0.take();
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let Some(child) = self.0.take() {
+            let _ = child.kill();
+        }
+    }
+}
+
+/// Runs `cmd` to the end like `Command::output`, killing it if the future is dropped.
+async fn run(cmd: Command) -> TauriResult<()> {
+    let (mut rx, child) = cmd.spawn()?;
+    let guard = KillOnDrop(Some(child));
+    let mut stderr: Vec<String> = vec![];
+    let mut code = None;
+    while let Some(msg) = rx.recv().await {
+        match msg {
+            CommandEvent::Stderr(line) => stderr.push(String::from_utf8_lossy(&line).into()),
+            CommandEvent::Error(line) => stderr.push(line),
+            CommandEvent::Terminated(msg) => code = msg.code,
+            _ => (),
+        }
+    }
+    guard.disarm();
+    if code != Some(0) {
+        return Err(TauriError::new(
+            format!(
+                "FFmpeg task failed\n{}",
+                clean_log(stderr.join("\n").as_bytes())
+            ),
+            code,
+        ));
+    }
+    Ok(())
+}
+
+// Cleared when `test` fails, see `FfmpegFallback`
+static AVAILABLE: AtomicBool = AtomicBool::new(true);
+
+pub fn available() -> bool {
+    AVAILABLE.load(Ordering::Relaxed)
+}
+
 pub async fn test() -> Result<()> {
+    let result = test_inner().await;
+    AVAILABLE.store(result.is_ok(), Ordering::Relaxed);
+    result
+}
+
+async fn test_inner() -> Result<()> {
     let app = get_app_handle();
     let result = app
         .shell()
@@ -51,6 +122,31 @@ pub async fn test() -> Result<()> {
     Ok(())
 }
 
+/// First line of `ffmpeg -version`, e.g. `ffmpeg version 7.1 Copyright ...`.
+pub async fn version() -> Result<String> {
+    let app = get_app_handle();
+    let result = app
+        .shell()
+        .sidecar(EXEC)?
+        .args(["-version"])
+        .output()
+        .await?;
+    clean_log(&result.stdout)
+        .lines()
+        .next()
+        .map(String::from)
+        .ok_or(anyhow!("Empty FFmpeg version output"))
+}
+
+/// Sidecars are bundled next to the main executable.
+pub fn path() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    Some(
+        exe.parent()?
+            .join(format!("{EXEC}{}", std::env::consts::EXE_SUFFIX)),
+    )
+}
+
 async fn get_duration(path: &Path) -> Result<u64> {
     let app = get_app_handle();
     let path = path.to_string_lossy().to_string();
@@ -90,34 +186,40 @@ pub async fn merge(
     ext: &str,
     tx: &Progress,
     mut cancel: oneshot::Receiver<()>,
+    temp_root: &Path,
     video: &Path,
     audio: &Path,
 ) -> TauriResult<PathBuf> {
     let app = get_app_handle();
-    let temp_root = config::read().temp_dir().join(&*id);
+    let temp_root = temp_root.join(&*id);
     fs::create_dir_all(&temp_root).await?;
 
     let output = temp_root.join(format!("{id}.{ext}"));
     let duration = get_duration(video).await?;
 
-    let mut c = app.shell().sidecar(EXEC)?;
-
-    c = c
-        .args(["-hide_banner", "-nostats", "-loglevel", "warning"])
-        .arg("-i")
-        .arg(video.as_os_str())
-        .arg("-i")
-        .arg(audio.as_os_str())
-        .args(["-c", "copy", "-shortest"]);
-
+    let mut args: Vec<String> = ["-hide_banner", "-nostats", "-loglevel", "warning"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    args.extend([
+        "-i".into(),
+        video.to_string_lossy().into(),
+        "-i".into(),
+        audio.to_string_lossy().into(),
+    ]);
+    args.extend(["-c", "copy", "-shortest"].map(String::from));
     if ext == "mp4" {
-        c = c.args(["-movflags", "+faststart"]);
+        args.extend(["-movflags", "+faststart"].map(String::from));
     }
+    args.extend(["-progress".into(), "pipe:1".into()]);
+    args.extend([output.to_string_lossy().into(), "-y".into()]);
 
-    c = c
-        .args(["-progress", "pipe:1"])
-        .arg(output.as_os_str())
-        .arg("-y");
+    if config::read().keep_intermediates {
+        let line = export::join(&[&[EXEC.to_string()], &args[..]].concat());
+        fs::write(temp_root.join("ffmpeg.txt"), line).await?;
+    }
+
+    let c = app.shell().sidecar(EXEC)?.args(&args);
 
     let (mut _rx, child) = c.spawn()?;
     let mut child = Some(child);
@@ -135,6 +237,7 @@ pub async fn convert_audio(
     id: Arc<String>,
     mut ext: &str,
     tx: &Progress,
+    temp_root: &Path,
     input: &Path,
     task: Arc<Task>,
 ) -> TauriResult<(PathBuf, String)> {
@@ -145,12 +248,30 @@ pub async fn convert_audio(
         ext = "mp3";
     }
 
-    let add_meta = ext != "eac3" && cfg.add_metadata;
-    let temp_root = cfg.temp_dir().join(&*id);
+    let album = naming::album(&task);
+    let add_meta = ext != "eac3" && (cfg.add_metadata || album.is_some());
+    let temp_root = temp_root.join(&*id);
     fs::create_dir_all(&temp_root).await?;
 
     let duration = get_duration(input).await?;
     let nfo = task.nfo.as_ref();
+    // Songs of the audio zone credit their performer apart from the uploader
+    let song = match (task.item.media_type.as_str(), task.item.sid) {
+        ("music", Some(sid)) if add_meta => audio::song(sid as u64)
+            .await
+            .map_err(|e| log::warn!("Failed to fetch au{sid}: {e}"))
+            .ok(),
+        _ => None,
+    };
+    // An album is shown with the series cover rather than the episode's
+    let cover = match &album {
+        Some(_) => nfo
+            .thumbs
+            .iter()
+            .find(|v| v.id.ends_with("season_cover"))
+            .or(nfo.thumbs.first()),
+        None => nfo.thumbs.first(),
+    };
 
     let mut c = app.shell().sidecar(EXEC)?;
 
@@ -158,7 +279,7 @@ pub async fn convert_audio(
         .args(["-hide_banner", "-nostats", "-loglevel", "warning", "-i"])
         .arg(input.as_os_str());
 
-    if let Some(thumb) = nfo.thumbs.first().filter(|_| add_meta) {
+    if let Some(thumb) = cover.filter(|_| add_meta) {
         let cover_url = format!("{}@.jpg", thumb.url);
         let cover = &temp_root.join("cover.jpg");
         get_image(cover, &cover_url).await?;
@@ -205,7 +326,27 @@ pub async fn convert_audio(
             .arg("-metadata")
             .arg(format!("comment={}", task.item.desc))
             .arg("-metadata")
-            .arg(format!("track={}", task.seq + 1));
+            .arg(format!(
+                "track={}",
+                album.as_ref().map_or(task.seq + 1, |v| v.track)
+            ));
+        if let Some(album) = &album {
+            c = c.arg("-metadata").arg(format!("album={}", album.title));
+            if let Some(artist) = &album.artist {
+                c = c
+                    .arg("-metadata")
+                    .arg(format!("artist={artist}"))
+                    .arg("-metadata")
+                    .arg(format!("album_artist={artist}"));
+            }
+        }
+        if let Some(song) = &song {
+            let artist = match song.author.is_empty() {
+                true => &song.uploader,
+                false => &song.author,
+            };
+            c = c.arg("-metadata").arg(format!("artist={artist}"));
+        }
         if ext == "flac" {
             c = c
                 .arg("-metadata")
@@ -257,11 +398,257 @@ pub async fn convert_audio(
     Ok((output, ext.into()))
 }
 
+const STORYBOARD_WIDTH: u64 = 160;
+const STORYBOARD_HEIGHT: u64 = 90;
+const STORYBOARD_COLUMNS: u64 = 10;
+// Keeps the sprite under JPEG's 65535px height limit
+const STORYBOARD_MAX_FRAMES: u64 = 7000;
+
+// It decodes the whole video, so only one runs at a time
+static STORYBOARD: Semaphore = Semaphore::const_new(1);
+
+fn vtt_time(secs: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.000",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Writes a sprite sheet with a frame every `interval` seconds next to `video`, plus a
+/// WebVTT track pointing each time range at its tile, for scrubbing previews.
+pub async fn storyboard(parent: Arc<String>, video: PathBuf, interval: u64) -> TauriResult<()> {
+    let _permit = STORYBOARD.acquire().await?;
+    let app = get_app_handle();
+    let duration = get_duration(&video).await?;
+    if duration == 0 {
+        return Ok(());
+    }
+    let interval = interval
+        .max(duration.div_ceil(STORYBOARD_MAX_FRAMES))
+        .max(1);
+    let frames = duration.div_ceil(interval);
+    let rows = frames.div_ceil(STORYBOARD_COLUMNS);
+
+    let temp_root = config::read()
+        .temp_dir()
+        .join(format!("{parent}_storyboard"));
+    fs::create_dir_all(&temp_root).await?;
+    let report = |content, chunk| {
+        let parent = parent.clone();
+        async move {
+            QueueEvent::Processing {
+                parent,
+                phase: ProcessPhase::Storyboard,
+                status: SubTaskStatus { content, chunk },
+            }
+            .emit(get_app_handle())?;
+            Ok(())
+        }
+    };
+
+    // Frames first, since a tiled output only gets a timestamp once it's complete
+    let (rx, child) = app
+        .shell()
+        .sidecar(EXEC)?
+        .args(["-hide_banner", "-nostats", "-loglevel", "warning", "-i"])
+        .arg(video.as_os_str())
+        .arg("-vf")
+        .arg(format!(
+            "fps=1/{interval},scale={STORYBOARD_WIDTH}:{STORYBOARD_HEIGHT}"
+        ))
+        .args(["-q:v", "4", "-progress", "pipe:1"])
+        .arg(temp_root.join("%05d.jpg").as_os_str())
+        .arg("-y")
+        .spawn()?;
+    let guard = KillOnDrop(Some(child));
+    monitor_with(duration, rx, report).await?;
+    guard.disarm();
+
+    let stem = video
+        .file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .ok_or(anyhow!("Invalid video path: {}", video.display()))?;
+    let sprite_name = format!("{stem}.thumbnails.jpg");
+    let sprite = video.with_file_name(&sprite_name);
+    let tile = app
+        .shell()
+        .sidecar(EXEC)?
+        .args(["-hide_banner", "-nostats", "-loglevel", "warning"])
+        .args(["-framerate", "1", "-i"])
+        .arg(temp_root.join("%05d.jpg").as_os_str())
+        .arg("-vf")
+        .arg(format!("tile={STORYBOARD_COLUMNS}x{rows}"))
+        .args(["-frames:v", "1", "-q:v", "4"])
+        .arg(sprite.as_os_str())
+        .arg("-y");
+    let result = run(tile).await;
+    let _ = fs::remove_dir_all(&temp_root).await;
+    result?;
+
+    let mut vtt = String::from("WEBVTT\n");
+    for i in 0..frames {
+        let (x, y) = (
+            i % STORYBOARD_COLUMNS * STORYBOARD_WIDTH,
+            i / STORYBOARD_COLUMNS * STORYBOARD_HEIGHT,
+        );
+        vtt.push_str(&format!(
+            "\n{} --> {}\n{sprite_name}#xywh={x},{y},{STORYBOARD_WIDTH},{STORYBOARD_HEIGHT}\n",
+            vtt_time(i * interval),
+            vtt_time(((i + 1) * interval).min(duration)),
+        ));
+    }
+    fs::write(video.with_file_name(format!("{stem}.thumbnails.vtt")), vtt).await?;
+    Ok(())
+}
+
+// Re-encoding is as heavy as a storyboard, so splits also run one at a time
+static CHAPTERS: Semaphore = Semaphore::const_new(1);
+
+/// Cuts `video` into `{stem} - {index} {title}` files next to it, one per chapter. The
+/// full video is kept, as storyboards and `reveal_file` point at it.
+pub async fn split_chapters(
+    parent: Arc<String>,
+    video: PathBuf,
+    chapters: Vec<Chapter>,
+    mode: ChapterSplit,
+) -> TauriResult<()> {
+    let _permit = CHAPTERS.acquire().await?;
+    let app = get_app_handle();
+    let stem = video
+        .file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .ok_or(anyhow!("Invalid video path: {}", video.display()))?;
+    let ext = video
+        .extension()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let width = chapters.len().to_string().len().max(2);
+    let total = chapters.len() as u64;
+    for (index, chapter) in chapters.iter().enumerate() {
+        QueueEvent::Processing {
+            parent: parent.clone(),
+            phase: ProcessPhase::Chapters,
+            status: SubTaskStatus {
+                content: total,
+                chunk: index as u64,
+            },
+        }
+        .emit(app)?;
+        let title = chapter
+            .content
+            .replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_");
+        let name = format!("{stem} - {:0width$} {}.{ext}", index + 1, title.trim());
+        let output = get_unique_path(&parent, video.with_file_name(name));
+        let length = chapter.to.saturating_sub(chapter.from).max(1);
+        cut(
+            &video,
+            &output,
+            chapter.from as f64,
+            length as f64,
+            mode == ChapterSplit::Reencode,
+        )
+        .await?;
+    }
+    QueueEvent::Processing {
+        parent,
+        phase: ProcessPhase::Chapters,
+        status: SubTaskStatus {
+            content: total,
+            chunk: total,
+        },
+    }
+    .emit(app)?;
+    Ok(())
+}
+
+/// Writes `length` seconds of `input` from `from` on to `output`.
+async fn cut(
+    input: &Path,
+    output: &Path,
+    from: f64,
+    length: f64,
+    reencode: bool,
+) -> TauriResult<()> {
+    let mut args: Vec<String> = ["-hide_banner", "-nostats", "-loglevel", "warning"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    // Seeking before `-i` snaps a stream copy to the previous keyframe
+    args.extend(["-ss".into(), from.to_string()]);
+    args.extend(["-i".into(), input.to_string_lossy().into()]);
+    args.extend(["-t".into(), length.to_string(), "-map".into(), "0".into()]);
+    match reencode {
+        true => args.extend(
+            [
+                "-c:v", "libx264", "-crf", "18", "-preset", "veryfast", "-c:a", "aac",
+            ]
+            .map(String::from),
+        ),
+        false => args.extend(["-c", "copy", "-avoid_negative_ts", "make_zero"].map(String::from)),
+    }
+    args.extend([output.to_string_lossy().into(), "-y".into()]);
+    run(get_app_handle().shell().sidecar(EXEC)?.args(&args)).await
+}
+
+/// Cuts `clip` out of the downloaded or muxed file at `input` into a new file next to
+/// it, re-encoding when `clip_reencode` is set so the cut is exact. Returns its path.
+pub async fn cut_clip(parent: &Arc<String>, input: &Path, clip: Clip) -> TauriResult<PathBuf> {
+    let ext = input
+        .extension()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let output = get_unique_path(parent, input.with_extension(format!("clip.{ext}")));
+    let reencode = config::read().clip_reencode;
+    cut(input, &output, clip.start, clip.length(), reencode).await?;
+    Ok(output)
+}
+
+// Anything smaller is taken for a failed encode, whatever ffmpeg's exit status said
+const MIN_TRANSCODED: u64 = 16 * 1024;
+
+/// Removes `source` after it was re-encoded into `output`, if `delete_transcoded` asks for
+/// it and `output` looks complete. Failures only get logged, the task already succeeded.
+pub async fn remove_source(source: &Path, output: &Path) {
+    let cfg = config::read();
+    if !cfg.delete_transcoded || cfg.keep_intermediates {
+        return;
+    }
+    let size = fs::metadata(output).await.map(|v| v.len()).unwrap_or(0);
+    if size < MIN_TRANSCODED {
+        log::warn!(
+            "Keeping {}, transcoded {} is only {size} bytes",
+            source.display(),
+            output.display()
+        );
+        return;
+    }
+    let reclaimed = fs::metadata(source).await.map(|v| v.len()).unwrap_or(0);
+    match fs::remove_file(source).await {
+        Ok(_) => log::info!("Removed {}, reclaiming {reclaimed} bytes", source.display()),
+        Err(e) => log::warn!("Failed to remove {}: {e}", source.display()),
+    }
+}
+
 async fn monitor(
     duration: u64,
-    mut rx: mpsc::Receiver<CommandEvent>,
+    rx: mpsc::Receiver<CommandEvent>,
     tx: &Progress,
 ) -> TauriResult<()> {
+    monitor_with(duration, rx, |content, chunk| tx.send(content, chunk)).await
+}
+
+/// Like `monitor`, for work that isn't a subtask and reports progress elsewhere.
+async fn monitor_with<F, Fut>(
+    duration: u64,
+    mut rx: mpsc::Receiver<CommandEvent>,
+    report: F,
+) -> TauriResult<()>
+where
+    F: Fn(u64, u64) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
     let mut stderr: Vec<String> = vec![];
     while let Some(msg) = rx.recv().await {
         match msg {
@@ -274,7 +661,7 @@ async fn monitor(
                 match key.trim() {
                     "out_time_us" | "out_time_ms" => {
                         let chunk = value.parse::<u64>().unwrap_or(0);
-                        tx.send(duration, chunk / 1_000_000).await?;
+                        report(duration, chunk / 1_000_000).await?;
                     }
                     "progress" => {
                         if value.trim() == "end" {
@@ -293,7 +680,7 @@ async fn monitor(
             CommandEvent::Terminated(msg) => {
                 let code = msg.code.unwrap_or(0);
                 if code == 0 {
-                    tx.send(1, 1).await?;
+                    report(1, 1).await?;
                 } else {
                     return Err(TauriError::new(
                         format!(
@@ -0,0 +1,167 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::{HashSet, VecDeque};
+
+use crate::{config, queue::runtime::TASK_MANAGER, services::bili, TauriResult};
+
+// Graphs are usually a few dozen nodes, this only guards against looping edges
+// the visited set doesn't catch and pathologically large graphs.
+const MAX_NODES: usize = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct InteractiveChoice {
+    pub edge_id: u64,
+    pub option: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct InteractiveNode {
+    pub edge_id: u64,
+    pub cid: u64,
+    pub title: String,
+    /// Position in breadth-first order from the start node, used as the part index.
+    pub index: usize,
+    pub choices: Vec<InteractiveChoice>,
+}
+
+/// Every node of an interactive video, for the frontend to enqueue as one multi-part
+/// download named by node title.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct InteractiveGraph {
+    pub aid: u64,
+    pub bvid: String,
+    pub graph_version: u64,
+    pub nodes: Vec<InteractiveNode>,
+}
+
+#[derive(Deserialize)]
+struct Interaction {
+    graph_version: u64,
+}
+
+#[derive(Deserialize)]
+struct PlayerData {
+    interaction: Option<Interaction>,
+}
+
+#[derive(Deserialize)]
+struct EdgeChoice {
+    id: u64,
+    cid: u64,
+    #[serde(default)]
+    option: String,
+}
+
+#[derive(Deserialize)]
+struct EdgeQuestion {
+    #[serde(default)]
+    choices: Vec<EdgeChoice>,
+}
+
+#[derive(Deserialize)]
+struct Edges {
+    #[serde(default)]
+    questions: Vec<EdgeQuestion>,
+}
+
+#[derive(Deserialize)]
+struct EdgeInfo {
+    title: String,
+    edge_id: u64,
+    // Missing on ending nodes
+    edges: Option<Edges>,
+}
+
+async fn edge_info(aid: u64, graph_version: u64, edge_id: Option<u64>) -> TauriResult<EdgeInfo> {
+    let mut query = vec![
+        ("aid", aid.to_string()),
+        ("graph_version", graph_version.to_string()),
+    ];
+    if let Some(edge_id) = edge_id {
+        query.push(("edge_id", edge_id.to_string()));
+    }
+    TASK_MANAGER
+        .resolve
+        .run(bili::get(
+            "https://api.bilibili.com/x/stein/edgeinfo_v2",
+            &query,
+        ))
+        .await
+}
+
+/// Returns `None` for regular videos. Only the start node is included unless the
+/// `interactive_graph` setting is on.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_interactive_graph(id: String) -> TauriResult<Option<InteractiveGraph>> {
+    let info = bili::get_video_info(id).await?;
+    if !info.interactive {
+        return Ok(None);
+    }
+    let player: PlayerData = bili::get_wbi(
+        "https://api.bilibili.com/x/player/wbi/v2",
+        &[("aid", info.aid.to_string()), ("cid", info.cid.to_string())],
+    )
+    .await?;
+    let graph_version = player
+        .interaction
+        .ok_or(anyhow!("No interaction info found for av{}", info.aid))?
+        .graph_version;
+
+    let full = config::read().interactive_graph;
+    let mut nodes = vec![];
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from([(None, info.cid)]);
+    while let Some((edge_id, cid)) = queue.pop_front() {
+        if nodes.len() >= MAX_NODES {
+            log::warn!(
+                "Interactive graph of av{} truncated at {MAX_NODES} nodes",
+                info.aid
+            );
+            break;
+        }
+        let edge = edge_info(info.aid, graph_version, edge_id).await?;
+        if !visited.insert(edge.edge_id) {
+            continue;
+        }
+        let choices: Vec<&EdgeChoice> = edge
+            .edges
+            .iter()
+            .flat_map(|v| v.questions.iter())
+            .flat_map(|q| q.choices.iter())
+            .collect();
+        if full {
+            for choice in &choices {
+                if !visited.contains(&choice.id) {
+                    queue.push_back((Some(choice.id), choice.cid));
+                }
+            }
+        }
+        nodes.push(InteractiveNode {
+            edge_id: edge.edge_id,
+            cid,
+            title: edge.title,
+            index: nodes.len(),
+            choices: choices
+                .iter()
+                .map(|c| InteractiveChoice {
+                    edge_id: c.id,
+                    option: c.option.clone(),
+                })
+                .collect(),
+        });
+        if !full {
+            break;
+        }
+    }
+    Ok(Some(InteractiveGraph {
+        aid: info.aid,
+        bvid: info.bvid.clone(),
+        graph_version,
+        nodes,
+    }))
+}
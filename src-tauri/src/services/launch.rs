@@ -0,0 +1,151 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::sync::LazyLock;
+use tauri::Url;
+use tauri_plugin_deep_link::DeepLinkExt;
+use tauri_specta::Event;
+use tokio::sync::Mutex;
+
+use crate::{
+    config,
+    services::bili::{self, MediaId},
+    shared::{get_app_handle, InitStatus, SecondLaunch, INIT_STATUS, READY},
+    TauriResult,
+};
+
+pub const SCHEME: &str = "bilitools";
+
+// Links that arrived before the frontend could take them, most likely the one that
+// cold started the app.
+static PENDING: LazyLock<Mutex<Vec<String>>> = LazyLock::new(|| Mutex::new(vec![]));
+
+/// Emitted when a link reaches the app from outside. The frontend resolves it, and
+/// enqueues it too when `enqueue` is set, so it follows `auto_download`.
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkOpened {
+    pub input: String,
+    pub id: MediaId,
+    pub enqueue: bool,
+}
+
+fn is_ready() -> bool {
+    READY.get().is_some() && matches!(**INIT_STATUS.load(), InitStatus::Ready)
+}
+
+/// `bilitools://BV1xx411c7mD`, `bilitools://www.bilibili.com/video/...` or, from a
+/// userscript, `bilitools://open?url=<encoded link>`.
+fn from_scheme(input: &str) -> String {
+    let Some(rest) = input
+        .strip_prefix(SCHEME)
+        .and_then(|v| v.strip_prefix("://"))
+    else {
+        return input.into();
+    };
+    let url = Url::parse(input).ok().and_then(|v| {
+        v.query_pairs()
+            .find(|(k, _)| k == "url")
+            .map(|(_, v)| v.into_owned())
+    });
+    if let Some(url) = url {
+        return url;
+    }
+    rest.trim_end_matches('/').into()
+}
+
+async fn emit(input: String) -> TauriResult<()> {
+    let id = bili::normalize(&from_scheme(&input)).await?;
+    LinkOpened {
+        input,
+        id,
+        enqueue: config::read().auto_download,
+    }
+    .emit(get_app_handle())?;
+    Ok(())
+}
+
+/// Hands `input` to the frontend, or holds it until [`flush`] once startup is done.
+pub async fn open(input: String) {
+    let mut pending = PENDING.lock().await;
+    if !is_ready() {
+        pending.push(input);
+        return;
+    }
+    drop(pending);
+    if let Err(e) = emit(input.clone()).await {
+        log::warn!("Ignoring link {input}: {}", e.message);
+    }
+}
+
+/// Called once storage and services are up and again once the frontend has run
+/// `init`, only the later of the two finds the app ready.
+pub async fn flush() {
+    let list = {
+        let mut pending = PENDING.lock().await;
+        if !is_ready() {
+            return;
+        }
+        std::mem::take(&mut *pending)
+    };
+    for input in list {
+        if let Err(e) = emit(input.clone()).await {
+            log::warn!("Ignoring link {input}: {}", e.message);
+        }
+    }
+}
+
+/// Forwarded by the single-instance plugin with the argv of the launch it stopped.
+/// Arguments that aren't links are skipped, the first one is the executable. Scheme
+/// links come through the deep link handler instead.
+pub fn second_launch(argv: Vec<String>) {
+    if config::read().second_launch != SecondLaunch::Submit {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let prefix = format!("{SCHEME}:");
+        for arg in argv.into_iter().skip(1) {
+            if !arg.starts_with('-') && !arg.starts_with(&prefix) {
+                open(arg).await;
+            }
+        }
+    });
+}
+
+/// Registers or unregisters the scheme to match `url_scheme`. Only Windows and Linux
+/// register at runtime, macOS takes it from the bundle's Info.plist.
+pub fn register() -> Result<()> {
+    #[cfg(any(windows, target_os = "linux"))]
+    {
+        let deep_link = get_app_handle().deep_link();
+        if config::read().url_scheme {
+            deep_link.register(SCHEME)?;
+        } else if deep_link.is_registered(SCHEME)? {
+            deep_link.unregister(SCHEME)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn init() -> Result<()> {
+    // Without a desktop entry or xdg-mime on Linux, links already registered still arrive
+    if let Err(e) = register() {
+        log::warn!("Failed to register the {SCHEME} scheme: {e:#}");
+    }
+    let deep_link = get_app_handle().deep_link();
+    deep_link.on_open_url(|event| {
+        // macOS delivers links regardless of the setting
+        if !config::read().url_scheme {
+            return;
+        }
+        for url in event.urls() {
+            tauri::async_runtime::spawn(open(url.to_string()));
+        }
+    });
+    if config::read().url_scheme {
+        for url in deep_link.get_current()?.unwrap_or_default() {
+            tauri::async_runtime::spawn(open(url.to_string()));
+        }
+    }
+    Ok(())
+}
@@ -4,20 +4,63 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sha2::Sha256;
+use specta::Type;
+use std::collections::HashSet;
 use std::fmt::Write;
+use std::future::Future;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, LazyLock,
 };
-use tauri::http::{header, StatusCode};
-use tokio::time::{sleep, Duration};
+use tauri::{
+    http::{header, StatusCode},
+    Manager, Url,
+};
+use tauri_specta::Event;
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Duration, Instant},
+};
 
 use crate::{
-    shared::{get_ts, init_client, HEADERS},
+    config,
+    errors::AnyInt,
+    queue::runtime::TASK_MANAGER,
+    services::bili,
+    shared::{api_url, get_app_handle, get_ts, init_client, HEADERS},
     storage::cookies,
     TauriError, TauriResult,
 };
 
+#[derive(Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountChanged {
+    pub logged_in: bool,
+}
+
+/// Sent once a login's cookies are stored, `complete` when the ones downloads need are
+/// all there.
+#[derive(Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginCookies {
+    pub complete: bool,
+    pub missing: Vec<String>,
+}
+
+// The webview can get its cookies a little after the login response
+const WEBVIEW_POLLS: usize = 6;
+const WEBVIEW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// A failed refresh is handed back as is for this long instead of being tried again
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(30);
+
+type RefreshResult = std::result::Result<isize, (String, Option<AnyInt>)>;
+
+/// The latest cookie refresh and when it finished. Held for the whole refresh, since each
+/// one rotates the refresh token and concurrent ones would invalidate each other.
+static LAST_REFRESH: LazyLock<Mutex<Option<(Instant, RefreshResult)>>> =
+    LazyLock::new(Default::default);
+
 static LOGIN_POLLING: LazyLock<Arc<AtomicBool>> =
     LazyLock::new(|| Arc::new(AtomicBool::new(false)));
 
@@ -167,7 +210,9 @@ pub async fn get_bili_ticket() -> TauriResult<()> {
         let _ = write!(&mut hexsign, "{:02x}", b);
     }
     let response = client
-        .post("https://api.bilibili.com/bapis/bilibili.api.ticket.v1.Ticket/GenWebTicket")
+        .post(api_url(
+            "https://api.bilibili.com/bapis/bilibili.api.ticket.v1.Ticket/GenWebTicket",
+        ))
         .query(&[
             ("key_id", "ec02"),
             ("hexsign", &hexsign),
@@ -187,12 +232,85 @@ pub async fn get_bili_ticket() -> TauriResult<()> {
         .await
         .context("Failed to decode BiliTicket response")?;
     if let Some(data) = body.data {
-        Ok(cookies::insert(format!("bili_ticket={}", data.ticket)).await?)
+        cookies::insert(format!("bili_ticket={}", data.ticket)).await?;
+        Ok(())
     } else {
         Err(TauriError::new(body.message, Some(body.code)))
     }
 }
 
+/// Stores the cookies a login response set, warning in the log when they don't add up
+/// to a usable login.
+/// Cookies the main webview holds for bilibili, httponly ones included, as `Set-Cookie`
+/// values.
+fn webview_cookies() -> Vec<(String, String)> {
+    let Some(window) = get_app_handle().get_webview_window("main") else {
+        return vec![];
+    };
+    let url = Url::parse("https://www.bilibili.com").expect("Invalid bilibili url");
+    match window.cookies_for_url(url) {
+        Ok(list) => list
+            .into_iter()
+            .map(|v| (v.name().to_string(), v.to_string()))
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to read webview cookies: {e}");
+            vec![]
+        }
+    }
+}
+
+async fn store_login(cookies: Vec<String>, refresh_token: &str) -> Result<()> {
+    let mut stored = cookies
+        .iter()
+        .filter_map(|v| v.split_once('=').map(|(k, _)| k.trim().to_string()))
+        .collect::<HashSet<_>>();
+    let refresh = format!("refresh_token={refresh_token}");
+    let mut report = cookies::insert_many(cookies.into_iter().chain([refresh])).await?;
+    // Only names the response didn't set are taken, the webview may still hold an older
+    // session's values
+    if config::read().webview_cookies {
+        for _ in 0..WEBVIEW_POLLS {
+            if report.complete {
+                break;
+            }
+            sleep(WEBVIEW_POLL_INTERVAL).await;
+            let extra = webview_cookies()
+                .into_iter()
+                .filter(|(name, _)| stored.insert(name.clone()))
+                .map(|(_, cookie)| cookie)
+                .collect::<Vec<_>>();
+            if !extra.is_empty() {
+                log::info!("Took {} cookies from the webview", extra.len());
+                report = cookies::insert_many(extra).await?;
+            }
+        }
+    }
+    if !report.complete || !report.warnings.is_empty() {
+        log::warn!("Login cookies look incomplete: {report:?}");
+    }
+    LoginCookies {
+        complete: report.complete,
+        missing: report.missing,
+    }
+    .emit(get_app_handle())?;
+    Ok(())
+}
+
+/// Stores cookies pasted as a `Cookie` header (`name=value; name=value`), for logging
+/// in with an existing browser session.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn import_cookies(cookie: String) -> TauriResult<cookies::LoginReport> {
+    let pairs = cookie
+        .split(';')
+        .filter(|v| v.contains('='))
+        .map(|v| v.trim_start().to_string());
+    let report = cookies::insert_many(pairs).await?;
+    HEADERS.refresh().await?;
+    Ok(report)
+}
+
 pub async fn get_uuid() -> Result<()> {
     const DIGIT_MAP: [&str; 16] = [
         "1", "2", "3", "4", "5", "6", "7", "8", "9", "A", "B", "C", "D", "E", "F", "10",
@@ -218,7 +336,10 @@ pub async fn get_uuid() -> Result<()> {
 
 pub async fn get_buvid() -> TauriResult<()> {
     let client = init_client().await?;
-    let html_resp = client.get("https://www.bilibili.com").send().await?;
+    let html_resp = client
+        .get(api_url("https://www.bilibili.com"))
+        .send()
+        .await?;
     if html_resp.status() != StatusCode::OK {
         return Err(TauriError::new(
             "Error while fetching initial Cookies",
@@ -241,7 +362,7 @@ pub async fn get_buvid() -> TauriResult<()> {
     }
 
     let buvid_resp = client
-        .get("https://api.bilibili.com/x/frontend/finger/spi")
+        .get(api_url("https://api.bilibili.com/x/frontend/finger/spi"))
         .send()
         .await?;
     if buvid_resp.status() != StatusCode::OK {
@@ -271,7 +392,7 @@ pub async fn exit() -> TauriResult<isize> {
     let cookies = cookies::load().await?;
     let bili_csrf = cookies.get("bili_jct").map(String::as_str).unwrap_or("");
     let response = client
-        .post("https://passport.bilibili.com/login/exit/v2")
+        .post(api_url("https://passport.bilibili.com/login/exit/v2"))
         .query(&[("biliCSRF", bili_csrf)])
         .send()
         .await?;
@@ -309,6 +430,30 @@ pub async fn exit() -> TauriResult<isize> {
     Ok(body.code)
 }
 
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn logout(clear_webview: bool) -> TauriResult<()> {
+    let paused = TASK_MANAGER.auth.logout().await?;
+    log::info!("Logging out, paused tasks: {paused:?}");
+    if cookies::load().await?.contains_key("bili_jct") {
+        if let Err(e) = exit().await {
+            log::warn!("Failed to exit login remotely: {e}");
+        }
+    }
+    cookies::clear().await?;
+    bili::clear_cache().await;
+    get_buvid().await?;
+    get_uuid().await?;
+    HEADERS.refresh().await?;
+    if clear_webview {
+        if let Some(window) = get_app_handle().get_webview_window("main") {
+            window.clear_all_browsing_data()?;
+        }
+    }
+    AccountChanged { logged_in: false }.emit(get_app_handle())?;
+    Ok(())
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn sms_login(
@@ -319,7 +464,9 @@ pub async fn sms_login(
 ) -> TauriResult<isize> {
     let client = init_client().await?;
     let response = client
-        .post("https://passport.bilibili.com/x/passport-login/web/login/sms")
+        .post(api_url(
+            "https://passport.bilibili.com/x/passport-login/web/login/sms",
+        ))
         .query(&[
             ("cid", cid.to_string()),
             ("tel", tel.to_string()),
@@ -348,10 +495,7 @@ pub async fn sms_login(
         .await
         .context("Failed to decode SMS login response")?;
     if let Some(data) = body.data {
-        for cookie in cookies {
-            cookies::insert(cookie).await?;
-        }
-        cookies::insert(format!("refresh_token={}", data.refresh_token)).await?;
+        store_login(cookies, &data.refresh_token).await?;
         HEADERS.refresh().await?;
         Ok(body.code)
     } else {
@@ -377,7 +521,9 @@ pub async fn pwd_login(
 ) -> TauriResult<isize> {
     let client = init_client().await?;
     let response = client
-        .post("https://passport.bilibili.com/x/passport-login/web/login")
+        .post(api_url(
+            "https://passport.bilibili.com/x/passport-login/web/login",
+        ))
         .form(&[
             ("username", username),
             ("password", encoded_pwd),
@@ -411,10 +557,7 @@ pub async fn pwd_login(
         if data.refresh_token.is_empty() || data.status != 0 {
             return Err(TauriError::new(data.message, Some(data.status)));
         }
-        for cookie in cookies {
-            cookies::insert(cookie).await?;
-        }
-        cookies::insert(format!("refresh_token={}", data.refresh_token)).await?;
+        store_login(cookies, &data.refresh_token).await?;
         HEADERS.refresh().await?;
         Ok(data.status)
     } else {
@@ -427,7 +570,9 @@ pub async fn pwd_login(
 pub async fn switch_cookie(switch_code: String) -> TauriResult<isize> {
     let client = init_client().await?;
     let response = client
-        .post("https://passport.bilibili.com/x/passport-login/web/login")
+        .post(api_url(
+            "https://passport.bilibili.com/x/passport-login/web/login",
+        ))
         .query(&[("code", switch_code), ("source", "risk".into())])
         .send()
         .await?;
@@ -449,10 +594,7 @@ pub async fn switch_cookie(switch_code: String) -> TauriResult<isize> {
         .await
         .context("Failed to decode switch cookie response")?;
     if let Some(data) = body.data {
-        for cookie in cookies {
-            cookies::insert(cookie).await?;
-        }
-        cookies::insert(format!("refresh_token={}", data.refresh_token)).await?;
+        store_login(cookies, &data.refresh_token).await?;
         HEADERS.refresh().await?;
         Ok(body.code)
     } else {
@@ -471,10 +613,10 @@ pub async fn scan_login(
     LOGIN_POLLING.store(true, Ordering::SeqCst);
     while LOGIN_POLLING.load(Ordering::SeqCst) {
         let response = client
-            .get(format!(
+            .get(api_url(&format!(
                 "https://passport.bilibili.com/x/passport-login/web/qrcode/poll?qrcode_key={}",
                 qrcode_key
-            ))
+            )))
             .send()
             .await?;
         if response.status() != StatusCode::OK {
@@ -498,10 +640,7 @@ pub async fn scan_login(
             event.send(data.code)?;
             match data.code {
                 0 => {
-                    for cookie in cookies {
-                        cookies::insert(cookie).await?;
-                    }
-                    cookies::insert(format!("refresh_token={}", data.refresh_token)).await?;
+                    store_login(cookies, &data.refresh_token).await?;
                     log::info!("{}: {}", masked_key, "扫码登录成功");
                     HEADERS.refresh().await?;
                     return Ok(data.code);
@@ -521,9 +660,45 @@ pub async fn scan_login(
     Ok(86114)
 }
 
+/// Refreshes the cookies, one refresh at a time. Callers that queued up behind one share
+/// its result rather than refreshing again with the token it just used up.
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn refresh_cookie(refresh_csrf: String) -> TauriResult<isize> {
+    single_flight(&LAST_REFRESH, || refresh_inner(refresh_csrf)).await
+}
+
+/// Runs `refresh` under `slot`, unless a refresh finished while this call waited for it or
+/// failed within `REFRESH_COOLDOWN`, whose result is returned instead.
+async fn single_flight<F, Fut>(
+    slot: &Mutex<Option<(Instant, RefreshResult)>>,
+    refresh: F,
+) -> TauriResult<isize>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = TauriResult<isize>>,
+{
+    let asked = Instant::now();
+    let mut last = slot.lock().await;
+    if let Some((at, result)) = &*last {
+        let cooling = result.is_err() && at.elapsed() < REFRESH_COOLDOWN;
+        if *at >= asked || cooling {
+            log::info!("Reusing the cookie refresh finished {:?} ago", at.elapsed());
+            return result
+                .clone()
+                .map_err(|(message, code)| TauriError::new(message, code));
+        }
+    }
+    let result = refresh().await;
+    let shared = match &result {
+        Ok(code) => Ok(*code),
+        Err(e) => Err((e.message.clone(), e.code)),
+    };
+    *last = Some((Instant::now(), shared));
+    result
+}
+
+async fn refresh_inner(refresh_csrf: String) -> TauriResult<isize> {
     let client = init_client().await?;
     let cookies = cookies::load().await?;
     let bili_csrf = cookies.get("bili_jct").map(String::as_str).unwrap_or("");
@@ -532,7 +707,9 @@ pub async fn refresh_cookie(refresh_csrf: String) -> TauriResult<isize> {
         .map(String::as_str)
         .unwrap_or("");
     let refresh_token_resp = client
-        .post("https://passport.bilibili.com/x/passport-login/web/cookie/refresh")
+        .post(api_url(
+            "https://passport.bilibili.com/x/passport-login/web/cookie/refresh",
+        ))
         .query(&[
             ("csrf", bili_csrf),
             ("refresh_csrf", &refresh_csrf),
@@ -556,10 +733,7 @@ pub async fn refresh_cookie(refresh_csrf: String) -> TauriResult<isize> {
         .collect();
     let refresh_token_body: RefreshCookieResponse = refresh_token_resp.json().await?;
     if let Some(data) = refresh_token_body.data {
-        for cookie in cookies {
-            cookies::insert(cookie).await?;
-        }
-        cookies::insert(format!("refresh_token={}", data.refresh_token)).await?;
+        store_login(cookies, &data.refresh_token).await?;
         HEADERS.refresh().await?;
     } else {
         return Err(TauriError::new(
@@ -568,7 +742,9 @@ pub async fn refresh_cookie(refresh_csrf: String) -> TauriResult<isize> {
         ));
     }
     let confirm_refresh_resp = client
-        .post("https://passport.bilibili.com/x/passport-login/web/confirm/refresh")
+        .post(api_url(
+            "https://passport.bilibili.com/x/passport-login/web/confirm/refresh",
+        ))
         .query(&[("csrf", bili_csrf), ("refresh_token", refresh_token)])
         .send()
         .await?;
@@ -587,3 +763,52 @@ pub async fn refresh_cookie(refresh_csrf: String) -> TauriResult<isize> {
     }
     Ok(confirm_refresh_body.code)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn simultaneous_expiries_refresh_once() {
+        let slot = Mutex::new(None);
+        let runs = &AtomicUsize::new(0);
+        let refresh = move || async move {
+            runs.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(50)).await;
+            Ok(0)
+        };
+        let results = tokio::join!(
+            single_flight(&slot, refresh),
+            single_flight(&slot, refresh),
+            single_flight(&slot, refresh),
+            single_flight(&slot, refresh),
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        for result in [results.0, results.1, results.2, results.3] {
+            assert_eq!(result.unwrap(), 0);
+        }
+
+        // Asked after the last one finished, so it refreshes again
+        single_flight(&slot, refresh).await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_is_shared_while_cooling_down() {
+        let slot = Mutex::new(None);
+        let runs = &AtomicUsize::new(0);
+        let refresh = move || async move {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Err(TauriError::new("Refresh failed", Some(-101)))
+        };
+        let first = single_flight(&slot, refresh).await.unwrap_err();
+        let again = single_flight(&slot, refresh).await.unwrap_err();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(again.message, first.message);
+        assert_eq!(
+            again.code.map(|v| v.saturating_isize()),
+            first.code.map(|v| v.saturating_isize())
+        );
+    }
+}
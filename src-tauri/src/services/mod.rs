@@ -1,12 +1,69 @@
 pub mod aria2c;
+pub mod audio;
+pub mod bili;
+pub mod control;
 pub mod ffmpeg;
+pub mod interactive;
+pub mod launch;
 pub mod login;
+pub mod naming;
+pub mod network;
+pub mod power;
+pub mod presets;
 pub mod queue;
+pub mod subscribe;
+pub mod updater;
 
-use crate::shared::process_err as err;
+use crate::{
+    config,
+    shared::{process_err as err, FfmpegFallback},
+};
 
 pub async fn init() -> anyhow::Result<()> {
     aria2c::init().await.map_err(|e| err(e, "aria2c"))?;
-    ffmpeg::test().await.map_err(|e| err(e, "ffmpeg"))?;
+    if let Err(e) = ffmpeg::test().await {
+        if config::read().ffmpeg_fallback == FfmpegFallback::Fail {
+            return Err(err(e, "ffmpeg"));
+        }
+        log::warn!("FFmpeg unavailable, muxed downloads will keep separate streams: {e:#}");
+    }
+    // Links can still be pasted in, so this doesn't hold up startup
+    if let Err(e) = launch::init() {
+        err(e, "launch");
+    }
+    tauri::async_runtime::spawn(async {
+        let _ = network::watch().await.map_err(|e| err(e, "network"));
+    });
+    control::restart().await;
+    tauri::async_runtime::spawn(async {
+        let _ = network::watch_reachability()
+            .await
+            .map_err(|e| err(e, "reachability"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = queue::runtime::flusher()
+            .await
+            .map_err(|e| err(e, "flusher"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = subscribe::watch().await.map_err(|e| err(e, "subscribe"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = queue::runtime::watchdog()
+            .await
+            .map_err(|e| err(e, "watchdog"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = queue::runtime::tuner().await.map_err(|e| err(e, "tuner"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = power::watch().await.map_err(|e| err(e, "power"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = power::watch_battery().await.map_err(|e| err(e, "battery"));
+    });
+    tauri::async_runtime::spawn(async {
+        let _ = power::watch_quiet().await.map_err(|e| err(e, "quiet"));
+    });
     Ok(())
 }
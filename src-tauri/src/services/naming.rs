@@ -0,0 +1,643 @@
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
+use time::{OffsetDateTime, UtcOffset};
+use tokio::fs;
+
+use crate::{
+    config,
+    queue::{
+        runtime::TASK_MANAGER,
+        types::{SubTask, Task},
+    },
+    shared::get_ts,
+    TauriResult,
+};
+
+// Staging folder created inside an output folder with `temp_near_output`
+const TEMP_AREA: &str = ".bilitools-temp";
+
+// Most filesystems cap a single name at 255 bytes, NTFS at 255 UTF-16 units
+const MAX_COMPONENT: usize = 255;
+// MAX_PATH minus the terminating NUL
+const MAX_PATH: usize = 259;
+// Room kept after a file stem for what gets appended to it, like `.zh-CN.srt`
+const FILE_RESERVE: usize = 24;
+// Room kept after a folder for the files placed inside it
+const FOLDER_RESERVE: usize = 64;
+
+// Names Windows refuses for a file or folder, whatever the extension
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+// Characters the frontend replaces with `_` after filling a template
+const UNSAFE: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+// Placeholders of the frontend's `NamingTemplates`, then those filled by `apply` and
+// `apply_clip`
+const SERIES_KEYS: &[&str] = &["showtitle", "container"];
+const ITEM_KEYS: &[&str] = &["showtitle", "title", "container", "mediaType", "index"];
+const FILE_KEYS: &[&str] = &["taskType", "res", "abr", "enc", "fmt", "start", "end"];
+const SHARED_KEYS: &[&str] = &[
+    "pubtime", "downtime", "upper", "upperid", "aid", "sid", "fid", "cid", "bvid", "epid", "ssid",
+    "opid",
+];
+const EPISODE_KEYS: &[&str] = &["ep", "ep_title"];
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{([^{}]+)\}").unwrap());
+// The dayjs tokens previews understand in `{pubtime:…}` and `{downtime:…}`
+static DATE_TOKEN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"YYYY|YY|MM|M|DD|D|HH|H|mm|m|ss|s").unwrap());
+
+// `第12话 标题`, `EP12 标题` or a bare `12 标题`
+static NUMBERED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:第\s*(\d+)\s*[话話集期回]|(?i:ep?)\s*(\d+)|(\d+))(?:[\s.:：、_-]+|$)(.*)$")
+        .unwrap()
+});
+// An episode number closing a name, kept when the rest is trimmed: `… 第12话`, `… - 03`
+static TRAILING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[\s_-]*(?:第\s*\d+\s*[话話集期回]|(?i:ep?|p)\s*\d+|\d+)$").unwrap()
+});
+// Specials, previews and the like, optionally numbered: `SP`, `PV2`, `OVA 1 标题`
+static SPECIAL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?i:(sp|ova|oad|pv|cm|op|ed|预告|特别篇|番外))\s*(\d*)(?:[\s.:：、_-]+|$)(.*)$",
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Label {
+    Numbered(usize),
+    Special(String, Option<usize>),
+    /// Nothing recognizable, the title is used as is.
+    Other,
+}
+
+fn parse(title: &str) -> (Label, String) {
+    if let Some(caps) = NUMBERED.captures(title) {
+        let number = (1..=3)
+            .find_map(|i| caps.get(i))
+            .and_then(|v| v.as_str().parse().ok());
+        if let Some(number) = number {
+            return (Label::Numbered(number), caps[4].trim().into());
+        }
+    }
+    if let Some(caps) = SPECIAL.captures(title) {
+        let tag = caps[1].to_uppercase();
+        return (
+            Label::Special(tag, caps[2].parse().ok()),
+            caps[3].trim().into(),
+        );
+    }
+    (Label::Other, title.trim().into())
+}
+
+/// Episode number of the task: from the title for bangumi, or its position for
+/// multi-part videos whose part names carry none.
+fn label(task: &Task) -> (Label, String) {
+    let item = &task.item;
+    match parse(&item.title) {
+        (Label::Other, title) if item.media_type != "bangumi" => {
+            (Label::Numbered(item.index + 1), title)
+        }
+        v => v,
+    }
+}
+
+fn series(task: &Task) -> String {
+    let item = &task.item;
+    // Parts of a video share the aid, episodes of a season the ssid
+    match item.ssid {
+        Some(ssid) => format!("{}:ss{ssid}", item.media_type),
+        None => format!("{}:av{:?}", item.media_type, item.aid),
+    }
+}
+
+/// Width that keeps every episode of the series sorting in order, 2 digits below 100.
+fn width(total: usize) -> usize {
+    total.to_string().len().max(2)
+}
+
+/// Highest episode number among queued tasks of the same series. Tasks whose lock is
+/// held elsewhere are skipped rather than waited on, as their handlers may be waiting
+/// on this one.
+fn total(task: &Task) -> usize {
+    let own = match label(task).0 {
+        Label::Numbered(v) => v,
+        _ => 0,
+    };
+    let Ok(tasks) = TASK_MANAGER.tasks.try_read() else {
+        return own;
+    };
+    let key = series(task);
+    tasks
+        .iter()
+        .filter(|(id, _)| **id != task.id)
+        .filter_map(|(_, v)| v.try_read().ok())
+        .filter(|v| series(v) == key)
+        .filter_map(|v| match label(&v).0 {
+            Label::Numbered(n) => Some(n),
+            _ => None,
+        })
+        .fold(own, usize::max)
+}
+
+fn episode(label: &Label, title: &str, width: usize) -> String {
+    match label {
+        Label::Numbered(n) => format!("{n:0width$}"),
+        Label::Special(tag, Some(n)) => format!("{tag}{n:0width$}"),
+        Label::Special(tag, None) => tag.clone(),
+        Label::Other => title.into(),
+    }
+}
+
+/// Tags and names of a task submitted with `music_album`.
+pub struct Album {
+    /// The series, or the task's own title outside of one.
+    pub title: String,
+    pub artist: Option<String>,
+    /// The episode number, so missing episodes leave a gap instead of renumbering the
+    /// rest. Specials and unnumbered parts use their position.
+    pub track: usize,
+    /// File stem, `01 Title`.
+    pub file: String,
+}
+
+pub fn album(task: &Task) -> Option<Album> {
+    if !task.select.media.music_album {
+        return None;
+    }
+    let (label, title) = label(task);
+    let track = match label {
+        Label::Numbered(n) => n,
+        _ => task.item.index + 1,
+    };
+    let title = match title.is_empty() {
+        true => task.item.title.trim().to_string(),
+        false => title,
+    };
+    let clean = |v: &str| v.replace(UNSAFE, "_");
+    Some(Album {
+        title: clean(
+            task.nfo
+                .showtitle
+                .as_deref()
+                .unwrap_or(&task.item.title)
+                .trim(),
+        ),
+        artist: task.nfo.upper.as_ref().map(|v| v.name.clone()),
+        file: clean(&format!("{track:0w$} {title}", w = width(total(task)))),
+        track,
+    })
+}
+
+/// Fills `{ep}` and `{ep_title}`, which the frontend's template leaves untouched, in a
+/// folder or file name resolved for `task`.
+pub fn apply(task: &Task, name: &str) -> String {
+    if !name.contains("{ep}") && !name.contains("{ep_title}") {
+        return name.into();
+    }
+    let (label, title) = label(task);
+    // A bare `12` has no title left after the number
+    let title = if title.is_empty() {
+        task.item.title.trim().to_string()
+    } else {
+        title
+    };
+    let ep = episode(&label, &title, width(total(task)));
+    // Same characters the frontend replaces in the rest of the name
+    let clean = |v: &str| v.replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_");
+    name.replace("{ep}", &clean(&ep))
+        .replace("{ep_title}", &clean(&title))
+}
+
+fn timestamp(secs: f64) -> String {
+    let secs = secs as u64;
+    format!("{:02}-{:02}-{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Fills `{start}` and `{end}` in a file name for a clipped `task`, or appends the range
+/// when the template has neither so clips of one video don't share a name.
+pub fn apply_clip(task: &Task, name: &str) -> String {
+    let Some(clip) = task.clip else {
+        return name.replace("{start}", "").replace("{end}", "");
+    };
+    let (start, end) = (timestamp(clip.start), timestamp(clip.end));
+    if !name.contains("{start}") && !name.contains("{end}") {
+        return format!("{name} [{start} - {end}]");
+    }
+    name.replace("{start}", &start).replace("{end}", &end)
+}
+
+/// Where the downloads of a task placed in `folder` are staged. With `temp_near_output`
+/// that is a folder inside it, falling back to `temp_dir` when it can't be written.
+pub async fn temp_root(folder: &Path) -> PathBuf {
+    let cfg = config::read();
+    if !cfg.temp_near_output {
+        return cfg.temp_dir();
+    }
+    let dir = folder.join(TEMP_AREA);
+    let probe = dir.join(".probe");
+    let result = async {
+        fs::create_dir_all(&dir).await?;
+        fs::write(&probe, b"").await?;
+        fs::remove_file(&probe).await
+    };
+    match result.await {
+        Ok(_) => dir,
+        Err(e) => {
+            log::warn!("Can't stage in {}, using temp_dir: {e}", dir.display());
+            cfg.temp_dir()
+        }
+    }
+}
+
+/// Removes what the subtasks left in the staging folder of `folder`, and the folder itself
+/// once no other task is using it.
+pub async fn clean_temp(folder: &Path, subtasks: &[Arc<SubTask>]) {
+    let dir = folder.join(TEMP_AREA);
+    for subtask in subtasks {
+        let _ = fs::remove_dir_all(dir.join(&*subtask.id)).await;
+    }
+    let _ = fs::remove_dir(dir).await;
+}
+
+/// Length as the platform counts it: UTF-16 units on Windows, bytes elsewhere.
+fn measure(v: &str, windows: bool) -> usize {
+    match windows {
+        true => v.encode_utf16().count(),
+        false => v.len(),
+    }
+}
+
+fn truncate(name: &str, budget: usize, windows: bool) -> String {
+    if measure(name, windows) <= budget {
+        return name.into();
+    }
+    let (head, tail) = match TRAILING.find(name) {
+        Some(m) if m.start() > 0 && measure(m.as_str(), windows) < budget => {
+            name.split_at(m.start())
+        }
+        _ => (name, ""),
+    };
+    let room = budget - measure(tail, windows);
+    let mut kept = 0;
+    let head: String = head
+        .chars()
+        .take_while(|c| {
+            kept += measure(c.encode_utf8(&mut [0; 4]), windows);
+            kept <= room
+        })
+        .collect();
+    let result = format!("{}{tail}", head.trim_end());
+    match result.is_empty() {
+        true => name.chars().take(1).collect(),
+        false => result,
+    }
+}
+
+/// Trims `name` so it fits in `dir` along with `suffix` appended to it and `inside` for
+/// what goes below it, keeping a trailing episode number. Only Windows without
+/// `long_paths` limits the whole path, elsewhere a single name overflows first.
+fn fit(dir: &Path, name: &str, suffix: usize, inside: usize) -> String {
+    let windows = cfg!(windows);
+    let result = truncate(name, budget(dir, suffix, inside, windows), windows);
+    if result.len() != name.len() {
+        log::info!("Shortened {name} to {result} to fit in {}", dir.display());
+    }
+    result
+}
+
+fn budget(dir: &Path, suffix: usize, inside: usize, windows: bool) -> usize {
+    let budget = MAX_COMPONENT - suffix;
+    if !windows || config::read().long_paths {
+        return budget;
+    }
+    let used = measure(&dir.to_string_lossy(), windows) + 1;
+    budget.min(MAX_PATH.saturating_sub(used + suffix + inside))
+}
+
+/// Shortens a file stem placed in `dir`.
+pub fn fit_file(dir: &Path, stem: &str) -> String {
+    fit(dir, stem, FILE_RESERVE, 0)
+}
+
+/// Shortens a folder created in `parent`, leaving room for the files it will hold.
+pub fn fit_folder(parent: &Path, name: &str) -> String {
+    fit(parent, name, 0, FOLDER_RESERVE)
+}
+
+// Template preview
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateScope {
+    Series,
+    Item,
+    #[default]
+    File,
+}
+
+impl TemplateScope {
+    fn accepts(&self, key: &str) -> bool {
+        let own = match self {
+            TemplateScope::Series => SERIES_KEYS,
+            TemplateScope::Item => ITEM_KEYS,
+            TemplateScope::File => FILE_KEYS,
+        };
+        let item = *self != TemplateScope::Series;
+        own.contains(&key)
+            || SHARED_KEYS.contains(&key)
+            || (item && (ITEM_KEYS.contains(&key) || EPISODE_KEYS.contains(&key)))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformName {
+    pub os: String,
+    pub name: String,
+    /// Shortened to fit the name or, on Windows without `long_paths`, the path limit.
+    pub truncated: bool,
+    /// A device name Windows won't create, like `CON` or `COM1.mp4`.
+    pub reserved: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplatePreview {
+    /// Filled in as the queue would, before sanitizing.
+    pub filled: String,
+    /// Where it lands on this system, under `down_dir`.
+    pub path: PathBuf,
+    /// Placeholders the scope doesn't know, left in the name as typed.
+    pub invalid: Vec<String>,
+    /// Characters replaced with `_`, including path separators: a template can't add
+    /// folders.
+    pub sanitized: Vec<char>,
+    pub platforms: Vec<PlatformName>,
+}
+
+/// Values used for placeholders the sample doesn't give.
+fn sample_value(key: &str) -> String {
+    match key {
+        "showtitle" => "Sample Series",
+        "title" | "ep_title" => "Sample Title",
+        "container" | "mediaType" | "taskType" => "Video",
+        "pubtime" => "1700000000",
+        "upper" => "Sample Uploader",
+        "upperid" => "12345",
+        "aid" => "170001",
+        "cid" => "279786",
+        "bvid" => "BV17x411w7KC",
+        "epid" => "374717",
+        "ssid" => "28747",
+        "res" => "1080P",
+        "abr" => "192K",
+        "enc" => "AVC",
+        "fmt" => "DASH",
+        "index" => "1",
+        "ep" => "01",
+        "start" => "00-00-00",
+        "end" => "00-01-30",
+        _ => "",
+    }
+    .into()
+}
+
+/// A timestamp through a dayjs pattern, as the frontend formats `{pubtime:…}`.
+fn format_date(ts: i64, pattern: Option<&str>) -> String {
+    let pattern = pattern.unwrap_or("YYYY-MM-DD_HH-mm-ss");
+    if pattern.eq_ignore_ascii_case("ts") {
+        return ts.to_string();
+    }
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let Ok(date) = OffsetDateTime::from_unix_timestamp(ts) else {
+        return ts.to_string();
+    };
+    let date = date.to_offset(offset);
+    DATE_TOKEN
+        .replace_all(pattern, |caps: &Captures| match &caps[0] {
+            "YYYY" => format!("{:04}", date.year()),
+            "YY" => format!("{:02}", date.year() % 100),
+            "MM" => format!("{:02}", date.month() as u8),
+            "M" => (date.month() as u8).to_string(),
+            "DD" => format!("{:02}", date.day()),
+            "D" => date.day().to_string(),
+            "HH" => format!("{:02}", date.hour()),
+            "H" => date.hour().to_string(),
+            "mm" => format!("{:02}", date.minute()),
+            "m" => date.minute().to_string(),
+            "ss" => format!("{:02}", date.second()),
+            _ => date.second().to_string(),
+        })
+        .into_owned()
+}
+
+fn platform(os: &str, dir: &Path, name: &str, scope: TemplateScope) -> PlatformName {
+    let windows = os == "windows";
+    let (suffix, inside) = match scope {
+        TemplateScope::File => (FILE_RESERVE, 0),
+        _ => (0, FOLDER_RESERVE),
+    };
+    let mut fitted = truncate(name, budget(dir, suffix, inside, windows), windows);
+    // Windows drops trailing dots and spaces, which may be all that's left
+    if windows {
+        fitted = fitted.trim_end_matches(['.', ' ']).to_string();
+    }
+    let stem = fitted.split('.').next().unwrap_or_default().trim_end();
+    PlatformName {
+        os: os.into(),
+        truncated: fitted.len() != name.len(),
+        reserved: windows && RESERVED.iter().any(|v| v.eq_ignore_ascii_case(stem)),
+        name: fitted,
+    }
+}
+
+/// Fills `template` for `scope` from `sample`, defaulting to made-up values, and shows
+/// the name each platform ends up with.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn preview_template(
+    template: String,
+    scope: Option<TemplateScope>,
+    sample: Option<BTreeMap<String, String>>,
+) -> TauriResult<TemplatePreview> {
+    let scope = scope.unwrap_or_default();
+    let sample = sample.unwrap_or_default();
+    let mut invalid = vec![];
+    let filled = PLACEHOLDER
+        .replace_all(&template, |caps: &Captures| {
+            let (key, arg) = match caps[1].split_once(':') {
+                Some((k, v)) => (k.trim(), Some(v.trim())),
+                None => (caps[1].trim(), None),
+            };
+            if !scope.accepts(key) {
+                invalid.push(key.to_string());
+                return caps[0].to_string();
+            }
+            let value = sample.get(key).cloned().unwrap_or_else(|| match key {
+                "downtime" => get_ts(true).to_string(),
+                _ => sample_value(key),
+            });
+            match key {
+                "pubtime" | "downtime" => match value.parse() {
+                    Ok(ts) => format_date(ts, arg),
+                    Err(_) => value,
+                },
+                _ => value,
+            }
+        })
+        .into_owned();
+
+    let mut sanitized = vec![];
+    for c in filled.chars().filter(|c| UNSAFE.contains(c)) {
+        if !sanitized.contains(&c) {
+            sanitized.push(c);
+        }
+    }
+    let name = filled.replace(UNSAFE, "_");
+    let name = name.trim_end_matches('.');
+
+    let dir = config::read().down_dir.clone();
+    let platforms: Vec<_> = ["windows", "macos", "linux"]
+        .into_iter()
+        .map(|os| platform(os, &dir, name, scope))
+        .collect();
+    let current = platforms
+        .iter()
+        .find(|v| v.os == std::env::consts::OS)
+        .map_or(name, |v| v.name.as_str());
+    Ok(TemplatePreview {
+        path: dir.join(current),
+        filled,
+        invalid,
+        sanitized,
+        platforms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::types::fixtures;
+
+    fn task(title: &str, media_type: &str, index: usize) -> Task {
+        let mut task = fixtures::task("a", "BV1xx411c7mD");
+        let item = Arc::make_mut(&mut task.item);
+        item.title = title.into();
+        item.media_type = media_type.into();
+        item.index = index;
+        task
+    }
+
+    #[test]
+    fn parses_numbered_and_special_titles() {
+        let cases = [
+            ("第12话 终章", Label::Numbered(12), "终章"),
+            ("EP3 Title", Label::Numbered(3), "Title"),
+            ("07", Label::Numbered(7), ""),
+            ("SP 特别篇", Label::Special("SP".into(), None), "特别篇"),
+            ("pv2", Label::Special("PV".into(), Some(2)), ""),
+            ("OVA 1 番外", Label::Special("OVA".into(), Some(1)), "番外"),
+            ("预告", Label::Special("预告".into(), None), ""),
+            ("Movie", Label::Other, "Movie"),
+        ];
+        for (title, label, rest) in cases {
+            assert_eq!(parse(title), (label, rest.to_string()), "{title}");
+        }
+    }
+
+    #[test]
+    fn pads_every_entry_to_the_widest_number() {
+        let titles = ["第1话 开端", "SP 特别篇", "第101话 终章", "PV2", "预告"];
+        let labels: Vec<_> = titles.iter().map(|v| parse(v)).collect();
+        let total = labels
+            .iter()
+            .filter_map(|(v, _)| match v {
+                Label::Numbered(n) => Some(*n),
+                _ => None,
+            })
+            .max()
+            .unwrap();
+        let eps: Vec<_> = labels
+            .iter()
+            .map(|(label, title)| episode(label, title, width(total)))
+            .collect();
+        assert_eq!(eps, ["001", "SP", "101", "PV002", "预告"]);
+    }
+
+    #[test]
+    fn width_is_at_least_two() {
+        assert_eq!(width(1), 2);
+        assert_eq!(width(99), 2);
+        assert_eq!(width(100), 3);
+    }
+
+    #[test]
+    fn fills_episode_placeholders() {
+        let name = "{ep} - {ep_title}";
+        assert_eq!(apply(&task("第3话 标题", "bangumi", 0), name), "03 - 标题");
+        assert_eq!(apply(&task("SP 特别篇", "bangumi", 5), name), "SP - 特别篇");
+        assert_eq!(apply(&task("PV1", "bangumi", 6), name), "PV01 - PV1");
+        // Parts without a number in their name are numbered by position
+        assert_eq!(apply(&task("Intro", "video", 1), name), "02 - Intro");
+        assert_eq!(apply(&task("Intro", "video", 1), "{title}"), "{title}");
+    }
+
+    #[test]
+    fn truncation_never_splits_a_character() {
+        // 3 bytes and 1 UTF-16 unit each
+        assert_eq!(truncate("中文中文", 7, false), "中文");
+        assert_eq!(truncate("中文中文", 3, true), "中文中");
+        // 4 bytes and 2 UTF-16 units each
+        assert_eq!(truncate("😀😀😀", 5, false), "😀");
+        assert_eq!(truncate("😀😀😀", 5, true), "😀😀");
+        assert_eq!(truncate("a😀", 2, true), "a");
+    }
+
+    #[test]
+    fn truncation_at_the_boundary() {
+        assert_eq!(truncate("中文中文", 12, false), "中文中文");
+        assert_eq!(truncate("中文中文", 11, false), "中文中");
+        assert_eq!(truncate("中文中文", 4, true), "中文中文");
+        // Never left empty, even when not one character fits
+        assert_eq!(truncate("标题", 1, false), "标");
+    }
+
+    #[test]
+    fn truncation_keeps_the_trailing_episode() {
+        let name = "很长的标题名称 第12话";
+        assert_eq!(truncate(name, 20, false), "很长的 第12话");
+        assert_eq!(truncate("Some long title - 03", 12, false), "Some lo - 03");
+    }
+
+    #[test]
+    fn budget_counts_the_whole_path_on_windows() {
+        // 603 bytes but 203 UTF-16 units
+        let dir = PathBuf::from(format!("D:\\{}", "目录".repeat(100)));
+        assert_eq!(
+            budget(&dir, FILE_RESERVE, 0, false),
+            MAX_COMPONENT - FILE_RESERVE
+        );
+        // The folder, a separator and the suffix, out of MAX_PATH
+        assert_eq!(
+            budget(&dir, FILE_RESERVE, 0, true),
+            MAX_PATH - 204 - FILE_RESERVE
+        );
+        assert_eq!(budget(&dir, 0, FOLDER_RESERVE, true), 0);
+
+        let dir = PathBuf::from(format!("D:\\{}", "目录".repeat(50)));
+        assert_eq!(
+            budget(&dir, 0, FOLDER_RESERVE, true),
+            MAX_PATH - 104 - FOLDER_RESERVE
+        );
+    }
+}
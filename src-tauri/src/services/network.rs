@@ -0,0 +1,207 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock,
+    },
+    time::Duration,
+};
+use tauri_specta::Event;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::sleep,
+};
+
+use crate::{
+    config,
+    queue::runtime::{QueueEvent, ResizableSemaphore, TASK_MANAGER},
+    shared::{api_url, get_app_handle, init_client},
+    TauriResult,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// Consecutive good probes before retrying, so a flapping link doesn't burn retries
+const STABLE_PROBES: usize = 3;
+const PROBE_URL: &str = "https://api.bilibili.com/x/web-interface/nav";
+
+pub static CONNECTIONS: LazyLock<Connections> = LazyLock::new(Connections::new);
+
+/// App-wide ceiling from the `max_connections` setting, shared by API requests and
+/// downloads. Waiters are served in arrival order, so a download asking for several
+/// connections can't starve the requests queued behind it.
+pub struct Connections {
+    sem: ResizableSemaphore,
+    in_use: AtomicUsize,
+}
+
+/// Held for as long as its connections are open.
+pub struct ConnectionPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    count: usize,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        CONNECTIONS.in_use.fetch_sub(self.count, Ordering::Relaxed);
+    }
+}
+
+impl Connections {
+    fn new() -> Self {
+        Self {
+            sem: ResizableSemaphore::new(0),
+            in_use: AtomicUsize::new(0),
+        }
+    }
+
+    fn semaphore(&self, limit: usize) -> Arc<Semaphore> {
+        self.sem.resize(limit);
+        self.sem.get()
+    }
+
+    /// Waits for `count` connections, capped at the limit so a large ask can't hang.
+    pub async fn acquire(&self, count: usize) -> TauriResult<ConnectionPermit> {
+        let limit = config::read().max_connections;
+        let permit = match limit {
+            0 => None,
+            _ => {
+                let n = count.clamp(1, limit) as u32;
+                Some(self.semaphore(limit).acquire_many_owned(n).await?)
+            }
+        };
+        self.in_use.fetch_add(count, Ordering::Relaxed);
+        Ok(ConnectionPermit {
+            _permit: permit,
+            count,
+        })
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns `None` when the platform can't tell whether the connection is metered.
+#[cfg(target_os = "windows")]
+pub async fn is_metered() -> Option<bool> {
+    use windows::Networking::Connectivity::{NetworkCostType, NetworkInformation};
+    tokio::task::spawn_blocking(|| {
+        let profile = NetworkInformation::GetInternetConnectionProfile().ok()?;
+        let cost = profile.GetConnectionCost().ok()?.NetworkCostType().ok()?;
+        match cost {
+            NetworkCostType::Unrestricted => Some(false),
+            NetworkCostType::Fixed | NetworkCostType::Variable => Some(true),
+            _ => None,
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[cfg(target_os = "linux")]
+pub async fn is_metered() -> Option<bool> {
+    use tauri_plugin_shell::ShellExt;
+    let output = get_app_handle()
+        .shell()
+        .command("busctl")
+        .args([
+            "get-property",
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "Metered",
+        ])
+        .output()
+        .await
+        .ok()?;
+    // NMMetered: 0 unknown, 1 yes, 2 no, 3 guess-yes, 4 guess-no
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "u 1" | "u 3" => Some(true),
+        "u 2" | "u 4" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub async fn is_metered() -> Option<bool> {
+    None
+}
+
+pub async fn watch() -> TauriResult<()> {
+    let mut paused = false;
+    loop {
+        sleep(POLL_INTERVAL).await;
+        let enabled = config::read().pause_on_metered;
+        let metered = if enabled {
+            is_metered().await
+        } else {
+            Some(false)
+        };
+        let Some(metered) = metered else {
+            continue;
+        };
+        if metered == paused {
+            continue;
+        }
+        // Left as it was on failure, so the next poll tries again
+        let list = match TASK_MANAGER.network_pause(metered).await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to apply metered connection {metered}: {e:#}");
+                continue;
+            }
+        };
+        paused = metered;
+        log::info!("Metered connection: {metered}, affected tasks: {list:?}");
+        if let Err(e) = (QueueEvent::Network { metered, list }).emit(get_app_handle()) {
+            log::warn!("Failed to send metered connection event: {e}");
+        }
+    }
+}
+
+/// Any answer at all counts, the endpoint replies to guests with an error code.
+async fn reachable() -> bool {
+    let Ok(client) = init_client().await else {
+        return false;
+    };
+    client
+        .head(api_url(PROBE_URL))
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+        .is_ok()
+}
+
+/// Probes Bilibili while tasks are held by `ReconnectGuard`, and retries them once the
+/// connection has been back for `STABLE_PROBES` polls in a row.
+pub async fn watch_reachability() -> TauriResult<()> {
+    let mut stable = 0;
+    loop {
+        sleep(PROBE_INTERVAL).await;
+        if !TASK_MANAGER.reconnect.pending().await {
+            stable = 0;
+            continue;
+        }
+        if !reachable().await {
+            if stable > 0 {
+                log::info!("Connection dropped again after {stable} good probes");
+            }
+            stable = 0;
+            continue;
+        }
+        stable += 1;
+        if stable < STABLE_PROBES {
+            continue;
+        }
+        stable = 0;
+        if let Err(e) = TASK_MANAGER.reconnect.resume().await {
+            log::warn!(
+                "Failed to retry tasks held for the connection: {}",
+                e.message
+            );
+        }
+    }
+}
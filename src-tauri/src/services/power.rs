@@ -0,0 +1,293 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
+use tauri_specta::Event;
+use time::{macros::format_description, OffsetDateTime, Time, UtcOffset};
+use tokio::{sync::watch, time::sleep};
+
+use crate::{
+    config,
+    queue::runtime::{QueueEvent, TASK_MANAGER},
+    shared::{get_app_handle, QuietHours},
+    TauriResult,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATTERY_INTERVAL: Duration = Duration::from_secs(30);
+// A wall clock jump past this between two polls means the system was asleep
+const WAKE_GAP: Duration = Duration::from_secs(30);
+const QUIET_INTERVAL: Duration = Duration::from_secs(30);
+
+// Whether quiet hours are on, for tasks waiting to start
+static QUIET: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+async fn transition(suspended: bool) -> TauriResult<()> {
+    if !config::read().pause_on_sleep {
+        return Ok(());
+    }
+    let list = TASK_MANAGER.sleep_pause(suspended).await?;
+    log::info!("System suspended: {suspended}, affected tasks: {list:?}");
+    QueueEvent::Power { suspended, list }.emit(get_app_handle())?;
+    Ok(())
+}
+
+/// Follows logind's `PrepareForSleep` signal, which fires with `true` before suspend
+/// and `false` after resume. Returns once `dbus-monitor` is unavailable or exits.
+#[cfg(target_os = "linux")]
+async fn watch_logind() -> TauriResult<()> {
+    use tauri_plugin_shell::{process::CommandEvent, ShellExt};
+    let (mut rx, _child) = get_app_handle()
+        .shell()
+        .command("dbus-monitor")
+        .args([
+            "--system",
+            "type='signal',interface='org.freedesktop.login1.Manager',member='PrepareForSleep'",
+        ])
+        .spawn()?;
+    let mut pending = false;
+    while let Some(event) = rx.recv().await {
+        let CommandEvent::Stdout(line) = event else {
+            if let CommandEvent::Terminated(msg) = event {
+                log::warn!("dbus-monitor exited ({:?})", msg.code);
+            }
+            continue;
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim();
+        if line.contains("member=PrepareForSleep") {
+            pending = true;
+        } else if pending && line.starts_with("boolean") {
+            pending = false;
+            if let Err(e) = transition(line.ends_with("true")).await {
+                log::warn!("Failed to follow the sleep signal: {}", e.message);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Without a suspend signal, spots a wake-up from the wall clock jumping between polls
+/// and bounces running tasks so their downloads reconnect instead of erroring out.
+async fn watch_clock() -> TauriResult<()> {
+    let mut last = SystemTime::now();
+    loop {
+        sleep(POLL_INTERVAL).await;
+        let now = SystemTime::now();
+        let gap = now.duration_since(last).unwrap_or_default();
+        last = now;
+        if gap < POLL_INTERVAL + WAKE_GAP {
+            continue;
+        }
+        log::info!("Woke up after {}s", gap.as_secs());
+        // Resuming still runs when the pause failed halfway
+        for suspended in [true, false] {
+            if let Err(e) = transition(suspended).await {
+                log::warn!("Failed to bounce tasks after waking up: {}", e.message);
+            }
+        }
+    }
+}
+
+pub async fn watch() -> TauriResult<()> {
+    #[cfg(target_os = "linux")]
+    if let Err(e) = watch_logind().await {
+        log::warn!(
+            "Failed to watch logind, falling back to clock: {}",
+            e.message
+        );
+    }
+    watch_clock().await
+}
+
+struct Battery {
+    level: u8,
+    charging: bool,
+}
+
+/// Batteries and the AC adapter as the kernel lists them, averaging several batteries.
+#[cfg(target_os = "linux")]
+async fn battery() -> Option<Battery> {
+    let read = |path: std::path::PathBuf| async move {
+        tokio::fs::read_to_string(path)
+            .await
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut entries = tokio::fs::read_dir("/sys/class/power_supply").await.ok()?;
+    let (mut levels, mut charging) = (vec![], false);
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        match read(path.join("type")).await.as_str() {
+            "Mains" => charging |= read(path.join("online")).await == "1",
+            "Battery" => {
+                if let Ok(level) = read(path.join("capacity")).await.parse::<u32>() {
+                    levels.push(level);
+                }
+                let status = read(path.join("status")).await;
+                charging |= status == "Charging" || status == "Full";
+            }
+            _ => (),
+        }
+    }
+    if levels.is_empty() {
+        return None;
+    }
+    Some(Battery {
+        level: (levels.iter().sum::<u32>() / levels.len() as u32).min(100) as u8,
+        charging,
+    })
+}
+
+#[cfg(target_os = "windows")]
+async fn battery() -> Option<Battery> {
+    use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+    let mut status = SYSTEM_POWER_STATUS::default();
+    unsafe { GetSystemPowerStatus(&mut status) }.ok()?;
+    // 128 is no system battery, 255 an unknown status or level
+    if status.BatteryFlag & 128 != 0 || status.BatteryLifePercent > 100 {
+        return None;
+    }
+    Some(Battery {
+        level: status.BatteryLifePercent,
+        charging: status.ACLineStatus == 1,
+    })
+}
+
+/// From `pmset -g batt`, e.g. `Now drawing from 'AC Power'` then `-InternalBattery-0
+/// (id=…)	85%; charging; …`.
+#[cfg(target_os = "macos")]
+async fn battery() -> Option<Battery> {
+    use tauri_plugin_shell::ShellExt;
+    let output = get_app_handle()
+        .shell()
+        .command("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .await
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let level = text
+        .split_once('%')?
+        .0
+        .rsplit(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse::<u8>()
+        .ok()?;
+    Some(Battery {
+        level,
+        charging: text.contains("'AC Power'"),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+async fn battery() -> Option<Battery> {
+    None
+}
+
+/// Pauses running tasks while on battery below `pause_on_battery` percent and resumes
+/// them once charging or back above it. Does nothing without a battery to read.
+pub async fn watch_battery() -> TauriResult<()> {
+    let mut paused = false;
+    loop {
+        sleep(BATTERY_INTERVAL).await;
+        let threshold = config::read().pause_on_battery;
+        let battery = match threshold {
+            0 => None,
+            _ => battery().await,
+        };
+        let low = battery
+            .as_ref()
+            .is_some_and(|v| !v.charging && v.level < threshold);
+        if low == paused {
+            continue;
+        }
+        let level = battery.map(|v| v.level);
+        // Left as it was on failure, so the next poll tries again
+        let list = match TASK_MANAGER.battery_pause(low).await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to apply battery low {low}: {e:#}");
+                continue;
+            }
+        };
+        paused = low;
+        log::info!("Battery low: {low} ({level:?}%), affected tasks: {list:?}");
+        let event = QueueEvent::Battery {
+            paused: low,
+            level,
+            list,
+        };
+        if let Err(e) = event.emit(get_app_handle()) {
+            log::warn!("Failed to send battery event: {e}");
+        }
+    }
+}
+
+/// Whether `now` falls inside `hours`, `None` when a time or the offset is invalid.
+/// Without `utc_offset` the system's offset is used, or UTC where it can't be read
+/// from a running process (most Linux systems).
+fn in_quiet(hours: &QuietHours, now: OffsetDateTime) -> Option<bool> {
+    let fmt = format_description!("[hour]:[minute]");
+    let start = Time::parse(hours.start.trim(), fmt).ok()?;
+    let end = Time::parse(hours.end.trim(), fmt).ok()?;
+    let offset = match hours.utc_offset {
+        Some(v) => UtcOffset::from_whole_seconds(v as i32 * 60).ok()?,
+        None => UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC),
+    };
+    let now = now.to_offset(offset).time();
+    Some(match start <= end {
+        true => start <= now && now < end,
+        false => now >= start || now < end,
+    })
+}
+
+/// Pauses running tasks while inside `quiet_hours` and resumes them once the window
+/// ends, while `wait_quiet` holds back tasks about to start. Does nothing while unset.
+/// Other holds ending inside the window leave its tasks paused, see `TaskManager::hold`.
+pub async fn watch_quiet() -> TauriResult<()> {
+    let mut invalid = None;
+    // Whether the running tasks were paused for it, which can lag behind `QUIET`
+    let mut applied = false;
+    loop {
+        let hours = config::read().quiet_hours.clone();
+        let active = hours.as_ref().is_some_and(|v| {
+            in_quiet(v, OffsetDateTime::now_utc()).unwrap_or_else(|| {
+                if invalid.as_ref() != Some(v) {
+                    log::warn!("Ignoring invalid quiet hours: {v:?}");
+                    invalid = Some(v.clone());
+                }
+                false
+            })
+        });
+        QUIET.send_if_modified(|v| std::mem::replace(v, active) != active);
+        if active != applied {
+            match TASK_MANAGER.quiet_pause(active).await {
+                Ok(list) => {
+                    applied = active;
+                    log::info!("Quiet hours: {active}, affected tasks: {list:?}");
+                    let event = QueueEvent::QuietHours { active, list };
+                    if let Err(e) = event.emit(get_app_handle()) {
+                        log::warn!("Failed to send quiet hours event: {e}");
+                    }
+                }
+                Err(e) => log::warn!("Failed to apply quiet hours {active}: {e:#}"),
+            }
+        }
+        sleep(QUIET_INTERVAL).await;
+    }
+}
+
+/// Returns once outside quiet hours, straight away when they're off.
+pub async fn wait_quiet() {
+    let mut rx = QUIET.subscribe();
+    let _ = rx.wait_for(|v| !v).await;
+}
+
+/// Lets the frontend report suspend and resume from platform hooks the backend can't
+/// listen to itself.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn system_sleep(suspended: bool) -> TauriResult<()> {
+    transition(suspended).await
+}
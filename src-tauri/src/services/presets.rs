@@ -0,0 +1,122 @@
+use anyhow::anyhow;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tauri_specta::Event;
+
+use crate::{
+    queue::types::{Task, TaskOverrides},
+    services::control::{normalize_all, queued_keys, ControlSubmit, ImportLine, ImportStatus},
+    shared::{get_app_handle, get_ts},
+    storage::presets::{self, Preset, PresetItem},
+    TauriResult,
+};
+
+// How long the overrides of an applied preset wait for the frontend to submit its tasks
+const PENDING_TTL: Duration = Duration::from_secs(30 * 60);
+
+// Overrides by `MediaId::key`, for the tasks `apply_preset` sent to the frontend
+static PENDING: LazyLock<Mutex<HashMap<String, (Instant, TaskOverrides)>>> =
+    LazyLock::new(Default::default);
+
+/// Overrides an applied preset saved for the media of `task`. Kept until they expire,
+/// as every part of a multi-part video is submitted as its own task.
+pub fn overrides_for(task: &Task) -> Option<TaskOverrides> {
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|_, (at, _)| at.elapsed() < PENDING_TTL);
+    let item = &task.item;
+    [
+        item.bvid.clone(),
+        item.aid.as_ref().map(|v| format!("av{v}")),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|key| pending.get(&key).map(|(_, v)| v.clone()))
+}
+
+/// Saves `items` under `name`, replacing a preset of the same name.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn save_preset(name: String, items: Vec<PresetItem>) -> TauriResult<Preset> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(anyhow!("A preset needs a name").into());
+    }
+    if items.is_empty() {
+        return Err(anyhow!("Preset {name} has no items").into());
+    }
+    let now = get_ts(true);
+    let created_at = presets::get(&name).await?.map_or(now, |v| v.created_at);
+    let preset = Preset {
+        name,
+        items,
+        created_at,
+        updated_at: now,
+    };
+    presets::upsert(&preset).await?;
+    Ok(preset)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn list_presets() -> TauriResult<Vec<Preset>> {
+    Ok(presets::load().await?)
+}
+
+/// Submits every item of preset `name` through [`ControlSubmit`], with its overrides
+/// applied once the frontend submits the tasks. Items already queued or repeated within
+/// the preset are skipped, the same way `import_url_list` reports them.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn apply_preset(name: String) -> TauriResult<Vec<ImportLine>> {
+    let preset = presets::get(&name)
+        .await?
+        .ok_or(anyhow!("No preset found for {name}"))?;
+    let queued = queued_keys().await;
+    let mut seen = HashSet::new();
+    let mut report = vec![];
+    let mut submits = vec![];
+    let ids = normalize_all(preset.items.iter().map(|v| v.source.clone()).collect()).await?;
+    for (index, (item, id)) in preset.items.into_iter().zip(ids).enumerate() {
+        let (status, error) = match id {
+            Ok(id) if queued.contains(&id.key()) || !seen.insert((id.key(), id.page)) => {
+                (ImportStatus::Duplicate, None)
+            }
+            Ok(id) => {
+                submits.push((id.key(), item.source.clone(), item.overrides));
+                (ImportStatus::Queued, None)
+            }
+            Err(e) => (ImportStatus::Invalid, Some(e.message)),
+        };
+        report.push(ImportLine {
+            line: index + 1,
+            input: item.source,
+            status,
+            error,
+        });
+    }
+    log::info!(
+        "Applying preset {name}: {} of {} items",
+        submits.len(),
+        report.len()
+    );
+    for (key, url, overrides) in submits {
+        if !key.is_empty() {
+            PENDING
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key, (Instant::now(), overrides));
+        }
+        ControlSubmit { url }.emit(get_app_handle())?;
+    }
+    Ok(report)
+}
+
+/// Returns whether there was a preset by that name.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn delete_preset(name: String) -> TauriResult<bool> {
+    Ok(presets::delete(&name).await?)
+}
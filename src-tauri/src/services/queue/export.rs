@@ -0,0 +1,166 @@
+use anyhow::anyhow;
+use serde::Serialize;
+use specta::Type;
+use std::sync::Arc;
+use tauri_plugin_http::reqwest::Url;
+
+use crate::{
+    config,
+    queue::{
+        handlers::get_ext,
+        runtime::{request_resolve, RequestAction, TASK_MANAGER},
+        types::{MediaUrls, TaskType},
+    },
+    shared::HEADERS,
+    storage::cookies,
+    TauriResult,
+};
+
+#[cfg(target_os = "windows")]
+const CURL: &str = "curl.exe";
+
+#[cfg(not(target_os = "windows"))]
+const CURL: &str = "curl";
+
+const MASK: &str = "***";
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskCommands {
+    pub curl: Vec<String>,
+    pub ffmpeg: Option<String>,
+}
+
+/// Quotes for PowerShell on Windows and POSIX shells elsewhere.
+fn quote(arg: &str) -> String {
+    #[cfg(target_os = "windows")]
+    {
+        format!("'{}'", arg.replace('\'', "''"))
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let safe = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c));
+        if safe {
+            arg.to_string()
+        } else {
+            format!("'{}'", arg.replace('\'', r"'\''"))
+        }
+    }
+}
+
+pub(crate) fn join(args: &[String]) -> String {
+    args.iter().map(|v| quote(v)).collect::<Vec<_>>().join(" ")
+}
+
+pub(crate) fn mask_cookie(cookie: &str) -> String {
+    cookie
+        .split("; ")
+        .filter(|v| !v.is_empty())
+        .map(|v| match v.split_once('=') {
+            Some((name, _)) => format!("{name}={MASK}"),
+            None => v.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn file_name(url: &str) -> TauriResult<String> {
+    let url = Url::parse(url)?;
+    Ok(url
+        .path_segments()
+        .and_then(|mut v| v.next_back())
+        .filter(|v| !v.is_empty())
+        .ok_or(anyhow!("Failed to get file name: {url:?}"))?
+        .to_string())
+}
+
+async fn curl(url: &str, mask: bool) -> TauriResult<String> {
+    let mut args = vec![CURL.to_string(), "-L".into()];
+    for (name, value) in HEADERS.to_header_map().await?.iter() {
+        let value = value.to_str().unwrap_or_default();
+        if value.is_empty() {
+            continue;
+        }
+        args.push("-H".into());
+        args.push(format!("{name}: {value}"));
+    }
+    let cookie = cookies::cookies_for(url).await?;
+    if !cookie.is_empty() {
+        args.push("-H".into());
+        args.push(format!(
+            "Cookie: {}",
+            if mask { mask_cookie(&cookie) } else { cookie }
+        ));
+    }
+    let proxy = &config::read().proxy;
+    if !proxy.address.is_empty() {
+        args.push("-x".into());
+        args.push(proxy.address.clone());
+        if !proxy.username.is_empty() {
+            args.push("-U".into());
+            args.push(if mask {
+                format!("{}:{MASK}", proxy.username)
+            } else {
+                format!("{}:{}", proxy.username, proxy.password)
+            });
+        }
+    }
+    args.push("-o".into());
+    args.push(file_name(url)?);
+    args.push(url.into());
+    Ok(join(&args))
+}
+
+fn ffmpeg(urls: &MediaUrls, title: &str, abr: usize) -> TauriResult<Option<String>> {
+    let (Some(video), Some(audio)) = (
+        urls.video_urls.as_ref().and_then(|v| v.first()),
+        urls.audio_urls.as_ref().and_then(|v| v.first()),
+    ) else {
+        return Ok(None);
+    };
+    let ext = get_ext(TaskType::AudioVideo, abr);
+    let mut args = vec![
+        "ffmpeg".to_string(),
+        "-i".into(),
+        file_name(video)?,
+        "-i".into(),
+        file_name(audio)?,
+        "-c".into(),
+        "copy".into(),
+        "-shortest".into(),
+    ];
+    if ext == "mp4" {
+        args.push("-movflags".into());
+        args.push("+faststart".into());
+    }
+    let title = title.replace(['\\', '/', ':', '*', '?', '"', '<', '>', '|'], "_");
+    args.push(format!("{title}.{ext}"));
+    Ok(Some(join(&args)))
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn export_commands(id: Arc<String>, mask: bool) -> TauriResult<TaskCommands> {
+    let task = TASK_MANAGER
+        .get_task(&id)
+        .await
+        .ok_or(anyhow!("No task found for {id}"))?;
+    let task = task.read().await.clone();
+    let urls = request_resolve::<MediaUrls>(id.clone(), None, RequestAction::RefreshUrls).await?;
+
+    let mut curl_list = vec![];
+    for url in [&urls.video_urls, &urls.audio_urls]
+        .into_iter()
+        .filter_map(|v| v.as_ref().and_then(|v| v.first()))
+    {
+        curl_list.push(curl(url, mask).await?);
+    }
+    let ffmpeg = ffmpeg(&urls, &task.item.title, task.select.abr.unwrap_or(0))?;
+    Ok(TaskCommands {
+        curl: curl_list,
+        ffmpeg,
+    })
+}
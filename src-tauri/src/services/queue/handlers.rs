@@ -1,23 +1,41 @@
 use anyhow::{anyhow, Context};
+use serde_json::json;
 use std::{
+    future::Future,
     path::{Path, PathBuf},
     pin::pin,
     sync::Arc,
 };
 use tokio::{
     fs,
-    sync::{broadcast::Receiver, oneshot, OnceCell, RwLock},
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        oneshot, OnceCell, RwLock,
+    },
+    time::Instant,
 };
 
 use tauri_plugin_shell::{process::CommandEvent, ShellExt};
+use tauri_specta::Event;
 
 use crate::{
     aria2c, config, ffmpeg,
     queue::{
-        runtime::{request_frontend, CtrlEvent, Progress, RequestAction, Scheduler},
-        types::{MediaNfo, MediaNfoThumb, MediaUrls, SubTask, Task, TaskType},
+        post::{self, TaskOutput},
+        runtime::{
+            request_frontend, request_resolve, CtrlEvent, Progress, QueueEvent, RequestAction,
+            Scheduler, TASK_MANAGER,
+        },
+        types::{
+            MediaNfo, MediaNfoThumb, MediaUrls, Phase, Prefetched, SubTask, Task, TaskState,
+            TaskType,
+        },
+    },
+    services::{audio, bili, naming},
+    shared::{
+        check_dir, get_app_handle, get_image, get_ts, get_unique_path, move_file, place_file,
+        FfmpegFallback, StreamOrder, WORKING_PATH,
     },
-    shared::{get_app_handle, get_image, get_unique_path, WORKING_PATH},
     TauriError, TauriResult,
 };
 
@@ -27,9 +45,11 @@ struct ProgressTask {
     urls: Option<Arc<MediaUrls>>,
     folder: Arc<PathBuf>,
     filename: Arc<String>,
+    /// Staging root the subtasks download and convert in, see `naming::temp_root`.
+    temp: Arc<PathBuf>,
 }
 
-fn get_ext(task_type: TaskType, abr: usize) -> &'static str {
+pub(super) fn get_ext(task_type: TaskType, abr: usize) -> &'static str {
     match task_type {
         TaskType::Audio => {
             if abr == 30250 {
@@ -51,7 +71,25 @@ fn get_ext(task_type: TaskType, abr: usize) -> &'static str {
     }
 }
 
-async fn handle_opus_images(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> TauriResult<()> {
+/// Drops Bilibili's `@...` resize/format suffix so the image comes back as uploaded,
+/// returning the url and its extension.
+fn original_image(url: &str) -> (String, String) {
+    let url = match url.rsplit_once('/') {
+        Some((dir, name)) => match name.split_once('@') {
+            Some((name, _)) => format!("{dir}/{name}"),
+            None => url.to_string(),
+        },
+        None => url.to_string(),
+    };
+    let ext = Path::new(url.split(['?', '#']).next().unwrap_or_default())
+        .extension()
+        .map(|v| v.to_string_lossy().to_lowercase())
+        .filter(|v| ["jpg", "jpeg", "png", "gif", "webp", "avif"].contains(&v.as_str()))
+        .unwrap_or("jpg".into());
+    (url, ext)
+}
+
+async fn handle_opus_images(ptask: &ProgressTask, _rx: Receiver<CtrlEvent>) -> TauriResult<()> {
     let subtask = &ptask.subtask;
     let parent = ptask.task.id.clone();
     let id = subtask.id.clone();
@@ -63,16 +101,20 @@ async fn handle_opus_images(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -
         request_frontend::<Vec<String>>(parent, Some(id), RequestAction::GetOpusImages).await?;
 
     let content = thumbs.len() as u64;
+    let token = prog.token().await;
+    // Zero-padded so file managers keep the gallery order
+    let width = thumbs.len().saturating_sub(1).to_string().len();
 
     for (index, thumb) in thumbs.iter().enumerate() {
-        if let Ok(CtrlEvent::Cancel) = rx.try_recv() {
+        if token.is_cancelled() {
             return Ok(());
         }
-        let url = format!("{}@.jpg", thumb);
+        let (url, ext) = original_image(thumb);
         let path = get_unique_path(
+            &ptask.task.id,
             ptask
                 .folder
-                .join(format!("{}.{}.jpg", &ptask.filename, index)),
+                .join(format!("{}.{index:0width$}.{ext}", &ptask.filename)),
         );
         get_image(&path, &url).await?;
         prog.send(content, index as u64).await?;
@@ -94,7 +136,10 @@ async fn handle_opus_content(ptask: &ProgressTask, _rx: Receiver<CtrlEvent>) ->
     let result =
         request_frontend::<Vec<u8>>(parent, Some(id), RequestAction::GetOpusContent).await?;
 
-    let output_file = get_unique_path(ptask.folder.join(format!("{}.md", &ptask.filename)));
+    let output_file = get_unique_path(
+        &ptask.task.id,
+        ptask.folder.join(format!("{}.md", &ptask.filename)),
+    );
     fs::write(&output_file, &*result).await?;
 
     prog.send(1, 1).await?;
@@ -119,8 +164,12 @@ async fn handle_subtitle(ptask: &ProgressTask, _rx: Receiver<CtrlEvent>) -> Taur
         .subtitles
         .as_str()
         .ok_or(anyhow!("No subtitle lang found"))?;
-    let output_file = get_unique_path(ptask.folder.join(format!("{}.{lang}.srt", &ptask.filename)));
-    fs::write(&output_file, &*result).await?;
+    let output_file = get_unique_path(
+        &ptask.task.id,
+        ptask.folder.join(format!("{}.{lang}.srt", &ptask.filename)),
+    );
+    let result = config::read().subtitle_encoding.encode(&result);
+    fs::write(&output_file, result).await?;
 
     prog.send(1, 1).await?;
     Ok(())
@@ -136,42 +185,51 @@ async fn handle_ai_summary(ptask: &ProgressTask, _rx: Receiver<CtrlEvent>) -> Ta
 
     let result = request_frontend::<Vec<u8>>(parent, Some(id), RequestAction::GetAISummary).await?;
 
-    let output_file = get_unique_path(ptask.folder.join(format!("{}.md", &ptask.filename)));
+    let output_file = get_unique_path(
+        &ptask.task.id,
+        ptask.folder.join(format!("{}.md", &ptask.filename)),
+    );
     fs::write(&output_file, &*result).await?;
 
     prog.send(1, 1).await?;
     Ok(())
 }
 
-async fn handle_nfo(
-    ptask: &ProgressTask,
-    _rx: Receiver<CtrlEvent>,
-    folder: &Path,
-    nfo: &Arc<MediaNfo>,
-) -> TauriResult<()> {
-    let subtask = &ptask.subtask;
-    let parent = ptask.task.id.clone();
-    let id = subtask.id.clone();
-
-    let prog = Progress::new(parent.clone(), id.clone());
-    prog.send(1, 0).await?;
-
-    let data = request_frontend::<Vec<u8>>(parent, Some(id), RequestAction::GetNfo).await?;
-
-    let output_file = if subtask.task_type == TaskType::AlbumNfo {
-        folder.join("tvshow.nfo")
-    } else {
-        ptask.folder.join(format!("{}.nfo", &ptask.filename))
+/// Writes the NFO subtasks of a task whose media is in place, the album's next to its
+/// series folder.
+pub async fn write_nfo(out: &TaskOutput) -> TauriResult<()> {
+    let Some(filename) = &out.filename else {
+        return Ok(());
     };
-    fs::write(&output_file, &*data).await?;
+    let parent = &out.task.id;
+    let nfos = out
+        .task
+        .subtasks
+        .iter()
+        .filter(|v| matches!(v.task_type, TaskType::AlbumNfo | TaskType::SingleNfo));
+    for subtask in nfos {
+        let id = subtask.id.clone();
+        let prog = Progress::new(parent.clone(), id.clone());
+        prog.send(1, 0).await?;
+
+        let data =
+            request_frontend::<Vec<u8>>(parent.clone(), Some(id), RequestAction::GetNfo).await?;
+
+        let output_file = if subtask.task_type == TaskType::AlbumNfo {
+            out.root.join("tvshow.nfo")
+        } else {
+            out.folder.join(format!("{filename}.nfo"))
+        };
+        fs::write(&output_file, &*data).await?;
 
-    if subtask.task_type == TaskType::AlbumNfo {
-        let path = folder.join("poster.jpg");
-        let url = format!("{}@.jpg", nfo.thumbs[0].url);
-        get_image(&path, &url).await?;
-    }
+        if subtask.task_type == TaskType::AlbumNfo {
+            let path = out.root.join("poster.jpg");
+            let url = format!("{}@.jpg", out.task.nfo.thumbs[0].url);
+            get_image(&path, &url).await?;
+        }
 
-    prog.send(1, 1).await?;
+        prog.send(1, 1).await?;
+    }
     Ok(())
 }
 
@@ -186,8 +244,7 @@ async fn handle_danmaku(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> Ta
     let danmaku =
         request_frontend::<Vec<u8>>(parent, Some(id.clone()), RequestAction::GetDanmaku).await?;
 
-    let temp_root = config::read().temp_dir();
-    let temp_dir = temp_root.join(&*id);
+    let temp_dir = ptask.temp.join(&*id);
     fs::create_dir_all(&temp_dir).await?;
 
     let xml = temp_dir.join("raw.xml");
@@ -200,7 +257,7 @@ async fn handle_danmaku(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> Ta
     if !config::read().convert.danmaku {
         fs::copy(
             &xml,
-            get_unique_path(PathBuf::from(format!("{output_file}.xml"))),
+            get_unique_path(&ptask.task.id, PathBuf::from(format!("{output_file}.xml"))),
         )
         .await?;
         return Ok(());
@@ -270,14 +327,15 @@ async fn handle_danmaku(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> Ta
         }
     }
 
-    if !ass.exists() {
+    let ass = match fs::read(&ass).await {
+        Ok(v) => config::read().subtitle_encoding.encode(&v),
         // no elems
-        fs::write(&ass, &[]).await?;
-    }
+        Err(_) => vec![],
+    };
 
-    fs::copy(
-        &ass,
-        get_unique_path(PathBuf::from(format!("{output_file}.ass"))),
+    fs::write(
+        get_unique_path(&ptask.task.id, PathBuf::from(format!("{output_file}.ass"))),
+        ass,
     )
     .await?;
     fs::remove_file(&xml).await?;
@@ -285,7 +343,7 @@ async fn handle_danmaku(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> Ta
     Ok(())
 }
 
-async fn handle_thumbs(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> TauriResult<()> {
+async fn handle_thumbs(ptask: &ProgressTask, _rx: Receiver<CtrlEvent>) -> TauriResult<()> {
     let subtask = &ptask.subtask;
     let parent = ptask.task.id.clone();
     let id = subtask.id.clone();
@@ -297,13 +355,15 @@ async fn handle_thumbs(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> Tau
         request_frontend::<Vec<MediaNfoThumb>>(parent, Some(id), RequestAction::GetThumbs).await?;
 
     let content = thumbs.len() as u64;
+    let token = prog.token().await;
 
     for (index, thumb) in thumbs.iter().enumerate() {
-        if let Ok(CtrlEvent::Cancel) = rx.try_recv() {
+        if token.is_cancelled() {
             return Ok(());
         }
         let url = format!("{}@.jpg", thumb.url);
         let path = get_unique_path(
+            &ptask.task.id,
             ptask
                 .folder
                 .join(format!("{}.{}.jpg", &ptask.filename, thumb.id)),
@@ -317,6 +377,104 @@ async fn handle_thumbs(ptask: &ProgressTask, mut rx: Receiver<CtrlEvent>) -> Tau
     Ok(())
 }
 
+/// Where the muxed file of this task ends up, before `existing_file` is applied.
+fn merged_path(ptask: &ProgressTask) -> PathBuf {
+    let abr = ptask.task.select.abr.unwrap_or(0);
+    let ext = get_ext(TaskType::AudioVideo, abr);
+    ptask.folder.join(format!("{}.{}", &ptask.filename, ext))
+}
+
+/// Runs `fut`, adding the time it took to the `timings` of task `id` under `phase`.
+async fn timed<T>(id: &Arc<String>, phase: Phase, fut: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let result = fut.await;
+    if let Some(task) = TASK_MANAGER.get_task(id).await {
+        task.write().await.timings.add(phase, started.elapsed());
+    }
+    result
+}
+
+/// Remembers where the task's main file went for `reveal_file`. The muxed file wins over
+/// its streams, and video over audio.
+async fn record_output(ptask: &ProgressTask, path: &Path) {
+    let Some(task) = TASK_MANAGER.get_task(&ptask.task.id).await else {
+        return;
+    };
+    let mut task = task.write().await;
+    let replace = match ptask.subtask.task_type {
+        TaskType::AudioVideo => true,
+        _ if ptask.task.select.media.audio_video => false,
+        TaskType::Video => true,
+        _ => task.output.is_none(),
+    };
+    if replace {
+        task.output = Some(path.to_path_buf());
+    }
+}
+
+/// Marks the subtask complete without running it if every output it feeds is already
+/// on disk and `existing_file` says to keep them.
+async fn skip_existing(
+    ptask: &ProgressTask,
+    prog: &Progress,
+    outputs: &[PathBuf],
+) -> TauriResult<bool> {
+    let policy = config::read().existing_file.clone();
+    if outputs.is_empty() || !outputs.iter().all(|v| policy.skips(v)) {
+        return Ok(false);
+    }
+    let id = ptask.subtask.id.clone();
+    log::info!("Skipping {id}, already exists: {}", outputs[0].display());
+    QueueEvent::Skipped {
+        parent: ptask.task.id.clone(),
+        id,
+        path: outputs[0].clone(),
+    }
+    .emit(get_app_handle())?;
+    if let Some(path) = outputs.last() {
+        record_output(ptask, path).await;
+    }
+    prog.send(1, 1).await?;
+    Ok(true)
+}
+
+/// Generates the storyboard of the task's placed video.
+pub async fn write_storyboard(out: &TaskOutput) -> TauriResult<()> {
+    let Some(video) = &out.output else {
+        return Ok(());
+    };
+    let interval = config::read().storyboard_interval;
+    let _post = TASK_MANAGER.post.acquire().await?;
+    ffmpeg::storyboard(out.task.id.clone(), video.clone(), interval).await
+}
+
+/// Cuts the task's muxed video into chapters. Videos without chapters keep the single
+/// file.
+pub async fn split_chapters(out: &TaskOutput) -> TauriResult<()> {
+    let item = &out.task.item;
+    let (Some(video), Some(aid), Some(cid)) = (&out.output, item.aid.clone(), item.cid) else {
+        return Ok(());
+    };
+    let chapters = bili::get_chapters(&aid.to_string(), cid).await?;
+    if chapters.is_empty() {
+        return Ok(());
+    }
+    let mode = config::read().chapter_split.clone();
+    let _post = TASK_MANAGER.post.acquire().await?;
+    ffmpeg::split_chapters(out.task.id.clone(), video.clone(), chapters, mode).await
+}
+
+/// Returns once `rx` carries a cancel, past any pause, resume or lag before it.
+async fn cancelled(rx: &mut Receiver<CtrlEvent>) {
+    loop {
+        match rx.recv().await {
+            Ok(CtrlEvent::Cancel) => return,
+            Err(RecvError::Closed) => std::future::pending().await,
+            _ => (),
+        }
+    }
+}
+
 async fn handle_merge(
     ptask: &ProgressTask,
     mut rx: Receiver<CtrlEvent>,
@@ -326,37 +484,63 @@ async fn handle_merge(
     let subtask = &ptask.subtask;
     let parent = ptask.task.id.clone();
     let id = subtask.id.clone();
+    let prog = Progress::new(parent.clone(), id.clone());
+
+    let target = merged_path(ptask);
+    if skip_existing(ptask, &prog, &[target.clone()]).await? {
+        return Ok(());
+    }
 
     let video = video_path.get().ok_or(anyhow!("No path for video found"))?;
     let audio = audio_path.get().ok_or(anyhow!("No path for audio found"))?;
-
-    let prog = Progress::new(parent.clone(), id.clone());
     let (cancel_tx, cancel_rx) = oneshot::channel();
+    let _post = tokio::select! {
+        res = TASK_MANAGER.post.acquire() => res?,
+        _ = cancelled(&mut rx) => return Ok(()),
+    };
 
     let abr = ptask.task.select.abr.unwrap_or(0);
     let ext = get_ext(subtask.task_type.clone(), abr);
 
-    let mut merge = pin!(ffmpeg::merge(
-        id.clone(),
-        ext,
-        &prog,
-        cancel_rx,
-        video,
-        audio,
+    let mut merge = pin!(timed(
+        &parent,
+        Phase::Merge,
+        ffmpeg::merge(id.clone(), ext, &prog, cancel_rx, &ptask.temp, video, audio)
     ));
     let path = tokio::select! {
         res = &mut merge => res,
-        Ok(CtrlEvent::Cancel) = rx.recv() => {
+        _ = cancelled(&mut rx) => {
             let _ = cancel_tx.send(());
             return Ok(());
         }
     }?;
+    let path = match ptask.task.clip {
+        Some(clip) => {
+            let cut = timed(
+                &parent,
+                Phase::Convert,
+                ffmpeg::cut_clip(&parent, &path, clip),
+            )
+            .await?;
+            fs::remove_file(&path).await?;
+            cut
+        }
+        None => path,
+    };
 
-    let output_file = get_unique_path(ptask.folder.join(format!("{}.{}", &ptask.filename, ext)));
+    let output_file = config::read().existing_file.target(&ptask.task.id, target);
 
-    fs::copy(&path, output_file).await?;
-    fs::remove_file(path).await?;
+    timed(&parent, Phase::Move, move_file(&path, &output_file)).await?;
+    record_output(ptask, &output_file).await;
 
+    if config::read().keep_intermediates {
+        log::info!(
+            "Keeping intermediates of {id}: {}, {}",
+            video.display(),
+            audio.display()
+        );
+        return Ok(());
+    }
     fs::remove_file(video).await?;
     fs::remove_file(audio).await?;
     Ok(())
@@ -388,7 +572,31 @@ async fn handle_media(
 
     let prog = Progress::new(parent.clone(), id.clone());
 
-    let mut download = pin!(aria2c::download(id.clone(), &prog, urls));
+    let abr = ptask.task.select.abr.unwrap_or(0);
+    let mut ext = get_ext(subtask.task_type.clone(), abr).to_string();
+
+    // Streams only kept as merge input are skipped when the merged file is there
+    let mut outputs = vec![];
+    let own = if subtask.task_type == TaskType::Video {
+        select.media.video
+    } else {
+        select.media.audio
+    };
+    if own {
+        outputs.push(ptask.folder.join(&*ptask.filename).with_extension(&ext));
+    }
+    if select.media.audio_video {
+        outputs.push(merged_path(ptask));
+    }
+    if skip_existing(ptask, &prog, &outputs).await? {
+        return Ok(());
+    }
+
+    let mut download = pin!(timed(
+        &parent,
+        Phase::Download,
+        aria2c::download(id.clone(), &ptask.temp, &prog, urls)
+    ));
     let mut path = loop {
         tokio::select! {
             res = &mut download => break res,
@@ -408,9 +616,6 @@ async fn handle_media(
         }
     }?;
 
-    let abr = ptask.task.select.abr.unwrap_or(0);
-    let mut ext = get_ext(subtask.task_type.clone(), abr).to_string();
-
     if subtask.task_type == TaskType::Video {
         video_path
     } else if subtask.task_type == TaskType::Audio {
@@ -420,26 +625,170 @@ async fn handle_media(
     }
     .set(path.clone())?;
 
-    if subtask.task_type == TaskType::Audio {
-        let (file, suffix) =
-            ffmpeg::convert_audio(id, &ext, &prog, &path, ptask.task.clone()).await?;
-        path = file.with_extension(&suffix);
+    if let Ok(meta) = fs::metadata(&path).await {
+        if let Some(task) = TASK_MANAGER.get_task(&parent).await {
+            task.write().await.timings.bytes += meta.len();
+        }
+    }
+
+    if subtask.task_type == TaskType::Audio && ffmpeg::available() {
+        // Keeps the download slot, the other stream may still be downloading
+        let _post = TASK_MANAGER.post.acquire().await?;
+        let convert =
+            ffmpeg::convert_audio(id, &ext, &prog, &ptask.temp, &path, ptask.task.clone());
+        let (file, suffix) = timed(&parent, Phase::Convert, convert).await?;
+        let converted = file.with_extension(&suffix);
+        // Without mp3 it's a stream copy, and a merge still reads the original
+        if config::read().convert.mp3 && !select.media.audio_video {
+            ffmpeg::remove_source(&path, &converted).await;
+        }
+        path = converted;
         ext = suffix;
     }
 
     if select.media.video || select.media.audio {
-        fs::copy(
-            &path,
-            &ptask.folder.join(&*ptask.filename).with_extension(ext),
-        )
-        .await?;
+        let output_file = config::read().existing_file.target(
+            &ptask.task.id,
+            ptask.folder.join(&*ptask.filename).with_extension(ext),
+        );
+        // The stream itself stays whole, the merge cuts its own output
+        let place = async {
+            match ptask.task.clip {
+                Some(clip) => {
+                    let _post = TASK_MANAGER.post.acquire().await?;
+                    let cut = timed(
+                        &parent,
+                        Phase::Convert,
+                        ffmpeg::cut_clip(&parent, &path, clip),
+                    )
+                    .await?;
+                    move_file(&cut, &output_file).await
+                }
+                // Still needed as merge input
+                None if select.media.audio_video => place_file(&path, &output_file).await,
+                None => move_file(&path, &output_file).await,
+            }
+        };
+        timed(&parent, Phase::Move, place).await?;
+        record_output(ptask, &output_file).await;
+        let item = &ptask.task.item;
+        if subtask.task_type == TaskType::Audio
+            && item.media_type == "music"
+            && config::read().audio_lyrics
+        {
+            if let Some(sid) = item.sid {
+                if let Err(e) = audio::save_lyrics(sid as u64, &output_file).await {
+                    log::warn!("Failed to save lyrics of au{sid}: {e}");
+                }
+            }
+        }
     }
-    if !select.media.audio_video {
+    if !select.media.audio_video && fs::try_exists(&path).await? {
         fs::remove_file(&path).await?;
     }
     Ok(())
 }
 
+pub async fn handle_info_json(out: &TaskOutput) -> TauriResult<()> {
+    let (task, urls, folder) = (&out.task, out.urls.as_deref(), &out.folder);
+    let Some(filename) = &out.filename else {
+        return Ok(());
+    };
+    let info = match &task.item.bvid {
+        Some(bvid) => bili::get_video_info(bvid.clone())
+            .await
+            .map_err(|e| log::warn!("Failed to fetch video info for {bvid}: {e}"))
+            .ok(),
+        None => None,
+    };
+    let select = &task.select;
+    let data = json!({
+        "id": task.id,
+        "type": task.media_type,
+        "item": task.item,
+        "nfo": task.nfo,
+        "info": info,
+        "stream": {
+            "res": select.res,
+            "abr": select.abr,
+            "enc": select.enc,
+            "fmt": select.fmt,
+            "videoUrls": urls.and_then(|v| v.video_urls.as_ref()),
+            "audioUrls": urls.and_then(|v| v.audio_urls.as_ref()),
+        },
+        "select": select,
+        "subtasks": task.subtasks,
+        "downloadedAt": get_ts(false),
+    });
+    let output_file = get_unique_path(&out.task.id, folder.join(format!("{filename}.info.json")));
+    fs::write(&output_file, serde_json::to_string_pretty(&data)?).await?;
+    Ok(())
+}
+
+/// Writes `{folder name}.m3u8` listing the completed tasks placed under `folder` in
+/// list order, with paths relative to it. Failed or missing files are left out, and
+/// it is rewritten whenever the members change.
+pub async fn write_playlist(folder: &Path) -> TauriResult<()> {
+    let mut members = vec![];
+    for lock in TASK_MANAGER.tasks.read().await.values() {
+        let task = lock.read().await;
+        let Some(output) = &task.output else {
+            continue;
+        };
+        if task.state != TaskState::Completed || !output.starts_with(folder) {
+            continue;
+        }
+        if !fs::try_exists(output).await.unwrap_or(false) {
+            continue;
+        }
+        members.push((task.item.index, task.seq, task.item.clone(), output.clone()));
+    }
+    // A single file isn't a collection
+    if members.len() < 2 {
+        return Ok(());
+    }
+    members.sort_by_key(|v| (v.0, v.1));
+    let name = folder
+        .file_name()
+        .ok_or(anyhow!("No folder name for {}", folder.display()))?
+        .to_string_lossy();
+    let path = folder.join(format!("{name}.m3u8"));
+    let mut content = String::from("#EXTM3U\n");
+    for (_, _, item, output) in members {
+        let relative = output.strip_prefix(folder).unwrap_or(&output);
+        let duration = item.duration.as_f64().unwrap_or(-1.0).round() as i64;
+        content += &format!(
+            "#EXTINF:{duration},{}\n{}\n",
+            item.title,
+            relative.to_string_lossy().replace('\\', "/")
+        );
+    }
+    fs::write(&path, content).await?;
+    log::info!("Wrote playlist {}", path.display());
+    Ok(())
+}
+
+// Bilibili's stream urls carry a deadline of about two hours
+const PREFETCH_TTL: i64 = 100 * 60;
+
+/// Asks the frontend for the task's metadata, stream urls when `with_urls`, and folder.
+pub async fn resolve_media(id: &Arc<String>, with_urls: bool) -> TauriResult<Prefetched> {
+    let nfo = request_resolve::<MediaNfo>(id.clone(), None, RequestAction::RefreshNfo).await?;
+    let urls = match with_urls {
+        true => {
+            Some(request_resolve::<MediaUrls>(id.clone(), None, RequestAction::RefreshUrls).await?)
+        }
+        false => None,
+    };
+    let folder = request_resolve::<String>(id.clone(), None, RequestAction::RefreshFolder).await?;
+    Ok(Prefetched {
+        nfo,
+        urls,
+        folder,
+        ts: get_ts(true),
+    })
+}
+
 pub async fn handle_task(scheduler: Arc<Scheduler>, task: Arc<RwLock<Task>>) -> TauriResult<()> {
     let temp_root = config::read().temp_dir();
     fs::create_dir_all(&temp_root)
@@ -450,70 +799,180 @@ pub async fn handle_task(scheduler: Arc<Scheduler>, task: Arc<RwLock<Task>>) ->
     let id = task_snapshot.id.clone();
     let select = task_snapshot.select.clone();
 
-    let nfo = request_frontend::<MediaNfo>(id.clone(), None, RequestAction::RefreshNfo).await?;
+    let remaining = task_snapshot
+        .status
+        .values()
+        .map(|v| v.content.saturating_sub(v.chunk))
+        .sum::<u64>();
+    // Staged next to the output the temp dir isn't used, unless that falls back to it
+    if !config::read().temp_near_output {
+        check_dir(&temp_root, "temp_dir", remaining).await?;
+    }
+    check_dir(&config::read().down_dir, "down_dir", remaining).await?;
+
+    let cached = task_snapshot
+        .prefetched
+        .clone()
+        .filter(|v| get_ts(true) - v.ts < PREFETCH_TTL);
+    let resolved = match cached {
+        Some(v) => {
+            log::info!("Using urls of {id} prefetched at {}", v.ts);
+            v
+        }
+        None => {
+            timed(
+                &id,
+                Phase::Resolve,
+                resolve_media(&id, select.media.any_true()),
+            )
+            .await?
+        }
+    };
     let mut guard = task.write().await;
-    guard.nfo = nfo;
-
-    let urls = if select.media.any_true() {
-        let urls =
-            request_frontend::<MediaUrls>(id.clone(), None, RequestAction::RefreshUrls).await?;
+    // Used once, a retry after this attempt resolves again
+    guard.prefetched = None;
+    guard.nfo = resolved.nfo;
+    let urls = resolved.urls;
+    if let Some(urls) = &urls {
         guard.subtasks = urls.subtasks.clone();
-        Some(urls)
-    } else {
-        None
-    };
+    }
 
-    let sub_folder =
-        request_frontend::<String>(id.clone(), None, RequestAction::RefreshFolder).await?;
+    let album = naming::album(&task_snapshot);
+    let sub_folder = match &album {
+        Some(album) => album.title.clone(),
+        None => naming::apply(&task_snapshot, &resolved.folder),
+    };
+    let sub_folder = naming::fit_folder(&scheduler.folder, &sub_folder);
 
-    let folder = Arc::new(if config::read().organize.sub_folder {
+    // Tracks of an album always share its folder
+    let folder = Arc::new(if album.is_some() || config::read().organize.sub_folder {
         scheduler.folder.join(&*sub_folder)
     } else {
         scheduler.folder.clone()
     });
     guard.folder = folder.clone();
+    let temp = Arc::new(naming::temp_root(&folder).await);
 
     drop(task_snapshot);
     drop(guard);
 
-    let task = Arc::new(task.read().await.clone());
+    let mut task = task.read().await.clone();
+    // Without FFmpeg the streams are kept as downloaded instead of muxed
+    let unmuxed = task.select.media.audio_video && !ffmpeg::available();
+    if unmuxed {
+        if config::read().ffmpeg_fallback == FfmpegFallback::Fail {
+            return Err(anyhow!("FFmpeg is unavailable, can't mux {id}").into());
+        }
+        log::warn!("FFmpeg is unavailable, keeping the streams of {id} unmuxed");
+        QueueEvent::Unmuxed { parent: id.clone() }.emit(get_app_handle())?;
+        task.select.media.audio_video = false;
+        task.select.media.video = true;
+        task.select.media.audio = true;
+    }
+    let task = Arc::new(task);
     fs::create_dir_all(&*folder)
         .await
         .context("Failed to create output folder")?;
 
     let video_path = Arc::new(OnceCell::new());
     let audio_path = Arc::new(OnceCell::new());
+    let mut info_name = None;
+
+    let prepare = |subtask: Arc<SubTask>| {
+        let (task, urls, temp) = (task.clone(), urls.clone(), temp.clone());
+        let folder: Arc<PathBuf> = folder.clone();
+        async move {
+            log::info!(
+                "Handling subtask: {}\n    task_type: {:?}\n    parent: {}",
+                subtask.id,
+                subtask.task_type,
+                task.id
+            );
+            let filename = match naming::album(&task) {
+                Some(album) => album.file,
+                None => {
+                    let filename = request_frontend::<String>(
+                        task.id.clone(),
+                        Some(subtask.id.clone()),
+                        RequestAction::GetFilename,
+                    )
+                    .await?;
+                    naming::apply(&task, &filename)
+                }
+            };
+            let filename = naming::apply_clip(&task, &filename);
+            let filename = Arc::new(naming::fit_file(&folder, &filename));
+            TauriResult::Ok(ProgressTask {
+                task,
+                subtask,
+                urls,
+                folder,
+                filename,
+                temp,
+            })
+        }
+    };
 
-    for subtask in task.subtasks.iter() {
-        let sub_id = subtask.id.clone();
-        log::info!(
-            "Handling subtask: {sub_id}\n    task_type: {:?}\n    parent: {id}",
-            subtask.task_type
-        );
+    let order = config::read().stream_order;
+    let is_media = |v: &Arc<SubTask>| matches!(v.task_type, TaskType::Video | TaskType::Audio);
+    let is_nfo = |v: &Arc<SubTask>| matches!(v.task_type, TaskType::AlbumNfo | TaskType::SingleNfo);
+    // Left to the NFO post-processor, so a failed one doesn't fail the media with it
+    let (nfos, mut subtasks): (Vec<_>, Vec<_>) = task.subtasks.iter().cloned().partition(is_nfo);
+    let position = |t: TaskType| subtasks.iter().position(|v| v.task_type == t);
+    if let (Some(v), Some(a)) = (position(TaskType::Video), position(TaskType::Audio)) {
+        // Video first, it's usually the larger of the two
+        if order == StreamOrder::Sequential && a < v {
+            subtasks.swap(a, v);
+        }
+    }
+    // Muxing goes after every download, so the download slot is handed off right before it
+    if let Some(i) = subtasks
+        .iter()
+        .position(|v| v.task_type == TaskType::AudioVideo)
+    {
+        let mux = subtasks.remove(i);
+        subtasks.push(mux);
+    }
+    let mut joined = None;
 
-        let filename = request_frontend::<String>(
-            id.clone(),
-            Some(sub_id.clone()),
-            RequestAction::GetFilename,
-        )
-        .await?;
-        let ptask = ProgressTask {
-            task: task.clone(),
-            subtask: subtask.clone(),
-            urls: urls.clone(),
-            folder: folder.clone(),
-            filename,
-        };
-        let folder = scheduler.folder.clone();
+    for subtask in subtasks.iter() {
+        let sub_id = subtask.id.clone();
+        if joined.as_ref() == Some(&sub_id) {
+            continue;
+        }
+        let ptask = prepare(subtask.clone()).await?;
+        info_name.get_or_insert_with(|| ptask.filename.clone());
         let video = video_path.clone();
         let audio = audio_path.clone();
         match subtask.task_type {
             TaskType::Video | TaskType::Audio => {
-                scheduler
-                    .try_join(&id, &sub_id, |rx| handle_media(&ptask, rx, &video, &audio))
-                    .await?;
+                let other = subtasks
+                    .iter()
+                    .find(|v| is_media(v) && v.id != sub_id)
+                    .filter(|_| order == StreamOrder::Parallel);
+                let Some(other) = other else {
+                    scheduler
+                        .try_join(&id, &sub_id, |rx| handle_media(&ptask, rx, &video, &audio))
+                        .await?;
+                    continue;
+                };
+                // Each stream takes its own share of `CONNECTIONS`, so running both at
+                // once stays within the connection limit, the second one just waits
+                joined = Some(other.id.clone());
+                let optask = prepare(other.clone()).await?;
+                tokio::try_join!(
+                    scheduler.try_join(&id, &sub_id, |rx| handle_media(&ptask, rx, &video, &audio)),
+                    scheduler.try_join(&id, &other.id, |rx| {
+                        handle_media(&optask, rx, &video, &audio)
+                    }),
+                )?;
+            }
+            TaskType::AudioVideo if unmuxed => {
+                Progress::new(id.clone(), sub_id.clone()).send(1, 1).await?;
             }
             TaskType::AudioVideo => {
+                // Moved last above, nothing is left to download
+                TASK_MANAGER.hand_off(&id);
                 scheduler
                     .try_join(&id, &sub_id, |rx| handle_merge(&ptask, rx, &video, &audio))
                     .await?;
@@ -528,13 +987,8 @@ pub async fn handle_task(scheduler: Arc<Scheduler>, task: Arc<RwLock<Task>>) ->
                     .try_join(&id, &sub_id, |rx| handle_danmaku(&ptask, rx))
                     .await?;
             }
-            TaskType::AlbumNfo | TaskType::SingleNfo => {
-                scheduler
-                    .try_join(&id, &sub_id, |rx| {
-                        handle_nfo(&ptask, rx, &folder, &task.nfo)
-                    })
-                    .await?;
-            }
+            // Taken out of `subtasks` above
+            TaskType::AlbumNfo | TaskType::SingleNfo => (),
             TaskType::AiSummary => {
                 scheduler
                     .try_join(&id, &sub_id, |rx| handle_ai_summary(&ptask, rx))
@@ -557,5 +1011,33 @@ pub async fn handle_task(scheduler: Arc<Scheduler>, task: Arc<RwLock<Task>>) ->
             }
         }
     }
-    Ok(())
+    // An NFO is named like the media it describes, and only needs a name of its own
+    // when there's no media
+    if let (None, Some(nfo)) = (&info_name, nfos.first()) {
+        info_name = Some(prepare(nfo.clone()).await?.filename);
+    }
+    if config::read().temp_near_output {
+        naming::clean_temp(&folder, &task.subtasks).await;
+    }
+    let output = match TASK_MANAGER.get_task(&id).await {
+        Some(lock) => {
+            let lock = lock.read().await;
+            if lock.state == TaskState::Cancelled {
+                return Ok(());
+            }
+            lock.output.clone()
+        }
+        None => return Ok(()),
+    };
+    // Nothing is left to download either way
+    TASK_MANAGER.hand_off(&id);
+    post::run_task(&TaskOutput {
+        task,
+        urls,
+        folder,
+        root: scheduler.folder.clone(),
+        filename: info_name,
+        output,
+    })
+    .await
 }
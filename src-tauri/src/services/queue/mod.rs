@@ -1,3 +1,6 @@
+pub mod export;
 pub mod handlers;
+pub mod post;
+pub mod preflight;
 pub mod runtime;
 pub mod types;
@@ -0,0 +1,163 @@
+use std::{future::Future, path::PathBuf, pin::Pin, sync::Arc};
+use tauri_specta::Event;
+
+use crate::{
+    config::{self, Settings},
+    ffmpeg,
+    queue::{
+        handlers,
+        runtime::{ProcessPhase, QueueEvent, Scheduler, TASK_MANAGER},
+        types::{MediaUrls, SubTaskStatus, Task},
+    },
+    shared::{get_app_handle, process_err, ChapterSplit},
+    TauriResult,
+};
+
+type StepFuture<'a> = Pin<Box<dyn Future<Output = TauriResult<()>> + Send + 'a>>;
+
+/// What a finished task left behind for its post-processors.
+pub struct TaskOutput {
+    pub task: Arc<Task>,
+    pub urls: Option<Arc<MediaUrls>>,
+    pub folder: Arc<PathBuf>,
+    /// Folder of the scheduler, which album NFOs go to.
+    pub root: PathBuf,
+    /// Names of the task's files, `None` when it had no subtasks.
+    pub filename: Option<Arc<String>>,
+    /// Where the main file was placed, see `Task::output`.
+    pub output: Option<PathBuf>,
+}
+
+enum Step {
+    /// Runs once per task, after its subtasks and before it counts as completed.
+    Task(for<'a> fn(&'a TaskOutput) -> StepFuture<'a>),
+    /// Runs once every task of the scheduler reached a terminal state.
+    Collection(for<'a> fn(&'a Scheduler) -> StepFuture<'a>),
+}
+
+struct PostProcessor {
+    phase: ProcessPhase,
+    enabled: fn(&Settings) -> bool,
+    /// Whether a per-task step applies to what the task asked for.
+    applies: fn(&Task) -> bool,
+    step: Step,
+}
+
+fn nfo(out: &TaskOutput) -> StepFuture<'_> {
+    Box::pin(handlers::write_nfo(out))
+}
+
+fn info_json(out: &TaskOutput) -> StepFuture<'_> {
+    Box::pin(handlers::handle_info_json(out))
+}
+
+fn storyboard(out: &TaskOutput) -> StepFuture<'_> {
+    Box::pin(handlers::write_storyboard(out))
+}
+
+fn chapters(out: &TaskOutput) -> StepFuture<'_> {
+    Box::pin(handlers::split_chapters(out))
+}
+
+fn playlist(sch: &Scheduler) -> StepFuture<'_> {
+    Box::pin(handlers::write_playlist(&sch.folder))
+}
+
+/// Optional steps in the order they run, each gated by its own setting. A failed step is
+/// only reported. Embedding metadata and the cover happens in the mux and moving the file
+/// into place in `handlers`, since the task has no output without them.
+static REGISTRY: &[PostProcessor] = &[
+    PostProcessor {
+        phase: ProcessPhase::Nfo,
+        enabled: |_| true,
+        applies: |task| task.select.nfo.album || task.select.nfo.single,
+        step: Step::Task(nfo),
+    },
+    PostProcessor {
+        phase: ProcessPhase::InfoJson,
+        enabled: |cfg| cfg.organize.info_json,
+        applies: |_| true,
+        step: Step::Task(info_json),
+    },
+    PostProcessor {
+        phase: ProcessPhase::Storyboard,
+        enabled: |cfg| cfg.storyboard_interval > 0 && ffmpeg::available(),
+        applies: |task| task.select.media.video || task.select.media.audio_video,
+        step: Step::Task(storyboard),
+    },
+    PostProcessor {
+        phase: ProcessPhase::Chapters,
+        enabled: |cfg| cfg.chapter_split != ChapterSplit::Off && ffmpeg::available(),
+        // A clip is usually shorter than a chapter
+        applies: |task| task.select.media.audio_video && task.clip.is_none(),
+        step: Step::Task(chapters),
+    },
+    PostProcessor {
+        phase: ProcessPhase::Playlist,
+        enabled: |cfg| cfg.organize.playlist,
+        applies: |_| true,
+        step: Step::Collection(playlist),
+    },
+];
+
+fn report(parent: &Arc<String>, phase: ProcessPhase, chunk: u64) -> TauriResult<()> {
+    QueueEvent::Processing {
+        parent: parent.clone(),
+        phase,
+        status: SubTaskStatus { content: 1, chunk },
+    }
+    .emit(get_app_handle())?;
+    Ok(())
+}
+
+async fn run<'a>(
+    processor: &PostProcessor,
+    parent: &Arc<String>,
+    step: StepFuture<'a>,
+) -> TauriResult<()> {
+    report(parent, processor.phase, 0)?;
+    match step.await {
+        Ok(()) => report(parent, processor.phase, 1),
+        Err(e) => {
+            process_err(e, &format!("{:?} ({parent})", processor.phase));
+            Ok(())
+        }
+    }
+}
+
+/// Runs the enabled per-task steps for a task whose subtasks all finished, before it
+/// counts as completed. Cancelling the task drops the step running and skips the rest.
+pub async fn run_task(out: &TaskOutput) -> TauriResult<()> {
+    let cfg = config::read();
+    let token = TASK_MANAGER.token(&out.task.id).await;
+    for processor in REGISTRY {
+        let Step::Task(step) = processor.step else {
+            continue;
+        };
+        if !(processor.enabled)(&cfg) || !(processor.applies)(&out.task) {
+            continue;
+        }
+        tokio::select! {
+            res = run(processor, &out.task.id, step(out)) => res?,
+            _ = token.cancelled() => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the enabled collection steps once `sch` has nothing left to run. `parent` is the
+/// task that finished last, which the processing events are reported under.
+pub async fn run_collection(sch: &Scheduler, parent: &Arc<String>) -> TauriResult<()> {
+    let cfg = config::read();
+    let steps = REGISTRY.iter().filter(|v| (v.enabled)(&cfg));
+    let mut finished = None;
+    for processor in steps {
+        let Step::Collection(step) = processor.step else {
+            continue;
+        };
+        if *finished.get_or_insert(sch.finished().await) {
+            run(processor, parent, step(sch)).await?;
+        }
+    }
+    Ok(())
+}
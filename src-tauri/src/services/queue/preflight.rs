@@ -0,0 +1,259 @@
+use anyhow::anyhow;
+use serde::Serialize;
+use specta::Type;
+use std::sync::Arc;
+use tauri::http::{header, StatusCode};
+use tauri_plugin_http::reqwest::Url;
+
+use crate::{
+    errors::{ErrorKind, GONE_CODES, VIP_ONLY_CODE},
+    queue::{
+        runtime::{request_resolve, RequestAction, TASK_MANAGER},
+        types::MediaUrls,
+    },
+    services::{
+        bili::{self, MemberTier},
+        network::CONNECTIONS,
+    },
+    shared::{get_ts, init_client},
+    TauriError, TauriResult,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum PreflightError {
+    /// The signed url is past its `deadline`, resolving again fixes it
+    Expired,
+    Forbidden,
+    RegionLocked,
+    Network,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamCheck {
+    pub stream: String,
+    pub reachable: bool,
+    pub status: Option<u16>,
+    pub content_length: Option<u64>,
+    pub accept_ranges: bool,
+    /// CDN host that answered, or the last one tried
+    pub host: Option<String>,
+    pub error: Option<PreflightError>,
+}
+
+fn expired(url: &Url) -> bool {
+    url.query_pairs()
+        .find(|(k, _)| k == "deadline")
+        .and_then(|(_, v)| v.parse::<i64>().ok())
+        .is_some_and(|v| v < get_ts(true))
+}
+
+fn classify(url: &Url, status: StatusCode) -> PreflightError {
+    match status {
+        StatusCode::FORBIDDEN if expired(url) => PreflightError::Expired,
+        StatusCode::FORBIDDEN => PreflightError::Forbidden,
+        StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => PreflightError::RegionLocked,
+        _ => PreflightError::Other,
+    }
+}
+
+/// Requests the first byte of `url`, which is enough to learn the size and whether the
+/// CDN serves ranges.
+pub(crate) async fn probe(stream: &str, url: &str) -> TauriResult<StreamCheck> {
+    let parsed = Url::parse(url)?;
+    let mut check = StreamCheck {
+        stream: stream.into(),
+        reachable: false,
+        status: None,
+        content_length: None,
+        accept_ranges: false,
+        host: parsed.host_str().map(String::from),
+        error: None,
+    };
+    let _conn = CONNECTIONS.acquire(1).await?;
+    let response = match init_client()
+        .await?
+        .get(url)
+        .header(header::RANGE, "bytes=0-0")
+        .send()
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("Preflight of {stream} failed: {e}");
+            check.error = Some(PreflightError::Network);
+            return Ok(check);
+        }
+    };
+    let status = response.status();
+    check.status = Some(status.as_u16());
+    check.host = response.url().host_str().map(String::from);
+    if status != StatusCode::OK && status != StatusCode::PARTIAL_CONTENT {
+        check.error = Some(classify(&parsed, status));
+        return Ok(check);
+    }
+    let headers = response.headers();
+    let header_str = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    check.reachable = true;
+    check.accept_ranges = status == StatusCode::PARTIAL_CONTENT
+        || header_str(header::ACCEPT_RANGES).is_some_and(|v| v == "bytes");
+    // `bytes 0-0/12345` on a ranged answer, the plain length otherwise
+    check.content_length = if status == StatusCode::PARTIAL_CONTENT {
+        header_str(header::CONTENT_RANGE)
+            .and_then(|v| v.rsplit('/').next())
+            .and_then(|v| v.parse().ok())
+    } else {
+        header_str(header::CONTENT_LENGTH).and_then(|v| v.parse().ok())
+    };
+    Ok(check)
+}
+
+/// Checks that the task's streams are reachable without downloading them, trying the
+/// backup urls of a stream until one answers.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn preflight(id: Arc<String>) -> TauriResult<Vec<StreamCheck>> {
+    if TASK_MANAGER.get_task(&id).await.is_none() {
+        return Err(anyhow!("No task found for {id}").into());
+    }
+    let urls = request_resolve::<MediaUrls>(id.clone(), None, RequestAction::RefreshUrls).await?;
+    let mut result = vec![];
+    for (stream, list) in [("video", &urls.video_urls), ("audio", &urls.audio_urls)] {
+        let Some(list) = list.as_ref().filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        let mut last = None;
+        for url in list {
+            let check = probe(stream, url).await?;
+            let reachable = check.reachable;
+            last = Some(check);
+            if reachable {
+                break;
+            }
+        }
+        result.extend(last);
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum AccessBlocker {
+    Vip,
+    /// Charge-exclusive (充电专属), and the account hasn't charged
+    Charge,
+    RegionLocked,
+    /// Paid content, which may already be bought
+    Purchase,
+    /// Deleted, under review or hidden
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessItem {
+    pub input: String,
+    pub title: Option<String>,
+    pub blockers: Vec<AccessBlocker>,
+    /// Why the item couldn't be checked, when it's none of the blockers.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessCounts {
+    pub vip: usize,
+    pub charge: usize,
+    pub region_locked: usize,
+    pub purchase: usize,
+    pub unavailable: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessReport {
+    pub tier: MemberTier,
+    pub total: usize,
+    /// Items with no blocker and no error.
+    pub accessible: usize,
+    pub counts: AccessCounts,
+    pub items: Vec<AccessItem>,
+}
+
+fn blocker(e: &TauriError) -> Option<AccessBlocker> {
+    let code = e.code.map(|v| v.saturating_isize());
+    match e.kind {
+        Some(ErrorKind::NotEntitled) => Some(AccessBlocker::Charge),
+        Some(ErrorKind::RegionLocked) => Some(AccessBlocker::RegionLocked),
+        _ if code == Some(VIP_ONLY_CODE) => Some(AccessBlocker::Vip),
+        _ if code.is_some_and(|v| GONE_CODES.contains(&v)) => Some(AccessBlocker::Unavailable),
+        _ => None,
+    }
+}
+
+async fn check_access(input: String) -> AccessItem {
+    let mut item = AccessItem {
+        input,
+        title: None,
+        blockers: vec![],
+        error: None,
+    };
+    let result = async {
+        let id = bili::normalize(&item.input).await?;
+        bili::video_info(&id).await
+    };
+    match TASK_MANAGER.resolve.run(result).await {
+        Ok(info) => {
+            if info.paid {
+                item.blockers.push(AccessBlocker::Purchase);
+            }
+            item.title = Some(info.title.clone());
+        }
+        Err(e) => match blocker(&e) {
+            Some(v) => item.blockers.push(v),
+            None => item.error = Some(e.message),
+        },
+    }
+    item
+}
+
+/// Checks what keeps each of `inputs` from being downloaded with the current account,
+/// without enqueuing anything. Runs through the resolver, so at most `resolve_conc` at
+/// a time.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn batch_access_report(inputs: Vec<String>) -> TauriResult<AccessReport> {
+    let tier = bili::get_member_tier().await?;
+    let handles: Vec<_> = inputs
+        .into_iter()
+        .map(|input| tauri::async_runtime::spawn(check_access(input)))
+        .collect();
+    let mut items = vec![];
+    for handle in handles {
+        items.push(handle.await?);
+    }
+    let mut counts = AccessCounts::default();
+    for blocker in items.iter().flat_map(|v| &v.blockers) {
+        *match blocker {
+            AccessBlocker::Vip => &mut counts.vip,
+            AccessBlocker::Charge => &mut counts.charge,
+            AccessBlocker::RegionLocked => &mut counts.region_locked,
+            AccessBlocker::Purchase => &mut counts.purchase,
+            AccessBlocker::Unavailable => &mut counts.unavailable,
+        } += 1;
+    }
+    let accessible = items
+        .iter()
+        .filter(|v| v.blockers.is_empty() && v.error.is_none())
+        .count();
+    log::info!("Access report of {} items: {counts:?}", items.len());
+    Ok(AccessReport {
+        tier,
+        total: items.len(),
+        accessible,
+        counts,
+        items,
+    })
+}
@@ -1,40 +1,83 @@
 use anyhow::{anyhow, Context, Result};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    ffi::OsString,
     future::Future,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, Ordering::SeqCst},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::SeqCst},
         Arc, LazyLock,
     },
+    time::Duration,
 };
 use tauri_specta::Event;
+use tokio::fs;
 use tokio::sync::{
     broadcast::{self, Receiver, Sender},
-    oneshot, OnceCell, RwLock, Semaphore,
+    oneshot, Mutex, Notify, OnceCell, OwnedSemaphorePermit, RwLock, Semaphore,
 };
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
 
 use specta::Type;
 use tauri::{async_runtime, Listener};
 
 use crate::{
     archive, config,
-    errors::{TauriError, TauriResult},
+    errors::{ErrorKind, ErrorParams, Recovery, TauriError, TauriResult, AUTH_EXPIRED_CODE},
     queue::{
-        handlers,
+        handlers, post,
         types::{QueueData, Task},
     },
-    shared::{get_app_handle, get_ts, get_unique_path, process_err},
-    storage::schedulers,
+    services::aria2c,
+    services::{
+        bili::{self, MemberTier},
+        ffmpeg, login, naming,
+        network::CONNECTIONS,
+        power, presets,
+        subscribe::{self, SubscriptionItem},
+    },
+    shared::{
+        check_dir, dir_size, get_app_handle, get_ts, get_unique_path, move_dir, numbered_path,
+        part_path, process_err, random_string,
+    },
+    storage::{labels, schedulers, subscriptions::SubscriptionKind},
 };
 
-use super::types::{PopupSelect, QueueType, SubTaskStatus, TaskState};
+use super::types::{
+    AudioQuality, PopupSelect, QueueImport, QueueType, SelectChild, SelectChildren, SelectPlan,
+    SubTaskStatus, TaskDetail, TaskFilter, TaskOverrides, TaskPage, TaskQuery, TaskState, Timings,
+};
 
 pub static TASK_MANAGER: LazyLock<TaskManager> = LazyLock::new(TaskManager::new);
 
 pub static QUEUE_READY: LazyLock<OnceCell<()>> = LazyLock::new(OnceCell::new);
 
+// Largest page `get_tasks` returns
+const MAX_PAGE: u64 = 500;
+
+// Folders and channels change, but not while the user is picking from one
+const CHILDREN_TTL: Duration = Duration::from_secs(300);
+
+// Bumped when a `queue_export` file stops being readable by older versions
+const QUEUE_FILE_VERSION: u32 = 1;
+
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(30);
+
+const RAMP_STEP: Duration = Duration::from_secs(3);
+
+const TUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+const MAX_STALL_RETRIES: usize = 3;
+
+// Past this many reconnect retries a task is left failed, the network isn't the problem
+const MAX_RECONNECT_RETRIES: usize = 5;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
 // Syncing
 
 pub struct Progress {
@@ -51,6 +94,55 @@ impl Progress {
             .progress(&self.parent, &self.id, content, chunk)
             .await
     }
+    /// Token that flips as soon as the parent task is cancelled.
+    pub async fn token(&self) -> CancellationToken {
+        TASK_MANAGER.token(&self.parent).await
+    }
+    /// Reports byte-level transfer figures, which `queue_stats` aggregates.
+    pub async fn transfer(&self, speed: u64, remaining: u64) {
+        TASK_MANAGER
+            .transfer(&self.parent, &self.id, speed, remaining)
+            .await
+    }
+}
+
+struct Transfer {
+    parent: Arc<String>,
+    speed: u64,
+    remaining: u64,
+}
+
+/// An output path held by `reserve_path`.
+struct PathClaim {
+    owner: Arc<String>,
+    /// The file was already there, as when overwriting, so it showing up ends nothing.
+    existed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    pub pending: usize,
+    pub active: usize,
+    pub paused: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    /// Summed download speed of active subtasks, in bytes per second.
+    pub speed: u64,
+    pub remaining: u64,
+    /// Seconds left at the current speed.
+    pub eta: Option<u64>,
+    /// Number of tasks allowed to run at once right now.
+    pub concurrency: usize,
+    /// Connections held against the `max_connections` ceiling.
+    pub connections: usize,
+    /// Tasks holding a download slot, out of `concurrency`.
+    pub downloading: usize,
+    /// Size of the FFmpeg pool, see `post_conc`.
+    pub post_concurrency: usize,
+    /// FFmpeg jobs running in it.
+    pub post_processing: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
@@ -88,7 +180,123 @@ pub enum QueueEvent {
         id: Option<Arc<String>>,
         message: String,
         code: Option<isize>,
+        kind: Option<ErrorKind>,
+        params: ErrorParams,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recovery: Option<Recovery>,
+    },
+    Network {
+        metered: bool,
+        list: Vec<Arc<String>>,
+    },
+    AuthExpired {
+        list: Vec<Arc<String>>,
+    },
+    /// Connectivity came back and the tasks that failed on network errors were retried.
+    Reconnected {
+        list: Vec<Arc<String>>,
+    },
+    /// A task failed again after its automatic retries ran out, and stays failed until
+    /// retried by hand.
+    RetriesExhausted {
+        parent: Arc<String>,
+        retries: usize,
+        error: String,
+    },
+    Power {
+        suspended: bool,
+        list: Vec<Arc<String>>,
     },
+    /// Tasks paused as the battery ran below `pause_on_battery`, or resumed after.
+    Battery {
+        paused: bool,
+        level: Option<u8>,
+        list: Vec<Arc<String>>,
+    },
+    /// Tasks paused as `quiet_hours` began, or resumed as they ended.
+    QuietHours {
+        active: bool,
+        list: Vec<Arc<String>>,
+    },
+    Stats(QueueStats),
+    AudioFallback {
+        parent: Arc<String>,
+        wanted: AudioQuality,
+        chosen: Option<AudioQuality>,
+        abr: usize,
+    },
+    QualityFallback {
+        parent: Arc<String>,
+        wanted: usize,
+        chosen: usize,
+        tier: MemberTier,
+    },
+    /// `prefer_hfr` is set but `res` has no 60fps variant the account may play, so the
+    /// standard one is used.
+    HfrUnavailable {
+        parent: Arc<String>,
+        res: usize,
+    },
+    /// Post-processing of a finished task, which runs outside the queue.
+    Processing {
+        parent: Arc<String>,
+        phase: ProcessPhase,
+        status: SubTaskStatus,
+    },
+    /// FFmpeg is unavailable, so the task keeps its video and audio as separate files.
+    Unmuxed {
+        parent: Arc<String>,
+    },
+    /// Tasks cancelled together by `cancel_matching`, already gone from the queue.
+    Cancelled {
+        list: Vec<Arc<String>>,
+    },
+    /// How long a completed task spent in each phase, also kept in its `timings`.
+    Timings {
+        parent: Arc<String>,
+        timings: Timings,
+    },
+    /// The subtask's output already existed on disk and `existing_file` is `skip`.
+    Skipped {
+        parent: Arc<String>,
+        id: Arc<String>,
+        path: PathBuf,
+    },
+    /// Enumeration of subscription `id` was stopped by `cancel_enumeration` after
+    /// finding `found` items, which are still handed on.
+    EnumerationCancelled {
+        id: String,
+        found: usize,
+    },
+}
+
+/// Discrepancies fixed by `repair_archive`, as task ids or archive row names.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairReport {
+    pub loaded: Vec<Arc<String>>,
+    pub persisted: Vec<Arc<String>>,
+    pub dropped: Vec<Arc<String>>,
+    pub quarantined: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ProcessPhase {
+    Nfo,
+    Storyboard,
+    Chapters,
+    InfoJson,
+    Playlist,
+}
+
+/// Why running tasks were paused without the user asking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum HoldReason {
+    Metered,
+    Sleep,
+    Battery,
+    Quiet,
 }
 
 // Tasks
@@ -96,11 +304,35 @@ pub enum QueueEvent {
 pub struct TaskManager {
     pub schedulers: RwLock<HashMap<Arc<String>, Arc<Scheduler>>>,
     pub tasks: RwLock<HashMap<Arc<String>, Arc<RwLock<Task>>>>,
+    pub plans: RwLock<HashMap<Arc<String>, Arc<SelectPlan>>>,
+    /// Pages `get_select_children` resolved, by parent and page number.
+    children: RwLock<HashMap<(String, usize), (Instant, Arc<(Vec<SubscriptionItem>, bool)>)>>,
+    submitting: Mutex<HashMap<String, Arc<String>>>,
+    /// Tasks paused automatically, with their sid and every reason still holding them.
+    holds: Mutex<HashMap<Arc<String>, (Arc<String>, HashSet<HoldReason>)>>,
+    last_progress: RwLock<HashMap<Arc<String>, Instant>>,
+    // When each active task last became active, folded into `Task::active` as it leaves
+    active_since: Mutex<HashMap<Arc<String>, Instant>>,
+    throttle: Mutex<HashMap<Arc<String>, (Instant, Option<QueueEvent>)>>,
+    reserved: std::sync::Mutex<HashMap<PathBuf, PathClaim>>,
+    stalls: Mutex<HashMap<Arc<String>, usize>>,
+    dirty: Mutex<HashSet<Arc<String>>>,
+    transfers: Mutex<HashMap<Arc<String>, Transfer>>,
+    tokens: Mutex<HashMap<Arc<String>, CancellationToken>>,
+    // Download slots of running tasks by attempt, handed back once only muxing is left
+    slots: std::sync::Mutex<HashMap<Arc<String>, DownloadSlot>>,
+    attempts: AtomicU64,
+    progress_updates: AtomicU64,
+    progress_writes: AtomicU64,
     pub waiting: RwLock<VecDeque<Arc<String>>>,
     pub doing: RwLock<VecDeque<Arc<String>>>,
     pub complete: RwLock<VecDeque<Arc<String>>>,
-    pub sem: RwLock<Arc<Semaphore>>,
-    pub conc: RwLock<usize>,
+    pub sem: ResizableSemaphore,
+    pub resolve: ResolvePool,
+    pub post: PostPool,
+    pub ramp: Ramp,
+    pub auth: AuthGuard,
+    pub reconnect: ReconnectGuard,
 }
 
 impl Default for TaskManager {
@@ -115,11 +347,31 @@ impl TaskManager {
         Self {
             schedulers: Default::default(),
             tasks: Default::default(),
+            plans: Default::default(),
+            children: Default::default(),
+            submitting: Default::default(),
+            holds: Default::default(),
+            last_progress: Default::default(),
+            active_since: Default::default(),
+            throttle: Default::default(),
+            reserved: Default::default(),
+            stalls: Default::default(),
+            dirty: Default::default(),
+            transfers: Default::default(),
+            tokens: Default::default(),
+            slots: Default::default(),
+            attempts: AtomicU64::new(0),
+            progress_updates: AtomicU64::new(0),
+            progress_writes: AtomicU64::new(0),
             waiting: Default::default(),
             doing: Default::default(),
             complete: Default::default(),
-            sem: RwLock::new(Arc::new(Semaphore::new(conc))),
-            conc: RwLock::new(conc),
+            sem: ResizableSemaphore::new(conc),
+            resolve: ResolvePool::new(config::read().resolve_conc),
+            post: PostPool::new(config::read().post_conc),
+            ramp: Ramp::new(),
+            auth: Default::default(),
+            reconnect: Default::default(),
         }
     }
 
@@ -144,7 +396,200 @@ impl TaskManager {
             .ok_or(anyhow!("No scheduler with sid {sid} found"))
     }
 
-    pub async fn push_pending(&self, task: Task) -> Result<()> {
+    pub async fn get_plan(&self, id: &Arc<String>) -> Option<Arc<SelectPlan>> {
+        if let Some(plan) = self.plans.read().await.get(id).cloned() {
+            return Some(plan);
+        }
+        let task = self.get_task(id).await?;
+        let plan = Arc::new(SelectPlan::new(&*task.read().await));
+        self.plans.write().await.insert(id.clone(), plan.clone());
+        Some(plan)
+    }
+
+    /// `get_plan` with the qualities probed through the resolution pool while they're
+    /// missing. Only for the selection UI: a running task would already hold the permit
+    /// `request_resolve` took.
+    pub async fn get_plan_available(&self, id: &Arc<String>) -> Option<Arc<SelectPlan>> {
+        let plan = self.get_plan(id).await?;
+        if plan.available.is_some() {
+            return Some(plan);
+        }
+        let item = self.get_task(id).await?.read().await.item.clone();
+        let (Some(bvid), Some(cid)) = (&item.bvid, item.cid) else {
+            return Some(plan);
+        };
+        let available = match self.resolve.run(bili::get_qualities(bvid, cid)).await {
+            Ok(v) => v,
+            // Left out, so the next call asks again
+            Err(e) => {
+                log::warn!("Failed to get the qualities of {id}: {}", e.message);
+                return Some(plan);
+            }
+        };
+        let mut plans = self.plans.write().await;
+        // `update_select` may have replaced it meanwhile, and a removed task keeps none
+        let current = plans.get(id).cloned()?;
+        let plan = Arc::new(SelectPlan {
+            available: Some(available),
+            ..(*current).clone()
+        });
+        plans.insert(id.clone(), plan.clone());
+        Some(plan)
+    }
+
+    /// Page `pn` of a parent's items, resolved the first time it's asked for and then
+    /// kept for `CHILDREN_TTL`, so paging back and forth while picking doesn't ask again.
+    pub async fn children(
+        &self,
+        kind: SubscriptionKind,
+        target: &str,
+        pn: usize,
+    ) -> TauriResult<Arc<(Vec<SubscriptionItem>, bool)>> {
+        let key = (format!("{}:{target}", kind.as_str()), pn);
+        if let Some((ts, page)) = self.children.read().await.get(&key) {
+            if ts.elapsed() < CHILDREN_TTL {
+                return Ok(page.clone());
+            }
+        }
+        let page = Arc::new(
+            self.resolve
+                .run(subscribe::list_page(kind, target, pn))
+                .await?,
+        );
+        let mut children = self.children.write().await;
+        children.retain(|_, (ts, _)| ts.elapsed() < CHILDREN_TTL);
+        children.insert(key, (Instant::now(), page.clone()));
+        Ok(page)
+    }
+
+    pub async fn update_select(
+        &self,
+        id: &Arc<String>,
+        select: Arc<PopupSelect>,
+    ) -> Result<Option<Arc<SelectPlan>>> {
+        let Some(lock) = self.get_task(id).await else {
+            return Ok(None);
+        };
+        let plan = self
+            .get_plan(id)
+            .await
+            .map(|p| p.with_select(select.clone()));
+        let mut task = lock.write().await;
+        task.select = select;
+        archive::upsert(&task).await?;
+        drop(task);
+        let Some(plan) = plan.map(Arc::new) else {
+            return Ok(None);
+        };
+        self.plans.write().await.insert(id.clone(), plan.clone());
+        Ok(Some(plan))
+    }
+
+    /// Adds and removes labels on each of `ids`, skipping tasks that no longer exist.
+    pub async fn relabel(
+        &self,
+        ids: &[Arc<String>],
+        add: &[String],
+        remove: &[String],
+    ) -> Result<()> {
+        for id in ids {
+            let Some(lock) = self.get_task(id).await else {
+                continue;
+            };
+            let mut task = lock.write().await;
+            task.labels.retain(|v| !remove.contains(v));
+            for label in add {
+                if !task.labels.contains(label) {
+                    task.labels.push(label.clone());
+                }
+            }
+            archive::upsert(&task).await?;
+            labels::set(id, &task.labels).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn find_scheduler(&self, id: &Arc<String>) -> Option<Arc<Scheduler>> {
+        let schedulers = self.schedulers.read().await;
+        for sch in schedulers.values() {
+            if sch
+                .list
+                .read()
+                .await
+                .iter()
+                .any(|v| v.as_str() == id.as_str())
+            {
+                return Some(sch.clone());
+            }
+        }
+        None
+    }
+
+    pub async fn forget_task(&self, id: &Arc<String>) -> Result<()> {
+        if let Some(lock) = self.get_task(id).await {
+            let state = lock.read().await.state.clone();
+            if !state.is_terminal() {
+                return Err(anyhow!("Task {id} is still {state:?}, refusing to delete"));
+            }
+        }
+        match self.find_scheduler(id).await {
+            Some(sch) => self.pop_task(&sch.sid, id).await?,
+            None => {
+                archive::delete(id).await?;
+                self.tasks.write().await.remove(id);
+                self.plans.write().await.remove(id);
+                self.snapshot(true).await?;
+            }
+        }
+        log::info!("Archive entry {id} deleted");
+        Ok(())
+    }
+
+    async fn find_duplicate(&self, identity: &str) -> Option<Arc<String>> {
+        let tasks = self.tasks.read().await;
+        for (id, lock) in tasks.iter() {
+            let task = lock.read().await;
+            if !task.state.is_terminal() && task.identity() == identity {
+                return Some(id.clone());
+            }
+        }
+        None
+    }
+
+    pub async fn submit(&self, task: Task) -> Result<Arc<String>> {
+        self.submit_with(task, |task| self.push_pending(task)).await
+    }
+
+    /// `submit` with how the task is queued left to `push`, which holds the identity's
+    /// claim until it returns.
+    async fn submit_with<F, Fut>(&self, task: Task, push: F) -> Result<Arc<String>>
+    where
+        F: FnOnce(Task) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let identity = task.identity();
+        {
+            let mut submitting = self.submitting.lock().await;
+            if let Some(id) = submitting.get(&identity) {
+                log::info!("Duplicate submit collapsed into in-flight task: {id}");
+                return Ok(id.clone());
+            }
+            if let Some(id) = self.find_duplicate(&identity).await {
+                log::info!("Duplicate submit collapsed into existing task: {id}");
+                return Ok(id);
+            }
+            submitting.insert(identity.clone(), task.id.clone());
+        }
+        let id = task.id.clone();
+        let result = push(task).await;
+        self.submitting.lock().await.remove(&identity);
+        result.map(|_| id)
+    }
+
+    pub async fn push_pending(&self, mut task: Task) -> Result<()> {
+        if task.submitted.is_none() {
+            task.submitted = Some(task.select.clone());
+        }
         archive::upsert(&task).await?;
         let task = Arc::new(RwLock::new(task));
         let (id, ts) = {
@@ -186,7 +631,7 @@ impl TaskManager {
             return Err(anyhow!("No unassigned tasks"));
         }
         let folder = if config::read().organize.top_folder {
-            &get_unique_path(config::read().down_dir.join(filename))
+            &get_unique_path(&sid, config::read().down_dir.join(filename))
         } else {
             &config::read().down_dir
         };
@@ -232,8 +677,10 @@ impl TaskManager {
             return Ok(());
         };
         let mut task = lock.write().await;
+        self.clock(&mut task, &state).await;
         task.state = state.clone();
         let snapshot = task.clone();
+        self.settle(parent, &state).await;
         archive::upsert(&snapshot).await?;
         let app = get_app_handle();
         QueueEvent::State {
@@ -244,6 +691,50 @@ impl TaskManager {
         Ok(())
     }
 
+    /// Adds the time `task` was active to `Task::active` once it leaves that state.
+    async fn clock(&self, task: &mut Task, state: &TaskState) {
+        let mut since = self.active_since.lock().await;
+        if *state == TaskState::Active {
+            since.entry(task.id.clone()).or_insert_with(Instant::now);
+        } else if let Some(at) = since.remove(&task.id) {
+            task.active += at.elapsed().as_millis() as u64;
+        }
+    }
+
+    /// Starts the active time of a task retried by hand over.
+    async fn rewind(&self, id: &Arc<String>) {
+        if let Some(task) = self.get_task(id).await {
+            task.write().await.active = 0;
+        }
+        if let Some(at) = self.active_since.lock().await.get_mut(id) {
+            *at = Instant::now();
+        }
+    }
+
+    /// Brings the per-task bookkeeping in line with a task entering `state`.
+    async fn settle(&self, parent: &Arc<String>, state: &TaskState) {
+        {
+            let mut last = self.last_progress.write().await;
+            if *state == TaskState::Active {
+                last.insert(parent.clone(), Instant::now());
+            } else {
+                last.remove(parent);
+            }
+        }
+        if state.is_terminal() {
+            self.stalls.lock().await.remove(parent);
+            self.tokens.lock().await.remove(parent);
+            self.release_claims(parent);
+        }
+        if *state != TaskState::Active {
+            self.transfers
+                .lock()
+                .await
+                .retain(|_, v| v.parent != *parent);
+        }
+        self.dirty.lock().await.remove(parent);
+    }
+
     pub async fn progress(
         &self,
         parent: &Arc<String>,
@@ -256,19 +747,190 @@ impl TaskManager {
             let status = task.status.get_mut(id).ok_or(anyhow!(format!(
                 "Failed to get subtask: {id}, parent: {parent}"
             )))?;
+            if status.chunk != chunk || status.content != content {
+                let mut last = self.last_progress.write().await;
+                if let Some(ts) = last.get_mut(parent) {
+                    *ts = Instant::now();
+                }
+            }
             *status = Arc::new(SubTaskStatus { content, chunk });
-            archive::upsert(&task).await?;
+            self.dirty.lock().await.insert(parent.clone());
+            self.progress_updates.fetch_add(1, SeqCst);
         }
-        let app = get_app_handle();
-        QueueEvent::Progress {
+        let event = QueueEvent::Progress {
             parent: parent.clone(),
             id: id.clone(),
             status: SubTaskStatus { content, chunk },
+        };
+        let interval = Duration::from_millis(config::read().progress_interval);
+        let mut throttle = self.throttle.lock().await;
+        // Starts and completions always go out so the frontend never misses a transition
+        if chunk >= content {
+            throttle.remove(id);
+        } else if chunk > 0 {
+            if let Some((at, pending)) = throttle.get_mut(id) {
+                if at.elapsed() < interval {
+                    *pending = Some(event);
+                    return Ok(());
+                }
+            }
+            throttle.insert(id.clone(), (Instant::now(), None));
+        }
+        drop(throttle);
+        event.emit(get_app_handle())?;
+        Ok(())
+    }
+
+    /// Claims an output path for `owner` so concurrent tasks never write the same name.
+    /// Paths held by another task, and with `avoid_existing` paths already on disk, move on
+    /// to the next numbered name. A claim ends with `release_path`, once a file that didn't
+    /// exist when it was claimed shows up, or when its task settles.
+    pub fn reserve_path(
+        &self,
+        owner: &Arc<String>,
+        path: PathBuf,
+        avoid_existing: bool,
+    ) -> PathBuf {
+        let mut reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        let mut candidate = path.clone();
+        let mut count = 1;
+        loop {
+            // Only the names tried are checked for a claim whose file has shown up
+            let ended = reserved.get(&candidate).is_some_and(|claim| {
+                !claim.existed && candidate.exists() && !part_path(&candidate).exists()
+            });
+            if ended {
+                reserved.remove(&candidate);
+            }
+            let taken = reserved.contains_key(&candidate)
+                || (avoid_existing && (candidate.exists() || part_path(&candidate).exists()));
+            if !taken {
+                break;
+            }
+            candidate = numbered_path(&path, count);
+            count += 1;
+        }
+        let claim = PathClaim {
+            owner: owner.clone(),
+            existed: candidate.exists(),
+        };
+        reserved.insert(candidate.clone(), claim);
+        candidate
+    }
+
+    pub fn release_path(&self, path: &Path) {
+        let mut reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        reserved.remove(path);
+    }
+
+    /// Drops every path claim `owner` still holds, its files are either placed or given up.
+    fn release_claims(&self, owner: &Arc<String>) {
+        let mut reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+        reserved.retain(|_, claim| claim.owner != *owner);
+    }
+
+    /// Emits the latest progress held back by `progress_interval`, once it's due.
+    async fn flush_progress(&self) -> Result<()> {
+        let interval = Duration::from_millis(config::read().progress_interval);
+        let mut due = vec![];
+        for (at, pending) in self.throttle.lock().await.values_mut() {
+            if at.elapsed() >= interval {
+                if let Some(event) = pending.take() {
+                    *at = Instant::now();
+                    due.push(event);
+                }
+            }
+        }
+        for event in due {
+            event.emit(get_app_handle())?;
         }
-        .emit(app)?;
         Ok(())
     }
 
+    pub async fn token(&self, id: &Arc<String>) -> CancellationToken {
+        let mut tokens = self.tokens.lock().await;
+        tokens.entry(id.clone()).or_default().clone()
+    }
+
+    async fn cancel_token(&self, id: &Arc<String>) {
+        if let Some(token) = self.tokens.lock().await.remove(id) {
+            token.cancel();
+        }
+    }
+
+    async fn transfer(&self, parent: &Arc<String>, id: &Arc<String>, speed: u64, remaining: u64) {
+        let mut transfers = self.transfers.lock().await;
+        if remaining == 0 {
+            transfers.remove(id);
+            return;
+        }
+        transfers.insert(
+            id.clone(),
+            Transfer {
+                parent: parent.clone(),
+                speed,
+                remaining,
+            },
+        );
+    }
+
+    /// Pending tasks of dispatched schedulers, i.e. those waiting for a download slot.
+    async fn queued(&self) -> usize {
+        let mut count = 0;
+        let doing = self.doing.read().await.clone();
+        for sid in doing {
+            let Ok(sch) = self.get_scheduler(&sid).await else {
+                continue;
+            };
+            if !sch.inited.initialized() {
+                continue;
+            }
+            for id in sch.list.read().await.iter() {
+                if let Some(lock) = self.get_task(id).await {
+                    if lock.read().await.state == TaskState::Pending {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    pub async fn stats(&self) -> QueueStats {
+        let mut stats = QueueStats::default();
+        {
+            let transfers = self.transfers.lock().await;
+            for v in transfers.values() {
+                stats.speed += v.speed;
+                stats.remaining += v.remaining;
+            }
+        }
+        if stats.speed > 0 {
+            stats.eta = Some(stats.remaining.div_ceil(stats.speed));
+        }
+        stats.concurrency = match Ramp::enabled() {
+            true => self.ramp.limit(),
+            false => self.sem.size(),
+        };
+        stats.connections = CONNECTIONS.in_use();
+        stats.downloading = self.slots.lock().unwrap_or_else(|e| e.into_inner()).len();
+        stats.post_concurrency = self.post.sem.size();
+        stats.post_processing = self.post.busy.load(SeqCst);
+        let tasks = self.tasks.read().await;
+        for lock in tasks.values() {
+            let count = match lock.read().await.state {
+                TaskState::Pending => &mut stats.pending,
+                TaskState::Active => &mut stats.active,
+                TaskState::Paused => &mut stats.paused,
+                TaskState::Completed => &mut stats.completed,
+                TaskState::Failed => &mut stats.failed,
+                TaskState::Cancelled => &mut stats.cancelled,
+            };
+            *count += 1;
+        }
+        stats
+    }
+
     pub async fn snapshot(&self, with_tasks: bool) -> Result<()> {
         let queue = QueueData {
             waiting: self.waiting.read().await.clone(),
@@ -317,74 +979,1111 @@ impl TaskManager {
         Ok(())
     }
 
-    pub async fn update_max_conc(&self, new_conc: usize) {
-        use std::cmp::Ordering;
-        let mut conc = self.conc.write().await;
-        let mut sem = self.sem.write().await;
-        match new_conc.cmp(&*conc) {
-            Ordering::Greater => (**sem).add_permits(new_conc - *conc),
-            Ordering::Less => *sem = Arc::new(Semaphore::new(new_conc)),
-            Ordering::Equal => (),
+    fn take_slot(
+        &self,
+        id: &Arc<String>,
+        permit: OwnedSemaphorePermit,
+        ramp: Option<RampGuard>,
+    ) -> u64 {
+        let attempt = self.attempts.fetch_add(1, SeqCst);
+        let slot = DownloadSlot {
+            attempt,
+            _permit: permit,
+            _ramp: ramp,
+        };
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.insert(id.clone(), slot);
+        attempt
+    }
+
+    /// Frees the download slot of `id` for the next task, once all that's left of it is
+    /// post-processing.
+    pub fn hand_off(&self, id: &Arc<String>) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        if slots.remove(id).is_some() {
+            log::info!("Task {id} handed its download slot off for post-processing");
         }
-        *conc = new_conc;
     }
 
-    pub async fn housekeeping(&self, id: &Arc<String>) -> Result<()> {
-        let Some(lock) = self.get_task(id).await else {
-            return Ok(());
-        };
-        let mut task = lock.write().await;
-        let subtasks = task
-            .subtasks
-            .iter()
-            .map(|t| t.id.clone())
-            .collect::<Vec<_>>();
-        let temp_root = config::read().temp_dir();
-        for id in subtasks {
-            let dir = temp_root.join(&*id);
-            let _ = tokio::fs::remove_dir_all(dir).await;
-            let _ = crate::services::aria2c::cancel(id.clone()).await;
-            task.status.insert(
-                id,
-                Arc::new(SubTaskStatus {
-                    chunk: 0,
-                    content: 0,
-                }),
-            );
+    // A retry may already hold a newer slot by the time an attempt ends
+    fn end_slot(&self, id: &Arc<String>, attempt: u64) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        if slots.get(id).is_some_and(|v| v.attempt == attempt) {
+            slots.remove(id);
         }
-        Ok(())
     }
 
-    pub async fn pop_task(&self, sid: &Arc<String>, id: &Arc<String>) -> Result<()> {
-        archive::delete(id).await?;
+    pub async fn update_max_conc(&self, new_conc: usize) {
+        self.sem.resize(new_conc);
+    }
 
-        let sch = self.get_scheduler(sid).await?;
-        sch.ctrls.write().await.remove(id);
+    /// Pauses every unfinished task of the running schedulers, returning `(id, sid)` pairs.
+    async fn pause_running(&self) -> Result<Vec<(Arc<String>, Arc<String>)>> {
+        self.pause_running_where(|_| true).await
+    }
 
-        let mut list = sch.list.write().await;
-        list.retain(|v| v.as_str() != id.as_str());
-        let snapshot = list.clone();
-        drop(list);
-        drop(sch);
+    /// Like `pause_running`, leaving alone the tasks `pred` rejects.
+    async fn pause_running_where(
+        &self,
+        pred: impl Fn(&Task) -> bool,
+    ) -> Result<Vec<(Arc<String>, Arc<String>)>> {
+        let mut list = vec![];
+        let doing = { self.doing.read().await.clone() };
+        for sid in doing {
+            let Ok(sch) = self.get_scheduler(&sid).await else {
+                continue;
+            };
+            let ids = { sch.list.read().await.clone() };
+            for id in ids {
+                let Some(ctrl) = sch.get_ctrl(&id).await else {
+                    continue;
+                };
+                if ctrl.is_paused() || ctrl.is_cancelled() {
+                    continue;
+                }
+                let Some(task) = self.get_task(&id).await else {
+                    continue;
+                };
+                {
+                    let task = task.read().await;
+                    if task.state.is_terminal() || !pred(&task) {
+                        continue;
+                    }
+                }
+                ctrl.send(CtrlEvent::Pause, &id);
+                self.state(&id, TaskState::Paused).await?;
+                list.push((id, sid.clone()));
+            }
+        }
+        Ok(list)
+    }
 
-        self.tasks.write().await.remove(id);
-        schedulers::update_list(sid, &snapshot).await?;
-        if snapshot.is_empty() && sid.as_str() != schedulers::WAITING_SID {
-            self.schedulers.write().await.remove(sid);
-            schedulers::delete(sid).await?;
-            for q in [&self.doing, &self.complete, &self.waiting] {
-                let mut guard = q.write().await;
-                if let Some(pos) = guard.iter().position(|v| v.as_str() == sid.as_str()) {
-                    guard.remove(pos);
+    /// Reconciles the tasks in memory with the `archive` table under the tasks lock.
+    /// Rows missing from memory are loaded and corrupt ones quarantined. Tasks missing
+    /// from the table are written back while a scheduler still lists them, and dropped
+    /// otherwise, since only their removal from memory was lost.
+    pub async fn repair(&self) -> Result<RepairReport> {
+        let mut report = RepairReport::default();
+        let mut listed = HashSet::new();
+        for sch in self.schedulers.read().await.values() {
+            listed.extend(sch.list.read().await.iter().cloned());
+        }
+        let mut tasks = self.tasks.write().await;
+        let mut stored = HashSet::new();
+        for (name, value) in archive::rows().await? {
+            let mut task: Task = match serde_json::from_str(&value) {
+                Ok(v) => v,
+                Err(e) => {
+                    archive::quarantine(&name, &value, &e.to_string()).await?;
+                    report.quarantined.push(name);
+                    continue;
+                }
+            };
+            stored.insert(task.id.clone());
+            if tasks.contains_key(&task.id) {
+                continue;
+            }
+            if task.state == TaskState::Active {
+                task.state = TaskState::Paused;
+            }
+            report.loaded.push(task.id.clone());
+            tasks.insert(task.id.clone(), Arc::new(RwLock::new(task)));
+        }
+        let missing: Vec<_> = tasks
+            .keys()
+            .filter(|v| !stored.contains(*v))
+            .cloned()
+            .collect();
+        for id in missing {
+            if listed.contains(&id) {
+                if let Some(lock) = tasks.get(&id) {
+                    archive::upsert(&*lock.read().await).await?;
                 }
+                report.persisted.push(id);
+            } else {
+                tasks.remove(&id);
+                report.dropped.push(id);
             }
         }
+        drop(tasks);
+        log::info!("Archive repaired: {report:?}");
         self.snapshot(true).await?;
+        Ok(report)
+    }
+
+    /// Points a task's output at `path`, after its file was moved outside the app.
+    pub async fn relink(&self, id: &Arc<String>, path: PathBuf) -> Result<()> {
+        let lock = self
+            .get_task(id)
+            .await
+            .ok_or(anyhow!("No task found for {id}"))?;
+        let mut task = lock.write().await;
+        log::info!("Relinking {id}: {:?} -> {}", task.output, path.display());
+        task.output = Some(path);
+        archive::upsert(&task).await?;
         Ok(())
     }
-}
 
-// Scheduler
+    /// Relinks completed tasks whose output is gone to a file of the same name under
+    /// `root`. A name found more than once is left alone, there's no telling which copy
+    /// is the task's.
+    pub async fn relink_orphans(&self, root: PathBuf) -> Result<Vec<Arc<String>>> {
+        let mut orphans: HashMap<OsString, Vec<Arc<String>>> = HashMap::new();
+        for lock in self.tasks.read().await.values() {
+            let task = lock.read().await;
+            let Some(path) = &task.output else {
+                continue;
+            };
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            if task.state == TaskState::Completed && !fs::try_exists(path).await? {
+                orphans
+                    .entry(name.into())
+                    .or_default()
+                    .push(task.id.clone());
+            }
+        }
+        if orphans.is_empty() {
+            return Ok(vec![]);
+        }
+        let names: HashSet<_> = orphans.keys().cloned().collect();
+        let found = async_runtime::spawn_blocking(move || {
+            let mut found: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+            for entry in walkdir::WalkDir::new(&root)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|v| v.file_type().is_file())
+            {
+                if names.contains(entry.file_name()) {
+                    found
+                        .entry(entry.file_name().into())
+                        .or_default()
+                        .push(entry.into_path());
+                }
+            }
+            found
+        })
+        .await?;
+        let mut relinked = vec![];
+        for (name, ids) in orphans {
+            match (ids.as_slice(), found.get(&name).map(Vec::as_slice)) {
+                ([id], Some([path])) => {
+                    self.relink(id, path.clone()).await?;
+                    relinked.push(id.clone());
+                }
+                (_, Some(paths)) => log::warn!(
+                    "Not relinking {name:?}: {} tasks, {} files",
+                    ids.len(),
+                    paths.len()
+                ),
+                _ => (),
+            }
+        }
+        Ok(relinked)
+    }
+
+    pub async fn network_pause(&self, pause: bool) -> Result<Vec<Arc<String>>> {
+        self.hold(HoldReason::Metered, pause).await
+    }
+
+    /// Pauses running tasks before the system sleeps and resumes them on wake, unless
+    /// another hold still applies to them.
+    pub async fn sleep_pause(&self, pause: bool) -> Result<Vec<Arc<String>>> {
+        self.hold(HoldReason::Sleep, pause).await
+    }
+
+    /// Pauses running tasks while the battery is low. Plugging in doesn't resume tasks
+    /// still paused for sleep, quiet hours or a metered connection.
+    pub async fn battery_pause(&self, pause: bool) -> Result<Vec<Arc<String>>> {
+        self.hold(HoldReason::Battery, pause).await
+    }
+
+    /// Pauses running tasks for `quiet_hours` and resumes them once the window ends,
+    /// unless another hold still applies to them.
+    pub async fn quiet_pause(&self, pause: bool) -> Result<Vec<Arc<String>>> {
+        self.hold(HoldReason::Quiet, pause).await
+    }
+
+    /// Adds `reason` to the running tasks, pausing them, and to the ones already held,
+    /// returning those it newly applies to. Lifting it resumes only the tasks no other
+    /// reason holds, and returns those.
+    async fn hold(&self, reason: HoldReason, pause: bool) -> Result<Vec<Arc<String>>> {
+        let mut holds = self.holds.lock().await;
+        let mut list = vec![];
+        if pause {
+            // A held task the user resumed since starts over with this reason alone
+            let paused = self.pause_running().await?;
+            for (id, (_, reasons)) in holds.iter_mut() {
+                if !paused.iter().any(|(v, _)| v == id) && reasons.insert(reason) {
+                    list.push(id.clone());
+                }
+            }
+            for (id, sid) in paused {
+                holds.insert(id.clone(), (sid, HashSet::from([reason])));
+                list.push(id);
+            }
+        } else {
+            let mut lifted = vec![];
+            holds.retain(|id, (sid, reasons)| {
+                if !reasons.remove(&reason) || !reasons.is_empty() {
+                    return true;
+                }
+                lifted.push((id.clone(), sid.clone()));
+                false
+            });
+            for (id, sid) in lifted {
+                let Ok(sch) = self.get_scheduler(&sid).await else {
+                    continue;
+                };
+                let Some(ctrl) = sch.get_ctrl(&id).await else {
+                    continue;
+                };
+                if !ctrl.is_paused() || ctrl.is_cancelled() {
+                    continue;
+                }
+                ctrl.send(CtrlEvent::Resume, &id);
+                self.state(&id, TaskState::Active).await?;
+                list.push(id);
+            }
+        }
+        Ok(list)
+    }
+
+    /// Moves the temp files of unfinished tasks from `old_temp` to `new_temp` and re-roots
+    /// their schedulers from `from` to `to`, then runs `commit`. Everything is undone if a
+    /// step fails. Schedulers already dispatched this session are refused, since their
+    /// downloads hold on to the old paths.
+    pub async fn relocate<Fut>(
+        &self,
+        (from, to): (&Path, &Path),
+        (old_temp, new_temp): (&Path, &Path),
+        commit: Fut,
+    ) -> TauriResult<()>
+    where
+        Fut: Future<Output = TauriResult<()>>,
+    {
+        let mut targets = vec![];
+        let mut subtasks = vec![];
+        let doing = self.doing.read().await.clone();
+        for sid in doing {
+            let sch = self.get_scheduler(&sid).await?;
+            let list = sch.list.read().await.clone();
+            let mut unfinished = vec![];
+            for id in &list {
+                let Some(lock) = self.get_task(id).await else {
+                    continue;
+                };
+                let task = lock.read().await;
+                if !task.state.is_terminal() {
+                    unfinished.push((id.clone(), task.subtasks.clone()));
+                }
+            }
+            if unfinished.is_empty() {
+                continue;
+            }
+            if sch.inited.initialized() {
+                return Err(anyhow!(
+                    "Scheduler {sid} is already running, wait for it to finish or restart first"
+                )
+                .into());
+            }
+            subtasks.extend(
+                unfinished
+                    .iter()
+                    .flat_map(|(_, v)| v.iter().map(|t| t.id.clone())),
+            );
+            if let Ok(rest) = sch.folder.strip_prefix(from) {
+                let folder = to.join(rest);
+                let ids = unfinished.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+                targets.push((sch, folder, list, ids));
+            }
+        }
+
+        let mut required = 0;
+        for id in &subtasks {
+            required += dir_size(&old_temp.join(&**id)).await;
+        }
+        fs::create_dir_all(new_temp).await?;
+        check_dir(new_temp, "temp_dir", required).await?;
+
+        let mut moved = vec![];
+        let result = async {
+            for id in &subtasks {
+                let src = old_temp.join(&**id);
+                if !fs::try_exists(&src).await? {
+                    continue;
+                }
+                // aria2 resumes from the moved control file once the gid is re-added
+                aria2c::cancel(id.clone()).await?;
+                let dst = new_temp.join(&**id);
+                move_dir(&src, &dst).await?;
+                moved.push((src, dst));
+            }
+            for (sch, folder, list, _) in &targets {
+                schedulers::upsert(&sch.sid, QueueType::Doing, list, folder, None).await?;
+            }
+            commit.await
+        }
+        .await;
+
+        if let Err(e) = result {
+            log::error!("Failed to relocate storage, rolling back: {e}");
+            for (src, dst) in moved.iter().rev() {
+                if let Err(e) = move_dir(dst, src).await {
+                    log::error!("Failed to move {} back: {e}", dst.display());
+                }
+            }
+            for (sch, _, list, _) in &targets {
+                schedulers::upsert(&sch.sid, QueueType::Doing, list, &sch.folder, None).await?;
+            }
+            return Err(e);
+        }
+
+        for (sch, folder, list, ids) in targets {
+            log::info!("Relocated scheduler {} to {}", sch.sid, folder.display());
+            let next = Scheduler::new(sch.sid.clone(), list, folder.clone());
+            self.schedulers.write().await.insert(sch.sid.clone(), next);
+            for id in ids {
+                let Some(lock) = self.get_task(&id).await else {
+                    continue;
+                };
+                let mut task = lock.write().await;
+                if let Ok(rest) = task.folder.strip_prefix(&sch.folder) {
+                    task.folder = Arc::new(folder.join(rest));
+                    archive::upsert(&task).await?;
+                }
+            }
+        }
+        self.snapshot(true).await?;
+        Ok(())
+    }
+
+    /// Persists tasks whose progress changed since the last flush. On failure the tasks
+    /// not written yet stay marked for the next one.
+    pub async fn flush(&self) -> Result<()> {
+        let dirty = std::mem::take(&mut *self.dirty.lock().await);
+        let mut pending = dirty.into_iter();
+        while let Some(id) = pending.next() {
+            let Some(lock) = self.get_task(&id).await else {
+                continue;
+            };
+            let snapshot = lock.read().await.clone();
+            if let Err(e) = archive::upsert(&snapshot).await {
+                let mut dirty = self.dirty.lock().await;
+                dirty.insert(id);
+                dirty.extend(pending);
+                return Err(e);
+            }
+            self.progress_writes.fetch_add(1, SeqCst);
+        }
+        Ok(())
+    }
+
+    async fn stalled(&self, window: Duration) -> Vec<Arc<String>> {
+        let mut last = self.last_progress.write().await;
+        let list = last
+            .iter()
+            .filter(|(_, ts)| ts.elapsed() >= window)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in &list {
+            last.remove(id);
+        }
+        list
+    }
+
+    async fn handle_stall(&self, id: &Arc<String>, window: Duration) -> TauriResult<()> {
+        let Some(sch) = self.find_scheduler(id).await else {
+            return Ok(());
+        };
+        match sch.get_ctrl(id).await {
+            Some(ctrl) if !ctrl.is_paused() && !ctrl.is_cancelled() => (),
+            _ => return Ok(()),
+        }
+        let retries = {
+            let mut stalls = self.stalls.lock().await;
+            let count = stalls.entry(id.clone()).or_default();
+            *count += 1;
+            *count
+        };
+        let message = format!(
+            "Task {id} stalled: no progress in {}s (attempt {retries}/{MAX_STALL_RETRIES})",
+            window.as_secs()
+        );
+        log::warn!("{message}");
+        QueueEvent::Error {
+            parent: id.clone(),
+            id: None,
+            message: message.clone(),
+            code: None,
+            kind: Some(ErrorKind::Network),
+            params: ErrorParams::from([
+                ("id".into(), id.to_string()),
+                ("timeout".into(), window.as_secs().to_string()),
+                ("attempt".into(), retries.to_string()),
+                ("max".into(), MAX_STALL_RETRIES.to_string()),
+            ]),
+            recovery: Some(Recovery::Retry),
+        }
+        .emit(get_app_handle())?;
+        if retries > MAX_STALL_RETRIES {
+            if let Some(ctrl) = sch.get_ctrl(id).await {
+                ctrl.send(CtrlEvent::Cancel, id);
+            }
+            self.state(id, TaskState::Failed).await?;
+            self.reconnect.on_failed(sch, id.clone(), message).await?;
+            return Ok(());
+        }
+        sch.retry(id).await?;
+        Ok(())
+    }
+
+    async fn overdue(&self, limit: Duration) -> Vec<Arc<String>> {
+        let since = { self.active_since.lock().await.clone() };
+        let mut list = vec![];
+        for (id, at) in since {
+            let Some(task) = self.get_task(&id).await else {
+                continue;
+            };
+            let active = Duration::from_millis(task.read().await.active) + at.elapsed();
+            if active >= limit {
+                list.push(id);
+            }
+        }
+        list
+    }
+
+    /// Fails a task that ran past `max_task_duration`, freeing its slot. Unlike a stall it
+    /// isn't retried, whatever it failed on before.
+    async fn handle_overdue(&self, id: &Arc<String>, limit: Duration) -> TauriResult<()> {
+        let Some(sch) = self.find_scheduler(id).await else {
+            return Ok(());
+        };
+        match sch.get_ctrl(id).await {
+            Some(ctrl) if !ctrl.is_cancelled() => ctrl.send(CtrlEvent::Cancel, id),
+            _ => return Ok(()),
+        }
+        let message = format!(
+            "Task {id} timed out: active for over {}s across its attempts",
+            limit.as_secs()
+        );
+        log::warn!("{message}");
+        if let Some(task) = self.get_task(id).await {
+            task.write().await.recovery = Some(Recovery::Retry);
+        }
+        QueueEvent::Error {
+            parent: id.clone(),
+            id: None,
+            message,
+            code: None,
+            kind: Some(ErrorKind::Timeout),
+            params: ErrorParams::from([
+                ("id".into(), id.to_string()),
+                ("timeout".into(), limit.as_secs().to_string()),
+            ]),
+            recovery: Some(Recovery::Retry),
+        }
+        .emit(get_app_handle())?;
+        self.state(id, TaskState::Failed).await?;
+        Ok(())
+    }
+
+    pub async fn housekeeping(&self, id: &Arc<String>) -> Result<()> {
+        let Some(lock) = self.get_task(id).await else {
+            return Ok(());
+        };
+        let mut task = lock.write().await;
+        let subtasks = task
+            .subtasks
+            .iter()
+            .map(|t| t.id.clone())
+            .collect::<Vec<_>>();
+        if config::read().temp_near_output {
+            naming::clean_temp(&task.folder, &task.subtasks).await;
+        }
+        let temp_root = config::read().temp_dir();
+        for id in subtasks {
+            let dir = temp_root.join(&*id);
+            let _ = tokio::fs::remove_dir_all(dir).await;
+            let _ = crate::services::aria2c::cancel(id.clone()).await;
+            task.status.insert(
+                id,
+                Arc::new(SubTaskStatus {
+                    chunk: 0,
+                    content: 0,
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn pop_task(&self, sid: &Arc<String>, id: &Arc<String>) -> Result<()> {
+        archive::delete(id).await?;
+        self.detach(sid, &[id.clone()]).await?;
+        self.snapshot(true).await?;
+        Ok(())
+    }
+
+    /// Drops `ids` from scheduler `sid` and from memory, and the scheduler with them
+    /// once it's left empty. Their archive rows are the caller's to delete.
+    async fn detach(&self, sid: &Arc<String>, ids: &[Arc<String>]) -> Result<()> {
+        let sch = self.get_scheduler(sid).await?;
+        let mut ctrls = sch.ctrls.write().await;
+        for id in ids {
+            ctrls.remove(id);
+        }
+        drop(ctrls);
+
+        let mut list = sch.list.write().await;
+        list.retain(|v| !ids.contains(v));
+        let snapshot = list.clone();
+        drop(list);
+        drop(sch);
+
+        let mut tasks = self.tasks.write().await;
+        let mut plans = self.plans.write().await;
+        for id in ids {
+            tasks.remove(id);
+            plans.remove(id);
+        }
+        drop((tasks, plans));
+        schedulers::update_list(sid, &snapshot).await?;
+        if snapshot.is_empty() && sid.as_str() != schedulers::WAITING_SID {
+            self.schedulers.write().await.remove(sid);
+            schedulers::delete(sid).await?;
+            for q in [&self.doing, &self.complete, &self.waiting] {
+                let mut guard = q.write().await;
+                if let Some(pos) = guard.iter().position(|v| v.as_str() == sid.as_str()) {
+                    guard.remove(pos);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Cancels the unfinished tasks matching `filter` the way `ctrl_event` does one,
+    /// deleting their rows in one transaction and sending one event for all of them.
+    pub async fn cancel_matching(&self, filter: &TaskFilter) -> Result<Vec<Arc<String>>> {
+        let mut ids = vec![];
+        for (id, lock) in self.tasks.read().await.iter() {
+            let task = lock.read().await;
+            if !task.state.is_terminal() && filter.matches(&task) {
+                ids.push(id.clone());
+            }
+        }
+        let mut groups: HashMap<Arc<String>, Vec<Arc<String>>> = HashMap::new();
+        for id in &ids {
+            if let Some(sch) = self.find_scheduler(id).await {
+                groups.entry(sch.sid.clone()).or_default().push(id.clone());
+            }
+        }
+        let cancelled = groups.values().flatten().cloned().collect::<Vec<_>>();
+        if cancelled.is_empty() {
+            return Ok(cancelled);
+        }
+        for (sid, list) in &groups {
+            let sch = self.get_scheduler(sid).await?;
+            for id in list {
+                if let Some(ctrl) = sch.get_ctrl(id).await {
+                    ctrl.send(CtrlEvent::Cancel, id);
+                }
+                self.cancel_token(id).await;
+                if let Some(lock) = self.get_task(id).await {
+                    lock.write().await.state = TaskState::Cancelled;
+                }
+                self.settle(id, &TaskState::Cancelled).await;
+                self.housekeeping(id).await?;
+            }
+        }
+        let names = cancelled.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        archive::delete_many(&names).await?;
+        for (sid, list) in &groups {
+            self.detach(sid, list).await?;
+        }
+        log::info!("Cancelled {} tasks matching {filter:?}", cancelled.len());
+        QueueEvent::Cancelled {
+            list: cancelled.clone(),
+        }
+        .emit(get_app_handle())?;
+        self.snapshot(true).await?;
+        Ok(cancelled)
+    }
+}
+
+// Concurrency
+
+/// A semaphore whose size can change while permits are out. Growing adds permits,
+/// shrinking swaps in a smaller semaphore and lets the permits out on the old one lapse.
+pub struct ResizableSemaphore {
+    inner: std::sync::Mutex<(usize, Arc<Semaphore>)>,
+}
+
+impl ResizableSemaphore {
+    pub fn new(size: usize) -> Self {
+        Self {
+            inner: std::sync::Mutex::new((size, Arc::new(Semaphore::new(size)))),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).0
+    }
+
+    pub fn resize(&self, size: usize) {
+        use std::cmp::Ordering;
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let (cur, sem) = &mut *guard;
+        match size.cmp(cur) {
+            Ordering::Greater => sem.add_permits(size - *cur),
+            Ordering::Less => *sem = Arc::new(Semaphore::new(size)),
+            Ordering::Equal => (),
+        }
+        *cur = size;
+    }
+
+    /// The current semaphore, to acquire from without holding the lock.
+    pub fn get(&self) -> Arc<Semaphore> {
+        let guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        guard.1.clone()
+    }
+}
+
+// Resolution
+
+pub struct ResolvePool {
+    sem: ResizableSemaphore,
+    backoff: RwLock<Option<Instant>>,
+}
+
+impl ResolvePool {
+    fn new(conc: usize) -> Self {
+        Self {
+            sem: ResizableSemaphore::new(conc.max(1)),
+            backoff: RwLock::new(None),
+        }
+    }
+
+    pub async fn update_conc(&self, new_conc: usize) {
+        self.sem.resize(new_conc.max(1));
+    }
+
+    pub async fn backoff(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut guard = self.backoff.write().await;
+        match *guard {
+            Some(v) if v >= until => (),
+            _ => {
+                log::warn!(
+                    "Resolution paused for {}s due to rate limit",
+                    duration.as_secs()
+                );
+                *guard = Some(until);
+                TASK_MANAGER.ramp.back_off(duration);
+            }
+        }
+    }
+
+    async fn wait_backoff(&self) {
+        loop {
+            let until = *self.backoff.read().await;
+            match until {
+                Some(v) if v > Instant::now() => sleep_until(v).await,
+                _ => break,
+            }
+        }
+    }
+
+    pub async fn run<T, Fut>(&self, fut: Fut) -> TauriResult<T>
+    where
+        Fut: Future<Output = TauriResult<T>>,
+    {
+        let _permit = self.sem.get().acquire_owned().await?;
+        self.wait_backoff().await;
+        let result = fut.await;
+        if let Err(e) = &result {
+            if e.is_rate_limited() {
+                self.backoff(RATE_LIMIT_BACKOFF).await;
+            }
+        }
+        result
+    }
+}
+
+// Post-processing
+
+struct DownloadSlot {
+    attempt: u64,
+    _permit: OwnedSemaphorePermit,
+    _ramp: Option<RampGuard>,
+}
+
+/// Gates FFmpeg work apart from the download slots, so downloads that finish together
+/// mux side by side up to `post_conc` while the slots they left go to the next tasks.
+pub struct PostPool {
+    sem: ResizableSemaphore,
+    busy: AtomicUsize,
+}
+
+/// Held for as long as its FFmpeg job runs.
+pub struct PostPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for PostPermit {
+    fn drop(&mut self) {
+        TASK_MANAGER.post.busy.fetch_sub(1, SeqCst);
+    }
+}
+
+impl PostPool {
+    fn new(conc: usize) -> Self {
+        Self {
+            sem: ResizableSemaphore::new(conc.max(1)),
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    pub async fn update_conc(&self, new_conc: usize) {
+        self.sem.resize(new_conc.max(1));
+    }
+
+    pub async fn acquire(&self) -> TauriResult<PostPermit> {
+        let permit = self.sem.get().acquire_owned().await?;
+        self.busy.fetch_add(1, SeqCst);
+        Ok(PostPermit { _permit: permit })
+    }
+}
+
+// Ramp-up
+
+struct RampState {
+    limit: usize,
+    active: usize,
+    step_at: Instant,
+    last_speed: u64,
+    plateau: bool,
+}
+
+/// Gates how many downloads run at once, below `max_conc`.
+///
+/// With `slow_start` it admits one task, then one more every `RAMP_STEP`. With `auto_conc`
+/// the limit is tuned from throughput instead: it grows by one while that keeps raising
+/// the aggregate speed and holds once it stops. Either way, rate limits and network errors
+/// halve it.
+pub struct Ramp {
+    state: std::sync::Mutex<RampState>,
+    notify: Notify,
+}
+
+pub struct RampGuard(&'static Ramp);
+
+impl Drop for RampGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.state.lock() {
+            state.active = state.active.saturating_sub(1);
+        }
+        self.0.notify.notify_waiters();
+    }
+}
+
+impl Ramp {
+    fn new() -> Self {
+        Self {
+            state: std::sync::Mutex::new(RampState {
+                limit: 1,
+                active: 0,
+                step_at: Instant::now(),
+                last_speed: 0,
+                plateau: false,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    async fn enter(&'static self) -> Result<RampGuard> {
+        loop {
+            let notified = self.notify.notified();
+            let wait = {
+                let mut state = self.state.lock().map_err(|e| anyhow!("{e}"))?;
+                let now = Instant::now();
+                let (min, max) = Self::bounds();
+                if config::read().auto_conc {
+                    state.limit = state.limit.clamp(min, max);
+                    if state.active == 0 {
+                        // A new batch may scale differently
+                        state.plateau = false;
+                        state.last_speed = 0;
+                    }
+                } else if state.active == 0 {
+                    // A new batch starts from a single task again
+                    state.limit = 1;
+                    state.step_at = state.step_at.max(now + RAMP_STEP);
+                } else if now >= state.step_at && state.limit < max {
+                    state.limit += 1;
+                    state.step_at = now + RAMP_STEP;
+                }
+                if state.active < state.limit {
+                    state.active += 1;
+                    return Ok(RampGuard(self));
+                }
+                state.step_at
+            };
+            tokio::select! {
+                _ = notified => (),
+                _ = sleep_until(wait) => (),
+            }
+        }
+    }
+
+    fn back_off(&self, duration: Duration) {
+        let (min, _) = Self::bounds();
+        if let Ok(mut state) = self.state.lock() {
+            state.limit = (state.limit / 2).max(min);
+            state.step_at = Instant::now() + duration;
+            state.plateau = false;
+        }
+    }
+
+    fn enabled() -> bool {
+        let cfg = config::read();
+        cfg.slow_start || cfg.auto_conc
+    }
+
+    fn bounds() -> (usize, usize) {
+        let cfg = config::read();
+        let max = cfg.max_conc.max(1);
+        match cfg.auto_conc {
+            true => (cfg.min_conc.clamp(1, max), max),
+            false => (1, max),
+        }
+    }
+
+    fn limit(&self) -> usize {
+        self.state.lock().map(|v| v.limit).unwrap_or(1)
+    }
+
+    /// One auto-tune step, given the current aggregate speed and whether tasks are
+    /// waiting on the limit.
+    fn tune(&self, speed: u64, saturated: bool) {
+        let (_, max) = Self::bounds();
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if Instant::now() < state.step_at {
+            return;
+        }
+        if !saturated {
+            state.last_speed = speed;
+            return;
+        }
+        // Needs a 5% gain over the previous step to keep growing
+        let gained = speed > state.last_speed + state.last_speed / 20;
+        if state.limit > 1 && !gained {
+            state.plateau = true;
+        }
+        if !state.plateau && state.limit < max {
+            state.limit += 1;
+            log::info!("Auto-tune: concurrency raised to {}", state.limit);
+            self.notify.notify_waiters();
+        }
+        state.last_speed = speed;
+    }
+}
+
+// Auth
+
+#[derive(Default)]
+struct AuthState {
+    refreshing: bool,
+    expired: bool,
+    failed: Vec<(Arc<Scheduler>, Arc<String>)>,
+    paused: Vec<(Arc<String>, Arc<String>)>,
+}
+
+/// Coordinates a single cookie refresh when several tasks fail on expired credentials.
+#[derive(Default)]
+pub struct AuthGuard {
+    state: Mutex<AuthState>,
+}
+
+impl AuthGuard {
+    pub fn is_expired(e: &TauriError) -> bool {
+        e.kind == Some(ErrorKind::AuthExpired)
+            || e.code.map(|v| v.saturating_isize()) == Some(AUTH_EXPIRED_CODE)
+    }
+
+    async fn on_expired(&self, sch: Arc<Scheduler>, id: Arc<String>) {
+        let mut state = self.state.lock().await;
+        state.failed.push((sch, id.clone()));
+        if state.refreshing || state.expired {
+            return;
+        }
+        state.refreshing = true;
+        drop(state);
+        async_runtime::spawn(async move {
+            let _ = TASK_MANAGER
+                .auth
+                .refresh(id)
+                .await
+                .map_err(|e| process_err(e, "auth"));
+        });
+    }
+
+    async fn refresh(&self, id: Arc<String>) -> TauriResult<()> {
+        log::info!("Credentials expired, refreshing cookie");
+        let result = async {
+            let csrf = request_frontend::<String>(id, None, RequestAction::RefreshCsrf).await?;
+            login::refresh_cookie((*csrf).clone()).await
+        }
+        .await;
+        let mut state = self.state.lock().await;
+        state.refreshing = false;
+        match result {
+            Ok(_) => {
+                let failed = std::mem::take(&mut state.failed);
+                drop(state);
+                log::info!("Cookie refreshed, retrying {} tasks", failed.len());
+                for (sch, id) in failed {
+                    sch.retry(&id).await?;
+                }
+            }
+            Err(e) => {
+                log::warn!("Failed to refresh cookie, pausing queue: {e}");
+                state.expired = true;
+                let paused = TASK_MANAGER.pause_running().await?;
+                let list = paused.iter().map(|(id, _)| id.clone()).collect();
+                state.paused.extend(paused);
+                drop(state);
+                QueueEvent::AuthExpired { list }.emit(get_app_handle())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pauses running tasks that need an account after an explicit logout, they resume
+    /// on the next login. The rest carry on as a guest.
+    pub async fn logout(&self) -> TauriResult<Vec<Arc<String>>> {
+        let mut state = self.state.lock().await;
+        state.expired = true;
+        let paused = TASK_MANAGER
+            .pause_running_where(|task| task.select.requires_auth())
+            .await?;
+        let list = paused.iter().map(|(id, _)| id.clone()).collect();
+        state.paused.extend(paused);
+        Ok(list)
+    }
+
+    /// Resumes whatever `refresh` paused or left failed, once the user has logged in again.
+    pub async fn relogin(&self) -> TauriResult<()> {
+        let mut state = self.state.lock().await;
+        if !state.expired {
+            return Ok(());
+        }
+        state.expired = false;
+        let failed = std::mem::take(&mut state.failed);
+        let paused = std::mem::take(&mut state.paused);
+        drop(state);
+        for (id, sid) in paused {
+            let Ok(sch) = TASK_MANAGER.get_scheduler(&sid).await else {
+                continue;
+            };
+            if let Some(ctrl) = sch.get_ctrl(&id).await {
+                ctrl.send(CtrlEvent::Resume, &id);
+                TASK_MANAGER.state(&id, TaskState::Active).await?;
+            }
+        }
+        for (sch, id) in failed {
+            sch.retry(&id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct ReconnectState {
+    failed: Vec<(Arc<Scheduler>, Arc<String>)>,
+    attempts: HashMap<Arc<String>, usize>,
+}
+
+/// Holds tasks that failed on network errors until `network::watch_reachability` sees
+/// the connection come back. Auth and region failures never get here.
+#[derive(Default)]
+pub struct ReconnectGuard {
+    state: Mutex<ReconnectState>,
+}
+
+impl ReconnectGuard {
+    async fn on_failed(&self, sch: Arc<Scheduler>, id: Arc<String>, error: String) -> Result<()> {
+        if !config::read().reconnect_retry {
+            return Ok(());
+        }
+        let mut state = self.state.lock().await;
+        let attempts = state.attempts.entry(id.clone()).or_default();
+        if *attempts < MAX_RECONNECT_RETRIES {
+            *attempts += 1;
+            if !state.failed.iter().any(|(_, v)| *v == id) {
+                state.failed.push((sch, id));
+            }
+            return Ok(());
+        }
+        let retries = *attempts;
+        drop(state);
+        log::warn!("Task {id} keeps failing on network errors, not retrying it again");
+        if let Some(task) = TASK_MANAGER.get_task(&id).await {
+            let mut task = task.write().await;
+            task.exhausted = true;
+            archive::upsert(&task).await?;
+        }
+        QueueEvent::RetriesExhausted {
+            parent: id,
+            retries,
+            error,
+        }
+        .emit(get_app_handle())?;
+        Ok(())
+    }
+
+    /// Gives a task retried by hand its automatic retries back.
+    async fn reset(&self, id: &Arc<String>) {
+        self.state.lock().await.attempts.remove(id);
+        if let Some(task) = TASK_MANAGER.get_task(id).await {
+            task.write().await.exhausted = false;
+        }
+    }
+
+    pub async fn pending(&self) -> bool {
+        !self.state.lock().await.failed.is_empty()
+    }
+
+    /// Retries the held tasks that are still failed, skipping any retried by hand since.
+    pub async fn resume(&self) -> TauriResult<()> {
+        let failed = std::mem::take(&mut self.state.lock().await.failed);
+        let mut list = vec![];
+        for (sch, id) in failed {
+            let Some(task) = TASK_MANAGER.get_task(&id).await else {
+                continue;
+            };
+            if task.read().await.state != TaskState::Failed {
+                continue;
+            }
+            // One task that can't be retried doesn't keep the rest held
+            if let Err(e) = sch.retry(&id).await {
+                log::warn!("Failed to retry task {id} after reconnecting: {e:#}");
+                continue;
+            }
+            list.push(id);
+        }
+        if list.is_empty() {
+            return Ok(());
+        }
+        log::info!("Connection restored, retried tasks: {list:?}");
+        QueueEvent::Reconnected { list }.emit(get_app_handle())?;
+        Ok(())
+    }
+}
+
+// Scheduler
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Type)]
 #[serde(rename_all = "lowercase")]
@@ -481,6 +2180,22 @@ impl Scheduler {
         ctrls.get(id).cloned()
     }
 
+    async fn retry(self: &Arc<Self>, id: &Arc<String>) -> Result<()> {
+        if let Some(ctrl) = self.get_ctrl(id).await {
+            ctrl.send(CtrlEvent::Retry, id);
+            // The run it stops checks its own entry, not the one replacing it
+            ctrl.cancelled.store(true, SeqCst);
+        }
+        TASK_MANAGER.state(id, TaskState::Active).await?;
+        TASK_MANAGER.housekeeping(id).await?;
+        self.ctrls
+            .write()
+            .await
+            .insert(id.clone(), Arc::new(CtrlEntry::new()));
+        self.clone().handle_task_spawned(id);
+        Ok(())
+    }
+
     pub fn dispatch_spawned(self: Arc<Self>) {
         let name = format!("Scheduler #{}", self.sid);
         log::info!("{name} restored via database");
@@ -490,6 +2205,8 @@ impl Scheduler {
     }
 
     async fn dispatch(self: Arc<Self>) -> TauriResult<()> {
+        // Marks schedulers started through `process_queue` too, not only restored ones
+        let _ = self.inited.set(());
         let list = { self.list.read().await.clone() };
         let mut handles = Vec::with_capacity(list.len());
         for id in list {
@@ -524,30 +2241,90 @@ impl Scheduler {
         let Some(task) = TASK_MANAGER.get_task(id).await else {
             return Ok(());
         };
-        let sem = { TASK_MANAGER.sem.read().await.clone() };
-        let permit = sem.acquire_owned().await;
-        let _permit = permit;
+        // Taken now, a retry replaces the entry of the task while this run winds down
+        let ctrl = self.get_ctrl(id).await;
+        let cancelled = || ctrl.as_ref().is_some_and(|c| c.is_cancelled());
+        power::wait_quiet().await;
+        let permit = TASK_MANAGER.sem.get().acquire_owned().await?;
+        let ramp = match Ramp::enabled() {
+            true => Some(TASK_MANAGER.ramp.enter().await?),
+            false => None,
+        };
 
         TASK_MANAGER.state(id, TaskState::Active).await?;
-        match handlers::handle_task(self, task).await {
+        let started = Instant::now();
+        {
+            let mut task = task.write().await;
+            task.timings = Timings::default();
+            task.recovery = None;
+        }
+        let attempt = TASK_MANAGER.take_slot(id, permit, ramp);
+        let result = handlers::handle_task(self.clone(), task.clone()).await;
+        TASK_MANAGER.end_slot(id, attempt);
+        match result {
+            // Whatever a cancelled or superseded run ended with, it no longer decides
+            _ if cancelled() => (),
             Ok(_) => {
                 log::info!("task {} successfully completed", id.clone());
+                let timings = {
+                    let mut task = task.write().await;
+                    task.timings.finish(started.elapsed());
+                    task.timings.clone()
+                };
+                QueueEvent::Timings {
+                    parent: id.clone(),
+                    timings,
+                }
+                .emit(get_app_handle())?;
                 TASK_MANAGER.state(id, TaskState::Completed).await?;
             }
             Err(e) => {
                 log::error!("task {} failed: {e:#}", id.clone());
+                if e.is_rate_limited() {
+                    TASK_MANAGER.resolve.backoff(RATE_LIMIT_BACKOFF).await;
+                } else if e.kind == Some(ErrorKind::Network) {
+                    TASK_MANAGER.ramp.back_off(TUNE_INTERVAL);
+                }
+                let expired = AuthGuard::is_expired(&e);
+                let network = e.kind == Some(ErrorKind::Network);
+                let recovery = e.recovery();
+                task.write().await.recovery = Some(recovery);
+                let last = e.message.clone();
                 let app = get_app_handle();
                 QueueEvent::Error {
                     parent: id.clone(),
                     id: None,
                     message: format!("Task {id} failed: \n{}", e.message),
                     code: e.code.map(|v| v.saturating_isize()),
+                    kind: e.kind,
+                    params: e.params,
+                    recovery: Some(recovery),
                 }
                 .emit(app)?;
                 TASK_MANAGER.state(id, TaskState::Failed).await?;
+                if expired && config::read().auth_retry {
+                    TASK_MANAGER.auth.on_expired(self.clone(), id.clone()).await;
+                } else if network {
+                    TASK_MANAGER
+                        .reconnect
+                        .on_failed(self.clone(), id.clone(), last)
+                        .await?;
+                }
             }
         }
-        Ok(())
+        post::run_collection(&self, id).await
+    }
+
+    /// Whether every task of the scheduler has reached a terminal state.
+    pub async fn finished(&self) -> bool {
+        let list = self.list.read().await.clone();
+        for id in list {
+            match TASK_MANAGER.get_task(&id).await {
+                Some(task) if !task.read().await.state.is_terminal() => return false,
+                _ => (),
+            }
+        }
+        true
     }
 
     pub async fn try_join<F, Fut>(
@@ -566,10 +2343,10 @@ impl Scheduler {
         let mut rx = ctrl.tx.subscribe();
         let task_rx = ctrl.tx.subscribe();
         if let Err(e) = func(task_rx).await {
-            return Err(TauriError::new(
-                format!("SubTask {id} failed: \n{}", e.message),
-                e.code,
-            ));
+            return Err(TauriError {
+                message: format!("SubTask {id} failed: \n{}", e.message),
+                ..e
+            });
         }
         loop {
             if !ctrl.is_paused() || ctrl.is_cancelled() {
@@ -599,6 +2376,7 @@ pub enum RequestAction {
     GetAISummary,
     GetOpusContent,
     GetOpusImages,
+    RefreshCsrf,
 }
 
 impl RequestAction {
@@ -615,22 +2393,52 @@ impl RequestAction {
             RequestAction::GetAISummary => "getAISummary",
             RequestAction::GetOpusContent => "getOpusContent",
             RequestAction::GetOpusImages => "getOpusImages",
+            RequestAction::RefreshCsrf => "refreshCsrf",
         }
     }
 }
 
+/// What the frontend sends back when a request failed on its side.
+#[derive(Deserialize)]
+struct FrontendFailure {
+    message: String,
+    /// An HTTP status or Bilibili's code, a number or a numeric string.
+    #[serde(default)]
+    code: Option<serde_json::Value>,
+}
+
+impl From<FrontendFailure> for TauriError {
+    fn from(value: FrontendFailure) -> Self {
+        let code = value.code.and_then(|v| match v {
+            serde_json::Value::Number(v) => v.as_i64(),
+            serde_json::Value::String(v) => v.parse().ok(),
+            _ => None,
+        });
+        // Play urls for queued tasks are fetched there, so region locks and the like
+        // only get their kind here
+        TauriError::new(value.message, code).classify()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FrontendReply<T> {
+    Failed { failed: FrontendFailure },
+    Answer(Option<T>),
+}
+
 pub async fn request_frontend<T: DeserializeOwned + Send + 'static>(
     parent: Arc<String>,
     subtask: Option<Arc<String>>,
     action: RequestAction,
-) -> Result<Arc<T>> {
-    let (tx, rx) = oneshot::channel::<Result<Option<T>>>();
+) -> TauriResult<Arc<T>> {
+    let (tx, rx) = oneshot::channel::<Result<FrontendReply<T>>>();
     let app = get_app_handle();
     let id = if let Some(id) = &subtask { id } else { &parent };
     let endpoint = format!("{}_{}", action.as_str(), id);
     app.once(endpoint, move |event| {
         let _ = tx.send(
-            serde_json::from_str::<Option<T>>(event.payload())
+            serde_json::from_str::<FrontendReply<T>>(event.payload())
                 .context("Failed to deserialize frontend response"),
         );
     });
@@ -640,13 +2448,102 @@ pub async fn request_frontend<T: DeserializeOwned + Send + 'static>(
         action,
     }
     .emit(app)?;
-    let result = rx.await.context("No response from frontend")??;
+    let result = match rx.await.context("No response from frontend")?? {
+        FrontendReply::Failed { failed } => return Err(failed.into()),
+        FrontendReply::Answer(v) => v,
+    };
 
     Ok(Arc::new(
         result.ok_or(anyhow!("Error occurred from frontend"))?,
     ))
 }
 
+pub async fn request_resolve<T: DeserializeOwned + Send + 'static>(
+    parent: Arc<String>,
+    subtask: Option<Arc<String>>,
+    action: RequestAction,
+) -> TauriResult<Arc<T>> {
+    TASK_MANAGER
+        .resolve
+        .run(request_frontend::<T>(parent, subtask, action))
+        .await
+}
+
+pub async fn flusher() -> TauriResult<()> {
+    #[cfg(debug_assertions)]
+    let mut last_report = Instant::now();
+    let mut last_stats = QueueStats::default();
+    loop {
+        sleep(FLUSH_INTERVAL).await;
+        // A locked database or a closed window only costs this round
+        if let Err(e) = TASK_MANAGER.flush().await {
+            log::warn!("Failed to flush task progress: {e:#}");
+        }
+        if let Err(e) = TASK_MANAGER.flush_progress().await {
+            log::warn!("Failed to send throttled progress: {e:#}");
+        }
+        let stats = TASK_MANAGER.stats().await;
+        if (stats.active > 0 || last_stats.active > 0) && stats != last_stats {
+            if let Err(e) = QueueEvent::Stats(stats.clone()).emit(get_app_handle()) {
+                log::warn!("Failed to send queue stats: {e}");
+            }
+        }
+        last_stats = stats;
+        #[cfg(debug_assertions)]
+        if last_report.elapsed() >= Duration::from_secs(60) {
+            last_report = Instant::now();
+            let updates = TASK_MANAGER.progress_updates.load(SeqCst);
+            let writes = TASK_MANAGER.progress_writes.load(SeqCst);
+            if updates > 0 {
+                log::info!(
+                    "Archive progress writes: {writes} for {updates} updates ({} saved)",
+                    updates.saturating_sub(writes)
+                );
+            }
+        }
+    }
+}
+
+pub async fn tuner() -> TauriResult<()> {
+    loop {
+        sleep(TUNE_INTERVAL).await;
+        if !config::read().auto_conc {
+            continue;
+        }
+        let stats = TASK_MANAGER.stats().await;
+        // Tasks muxing after handing their slot off don't count against it
+        let saturated = stats.downloading >= stats.concurrency && TASK_MANAGER.queued().await > 0;
+        TASK_MANAGER.ramp.tune(stats.speed, saturated);
+    }
+}
+
+pub async fn watchdog() -> TauriResult<()> {
+    loop {
+        sleep(WATCHDOG_INTERVAL).await;
+        let (timeout, limit) = {
+            let config = config::read();
+            (config.stall_timeout, config.max_task_duration)
+        };
+        if limit > 0 {
+            let limit = Duration::from_secs(limit);
+            for id in TASK_MANAGER.overdue(limit).await {
+                if let Err(e) = TASK_MANAGER.handle_overdue(&id, limit).await {
+                    log::warn!("Failed to time out task {id}: {}", e.message);
+                }
+            }
+        }
+        if timeout == 0 {
+            continue;
+        }
+        let window = Duration::from_secs(timeout);
+        for id in TASK_MANAGER.stalled(window).await {
+            if let Err(e) = TASK_MANAGER.handle_stall(&id, window).await {
+                log::warn!("Failed to handle stalled task {id}: {}", e.message);
+            }
+        }
+    }
+}
+
 // Commands
 
 #[tauri::command(async)]
@@ -667,9 +2564,131 @@ pub async fn process_queue(sid: Arc<String>, folder: String) -> TauriResult<()>
 
 #[tauri::command(async)]
 #[specta::specta]
-pub async fn submit_task(task: Task) -> TauriResult<()> {
+pub async fn submit_task(mut task: Task) -> TauriResult<Arc<String>> {
+    if let Some(overrides) = presets::overrides_for(&task) {
+        let labels = std::mem::take(&mut task.labels);
+        task = task.duplicate(task.id.clone(), task.seq, &overrides);
+        task.labels = labels;
+    }
+    if task.select.media.music_album {
+        let media = &mut Arc::make_mut(&mut task.select).media;
+        media.audio = true;
+        media.video = false;
+        media.audio_video = false;
+    }
+    if let Some(clip) = &task.clip {
+        clip.check(task.item.duration.as_f64().unwrap_or_default())?;
+        if !ffmpeg::available() {
+            return Err(anyhow!("FFmpeg is unavailable, can't cut a clip").into());
+        }
+    }
+    Ok(TASK_MANAGER.submit(task).await?)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn duplicate_task(id: Arc<String>, overrides: TaskOverrides) -> TauriResult<Arc<String>> {
+    let task = TASK_MANAGER
+        .get_task(&id)
+        .await
+        .ok_or(anyhow!("No task found for {id}"))?;
+    let seq = TASK_MANAGER
+        .get_scheduler(&Arc::new(schedulers::WAITING_SID.into()))
+        .await?
+        .list
+        .read()
+        .await
+        .len();
+    let new_id = Arc::new(random_string(8));
+    let task = task.read().await.duplicate(new_id.clone(), seq, &overrides);
+    log::info!("Duplicated task {id} as {new_id} with {overrides:?}");
+    // Skip the dedup in `submit`: the caller explicitly asked for another copy.
     TASK_MANAGER.push_pending(task).await?;
-    Ok(())
+    Ok(new_id)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueueFile {
+    version: u32,
+    exported_at: i64,
+    // Kept loose so one task an older or newer version can't read doesn't fail the rest
+    tasks: Vec<serde_json::Value>,
+}
+
+/// Writes every task that hasn't finished, failed or been cancelled to `path`, for
+/// `queue_import` on another machine. Prefetched stream urls expire and output paths
+/// are local, so neither is kept.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn queue_export(path: PathBuf) -> TauriResult<usize> {
+    let mut list = vec![];
+    for lock in TASK_MANAGER.tasks.read().await.values() {
+        let task = lock.read().await;
+        if !task.state.is_terminal() {
+            let mut task = task.clone();
+            task.prefetched = None;
+            task.output = None;
+            list.push(task);
+        }
+    }
+    list.sort_by_key(|v| (v.ts, v.seq));
+    let file = QueueFile {
+        version: QUEUE_FILE_VERSION,
+        exported_at: get_ts(true),
+        tasks: list
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()?,
+    };
+    fs::write(&path, serde_json::to_vec_pretty(&file)?).await?;
+    log::info!("Exported {} queued tasks to {}", list.len(), path.display());
+    Ok(list.len())
+}
+
+/// Queues the tasks of a `queue_export` file as new pending tasks, with their labels.
+/// Urls are resolved again once they start. Tasks matching one already queued are
+/// skipped the same way `submit_task` collapses them.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn queue_import(path: PathBuf) -> TauriResult<QueueImport> {
+    let file: QueueFile = serde_json::from_slice(&fs::read(&path).await?)
+        .with_context(|| format!("{} isn't a queue export", path.display()))?;
+    if file.version > QUEUE_FILE_VERSION {
+        return Err(anyhow!(
+            "Queue export version {} is newer than this app supports",
+            file.version
+        )
+        .into());
+    }
+    let waiting = TASK_MANAGER
+        .get_scheduler(&Arc::new(schedulers::WAITING_SID.into()))
+        .await?;
+    let mut report = QueueImport::default();
+    for value in file.tasks {
+        let task: Task = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Skipping an unreadable task of {}: {e}", path.display());
+                report.invalid += 1;
+                continue;
+            }
+        };
+        let seq = waiting.list.read().await.len();
+        let new_id = Arc::new(random_string(8));
+        let mut new = task.duplicate(new_id.clone(), seq, &TaskOverrides::default());
+        new.labels = task.labels.clone();
+        if TASK_MANAGER.submit(new).await? != new_id {
+            report.duplicates += 1;
+            continue;
+        }
+        if !task.labels.is_empty() {
+            labels::set(&new_id, &task.labels).await?;
+        }
+        report.imported += 1;
+    }
+    log::info!("Imported queue from {}: {report:?}", path.display());
+    Ok(report)
 }
 
 #[tauri::command(async)]
@@ -687,6 +2706,90 @@ pub async fn open_folder(sid: Arc<String>, id: Option<Arc<String>>) -> TauriResu
     Ok(())
 }
 
+/// One page of the archive, for lists too long to take from a full snapshot. The rows
+/// pick and order the page, the tasks themselves come from memory as they may be
+/// ahead of what was last written.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_tasks(mut query: TaskQuery) -> TauriResult<TaskPage> {
+    query.limit = query.limit.clamp(1, MAX_PAGE);
+    let (ids, total) = archive::page(&query).await?;
+    let map = TASK_MANAGER.tasks.read().await;
+    let mut tasks = Vec::with_capacity(ids.len());
+    for id in ids {
+        if let Some(task) = map.get(&Arc::new(id)) {
+            tasks.push(Arc::new(task.read().await.clone()));
+        }
+    }
+    Ok(TaskPage { total, tasks })
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_task_detail(id: Arc<String>) -> TauriResult<TaskDetail> {
+    let task = TASK_MANAGER
+        .get_task(&id)
+        .await
+        .ok_or(anyhow!("No task found for {id}"))?;
+    let detail = TaskDetail::from(&*task.read().await);
+    Ok(detail)
+}
+
+/// Points a task at its output file after it was moved by hand, so revealing and opening
+/// it work again.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn relink_file(id: Arc<String>, new_path: PathBuf) -> TauriResult<()> {
+    let meta = fs::metadata(&new_path)
+        .await
+        .with_context(|| format!("{} doesn't exist", new_path.display()))?;
+    if !meta.is_file() {
+        return Err(anyhow!("{} is not a file", new_path.display()).into());
+    }
+    TASK_MANAGER.relink(&id, new_path).await?;
+    Ok(())
+}
+
+/// Scans `down_dir` for the files of completed tasks whose output went missing, returning
+/// the tasks relinked.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn relink_orphans() -> TauriResult<Vec<Arc<String>>> {
+    let root = config::read().down_dir.clone();
+    Ok(TASK_MANAGER.relink_orphans(root).await?)
+}
+
+/// Selects the completed task's main file in the system file manager, or opens its
+/// folder where selecting isn't supported.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn reveal_file(id: Arc<String>) -> TauriResult<()> {
+    let task = TASK_MANAGER
+        .get_task(&id)
+        .await
+        .ok_or(anyhow!("No task found for {id}"))?;
+    let path = task
+        .read()
+        .await
+        .output
+        .clone()
+        .ok_or(anyhow!("Task {id} has no output file"))?;
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        return Err(anyhow!("{} was moved or deleted", path.display()).into());
+    }
+    if let Err(e) = tauri_plugin_opener::reveal_item_in_dir(&path) {
+        log::warn!(
+            "Failed to reveal {}, opening its folder: {e}",
+            path.display()
+        );
+        let folder = path
+            .parent()
+            .ok_or(anyhow!("No parent for {}", path.display()))?;
+        tauri_plugin_opener::open_path(folder, None::<&str>)?;
+    }
+    Ok(())
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn ctrl_event(
@@ -699,9 +2802,18 @@ pub async fn ctrl_event(
         sch.clone().dispatch_spawned();
     }
     for id in list {
+        if event == CtrlEvent::Retry {
+            TASK_MANAGER.reconnect.reset(&id).await;
+            TASK_MANAGER.rewind(&id).await;
+            sch.retry(&id).await?;
+            continue;
+        }
         if let Some(ctrl) = sch.get_ctrl(&id).await {
             ctrl.send(event.clone(), &id);
         }
+        if event == CtrlEvent::Cancel {
+            TASK_MANAGER.cancel_token(&id).await;
+        }
         TASK_MANAGER
             .state(
                 &id,
@@ -712,16 +2824,6 @@ pub async fn ctrl_event(
                 },
             )
             .await?;
-        if event == CtrlEvent::Retry {
-            TASK_MANAGER.housekeeping(&id).await?;
-            let id = id.clone();
-            sch.ctrls
-                .write()
-                .await
-                .insert(id.clone(), Arc::new(CtrlEntry::new()));
-            let sch_clone = sch.clone();
-            sch_clone.handle_task_spawned(&id);
-        }
         if event == CtrlEvent::Cancel {
             TASK_MANAGER.housekeeping(&id).await?;
             TASK_MANAGER.pop_task(&sid, &id).await?;
@@ -730,6 +2832,287 @@ pub async fn ctrl_event(
     Ok(())
 }
 
+/// Bulk counterpart of cancelling through `ctrl_event`, returns how many were cancelled.
+/// Finished, failed and already cancelled tasks are left alone.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn cancel_matching(filter: TaskFilter) -> TauriResult<usize> {
+    Ok(TASK_MANAGER.cancel_matching(&filter).await?.len())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn select_audio(
+    parent: Arc<String>,
+    available: Vec<usize>,
+) -> TauriResult<Option<usize>> {
+    let prefs = config::read().format.audio_quality.clone();
+    let Some(abr) = AudioQuality::pick(&prefs, &available) else {
+        return Ok(None);
+    };
+    let chosen = AudioQuality::from_id(abr);
+    if let Some(wanted) = prefs.first().copied() {
+        if chosen != Some(wanted) {
+            log::info!(
+                "Task {parent}: {wanted:?} unavailable{}, using abr {abr}",
+                if wanted.requires_vip() {
+                    " (requires VIP)"
+                } else {
+                    ""
+                }
+            );
+            QueueEvent::AudioFallback {
+                parent,
+                wanted,
+                chosen,
+                abr,
+            }
+            .emit(get_app_handle())?;
+        }
+    }
+    Ok(Some(abr))
+}
+
+/// Caps the task's requested video quality to what the account's membership tier can
+/// play, recording both on the task. Fails instead when `strict_quality` is set.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn select_resolution(parent: Arc<String>, available: Vec<usize>) -> TauriResult<usize> {
+    let Some(lock) = TASK_MANAGER.get_task(&parent).await else {
+        return Err(anyhow!("No task with id {parent} found").into());
+    };
+    let select = lock.read().await.select.clone();
+    let tier = bili::get_member_tier().await?;
+    let Some(top) = available.iter().copied().max() else {
+        return Err(anyhow!("No video streams available for {parent}").into());
+    };
+    let wanted = select.requested_res.or(select.res).unwrap_or(top);
+    // Best stream at or below `limit`, or the lowest one if none qualifies
+    let pick = |limit: usize| {
+        let below = available.iter().copied().filter(|v| *v <= limit);
+        below
+            .max()
+            .or_else(|| available.iter().copied().min())
+            .unwrap_or(top)
+    };
+    let unrestricted = pick(wanted);
+    let chosen = pick(wanted.min(tier.max_res()));
+    if chosen != unrestricted {
+        fall_back(&parent, &select, wanted, chosen, tier).await?;
+    }
+    frame_rate(&parent, &available, chosen, tier).await
+}
+
+// Quality ids of the 60fps variants, by the id of the same resolution at standard fps.
+// 4K and above have no separate id, their streams can be either.
+const HFR_VARIANTS: [(usize, usize); 2] = [(64, 74), (80, 116)];
+
+fn is_hfr(res: usize) -> bool {
+    HFR_VARIANTS.iter().any(|(_, hfr)| *hfr == res)
+}
+
+/// Swaps `chosen` for its 60fps variant when `prefer_hfr` asks for it, noting when there
+/// is none. Either way the task records which one it got.
+async fn frame_rate(
+    parent: &Arc<String>,
+    available: &[usize],
+    mut chosen: usize,
+    tier: MemberTier,
+) -> TauriResult<usize> {
+    if config::read().prefer_hfr && !is_hfr(chosen) {
+        let variant = HFR_VARIANTS
+            .iter()
+            .find(|(res, _)| *res == chosen)
+            .map(|(_, hfr)| *hfr)
+            // 720P60 only needs a login, 1080P60 needs VIP
+            .filter(|v| available.contains(v) && *v <= tier.max_res());
+        match variant {
+            Some(v) => chosen = v,
+            None => {
+                log::info!("Task {parent}: no 60fps variant of {chosen}");
+                QueueEvent::HfrUnavailable {
+                    parent: parent.clone(),
+                    res: chosen,
+                }
+                .emit(get_app_handle())?;
+            }
+        }
+    }
+    if let Some(lock) = TASK_MANAGER.get_task(parent).await {
+        let mut task = lock.write().await;
+        task.hfr = Some(is_hfr(chosen));
+        archive::upsert(&task).await?;
+    }
+    Ok(chosen)
+}
+
+/// Handles `wanted` being above what `tier` may play, failing under `strict_quality` and
+/// settling for `chosen` otherwise.
+async fn fall_back(
+    parent: &Arc<String>,
+    select: &PopupSelect,
+    wanted: usize,
+    chosen: usize,
+    tier: MemberTier,
+) -> TauriResult<()> {
+    if config::read().strict_quality {
+        return Err(TauriError::new(
+            format!("Quality {wanted} is not available to {tier:?} accounts"),
+            None::<isize>,
+        )
+        .with_kind(ErrorKind::QualityUnavailable)
+        .with_param("wanted", wanted.to_string())
+        .with_param("chosen", chosen.to_string()));
+    }
+    log::info!("Task {parent}: quality {wanted} unavailable to {tier:?}, using {chosen}");
+    let mut next = select.clone();
+    next.res = Some(chosen);
+    next.requested_res = Some(wanted);
+    TASK_MANAGER.update_select(parent, Arc::new(next)).await?;
+    QueueEvent::QualityFallback {
+        parent: parent.clone(),
+        wanted,
+        chosen,
+        tier,
+    }
+    .emit(get_app_handle())?;
+    Ok(())
+}
+
+/// Points `down_dir` and `temp_dir` at new locations. With `move_existing`, in-flight temp
+/// files and output folders of unfinished tasks follow; completed files stay where they are.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn relocate_storage(
+    new_down_dir: PathBuf,
+    new_temp_dir: PathBuf,
+    move_existing: bool,
+) -> TauriResult<()> {
+    check_dir(&new_down_dir, "down_dir", 0).await?;
+    check_dir(&new_temp_dir, "temp_dir", 0).await?;
+    let settings = serde_json::Map::from_iter([
+        ("down_dir".into(), serde_json::to_value(&new_down_dir)?),
+        ("temp_dir".into(), serde_json::to_value(&new_temp_dir)?),
+    ]);
+    if !move_existing {
+        config::write(settings).await?;
+        return Ok(());
+    }
+    let cfg = config::read();
+    let new_temp = config::Settings::temp_root(&new_temp_dir);
+    TASK_MANAGER
+        .relocate(
+            (&cfg.down_dir, &new_down_dir),
+            (&cfg.temp_dir(), &new_temp),
+            async {
+                config::write(settings).await?;
+                Ok(())
+            },
+        )
+        .await
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn queue_stats() -> TauriResult<QueueStats> {
+    Ok(TASK_MANAGER.stats().await)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn repair_archive() -> TauriResult<RepairReport> {
+    Ok(TASK_MANAGER.repair().await?)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn archive_delete(id: Arc<String>) -> TauriResult<()> {
+    TASK_MANAGER.forget_task(&id).await?;
+    Ok(())
+}
+
+/// Resolves `ids` now so they can start later, offline or after a reconnect, without
+/// asking the frontend again. Returns the tasks that were resolved, the others are left
+/// to resolve when they start.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn prefetch_resolution(ids: Vec<Arc<String>>) -> TauriResult<Vec<Arc<String>>> {
+    let mut list = vec![];
+    for id in ids {
+        let Some(lock) = TASK_MANAGER.get_task(&id).await else {
+            continue;
+        };
+        let (terminal, with_urls) = {
+            let task = lock.read().await;
+            (task.state.is_terminal(), task.select.media.any_true())
+        };
+        if terminal {
+            continue;
+        }
+        let resolved = match handlers::resolve_media(&id, with_urls).await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to prefetch {id}: {e}");
+                continue;
+            }
+        };
+        let mut task = lock.write().await;
+        task.prefetched = Some(resolved);
+        archive::upsert(&task).await?;
+        list.push(id);
+    }
+    Ok(list)
+}
+
+/// Trimmed, without empty or repeated labels.
+fn clean_labels(labels: Vec<String>) -> Vec<String> {
+    let mut list: Vec<String> = vec![];
+    for label in labels.iter().map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        if !list.iter().any(|v| v == label) {
+            list.push(label.into());
+        }
+    }
+    list
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn label_add(ids: Vec<Arc<String>>, labels: Vec<String>) -> TauriResult<()> {
+    Ok(TASK_MANAGER
+        .relabel(&ids, &clean_labels(labels), &[])
+        .await?)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn label_remove(ids: Vec<Arc<String>>, labels: Vec<String>) -> TauriResult<()> {
+    Ok(TASK_MANAGER
+        .relabel(&ids, &[], &clean_labels(labels))
+        .await?)
+}
+
+/// Tasks carrying all of `labels`, or every task when none are given.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn find_by_labels(labels: Vec<String>) -> TauriResult<Vec<Arc<String>>> {
+    let labels = clean_labels(labels);
+    if labels.is_empty() {
+        return Ok(TASK_MANAGER.tasks.read().await.keys().cloned().collect());
+    }
+    Ok(labels::find(&labels)
+        .await?
+        .into_iter()
+        .map(Arc::new)
+        .collect())
+}
+
+/// Labels in use with the number of tasks each, for the history view's filter.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn label_counts() -> TauriResult<BTreeMap<String, usize>> {
+    Ok(labels::counts().await?)
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn update_max_conc(new_conc: usize) -> TauriResult<()> {
@@ -739,9 +3122,192 @@ pub async fn update_max_conc(new_conc: usize) -> TauriResult<()> {
 
 #[tauri::command(async)]
 #[specta::specta]
-pub async fn update_select(id: Arc<String>, select: Arc<PopupSelect>) -> TauriResult<()> {
-    if let Some(task) = TASK_MANAGER.get_task(&id).await {
-        task.write().await.select = select;
-    }
+pub async fn update_resolve_conc(new_conc: usize) -> TauriResult<()> {
+    TASK_MANAGER.resolve.update_conc(new_conc).await;
+    Ok(())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn update_post_conc(new_conc: usize) -> TauriResult<()> {
+    TASK_MANAGER.post.update_conc(new_conc).await;
     Ok(())
 }
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn update_select(
+    id: Arc<String>,
+    select: Arc<PopupSelect>,
+) -> TauriResult<Option<Arc<SelectPlan>>> {
+    Ok(TASK_MANAGER.update_select(&id, select).await?)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_select_plan(sid: Arc<String>) -> TauriResult<Vec<Arc<SelectPlan>>> {
+    let sch = TASK_MANAGER.get_scheduler(&sid).await?;
+    // Side by side, the resolution pool bounds how many qualities are probed at once
+    let handles: Vec<_> = sch
+        .list
+        .read()
+        .await
+        .iter()
+        .cloned()
+        .map(|id| async_runtime::spawn(async move { TASK_MANAGER.get_plan_available(&id).await }))
+        .collect();
+    let mut plans = Vec::with_capacity(handles.len());
+    for handle in handles {
+        if let Some(plan) = handle.await? {
+            plans.push(plan);
+        }
+    }
+    Ok(plans)
+}
+
+/// Page `page` (from 1) of a favorite folder, uploader or collection to pick from, with
+/// the items already queued marked and their plans. Only the first call for a page
+/// resolves it, selecting through `update_select` doesn't ask again.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn get_select_children(
+    kind: SubscriptionKind,
+    target: String,
+    page: usize,
+) -> TauriResult<SelectChildren> {
+    let children = TASK_MANAGER.children(kind, &target, page.max(1)).await?;
+    let mut queued = HashMap::new();
+    for (id, lock) in TASK_MANAGER.tasks.read().await.iter() {
+        if let Some(bvid) = &lock.read().await.item.bvid {
+            queued.entry(bvid.clone()).or_insert(id.clone());
+        }
+    }
+    let mut items = Vec::with_capacity(children.0.len());
+    for item in &children.0 {
+        let plan = match queued.get(&item.bvid) {
+            Some(id) => TASK_MANAGER.get_plan_available(id).await,
+            None => None,
+        };
+        items.push(SelectChild {
+            item: item.clone(),
+            selected: queued.contains_key(&item.bvid),
+            plan,
+        });
+    }
+    Ok(SelectChildren {
+        items,
+        more: children.1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::types::fixtures;
+
+    #[tokio::test]
+    async fn concurrent_identical_submits_make_one_task() {
+        let manager = &TaskManager::new();
+        let push = move |task: Task| async move {
+            // Long enough for the other submit to arrive while this one is in flight
+            sleep(Duration::from_millis(50)).await;
+            let id = task.id.clone();
+            manager
+                .tasks
+                .write()
+                .await
+                .insert(id, Arc::new(RwLock::new(task)));
+            Ok(())
+        };
+        let (a, b) = tokio::join!(
+            manager.submit_with(fixtures::task("a", "BV1xx411c7mD"), push),
+            manager.submit_with(fixtures::task("b", "BV1xx411c7mD"), push),
+        );
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(manager.tasks.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn submit_after_queued_returns_existing_task() {
+        let manager = &TaskManager::new();
+        let push = move |task: Task| async move {
+            let id = task.id.clone();
+            manager
+                .tasks
+                .write()
+                .await
+                .insert(id, Arc::new(RwLock::new(task)));
+            Ok(())
+        };
+        let first = manager
+            .submit_with(fixtures::task("a", "BV1xx411c7mD"), push)
+            .await
+            .unwrap();
+        let again = manager
+            .submit_with(fixtures::task("b", "BV1xx411c7mD"), push)
+            .await
+            .unwrap();
+        let other = manager
+            .submit_with(fixtures::task("c", "BV1yy411c7mE"), push)
+            .await
+            .unwrap();
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(manager.tasks.read().await.len(), 2);
+    }
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("bilitools-{}", random_string(8)))
+    }
+
+    #[tokio::test]
+    async fn two_tasks_on_one_name_place_two_files() {
+        let dir = scratch();
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("video.mp4");
+        // Both have resolved their output before either finishes downloading
+        let resolved = Arc::new(tokio::sync::Barrier::new(2));
+        let run = |owner: &str| {
+            let (dir, path, resolved) = (dir.clone(), path.clone(), resolved.clone());
+            let owner = Arc::new(owner.to_string());
+            tokio::spawn(async move {
+                let src = dir.join(format!("{owner}.download"));
+                fs::write(&src, owner.as_bytes()).await.unwrap();
+                let dst = crate::shared::get_unique_path(&owner, path);
+                resolved.wait().await;
+                crate::shared::move_file(&src, &dst).await.unwrap();
+                (owner, dst)
+            })
+        };
+        let (a, b) = tokio::join!(run("two-tasks-a"), run("two-tasks-b"));
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_ne!(a.1, b.1);
+        for (owner, dst) in [a, b] {
+            assert_eq!(fs::read_to_string(&dst).await.unwrap(), *owner);
+        }
+        let mut names = vec![];
+        let mut entries = fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+        assert_eq!(names, ["video.mp4", "video_1.mp4"]);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn claims_end_when_their_task_settles() {
+        let manager = TaskManager::new();
+        let path = scratch().join("video.mp4");
+        let (a, b) = (Arc::new("a".to_string()), Arc::new("b".to_string()));
+        assert_eq!(manager.reserve_path(&a, path.clone(), false), path);
+        assert_ne!(manager.reserve_path(&b, path.clone(), false), path);
+
+        // Paused keeps the claim for when it resumes
+        manager.settle(&a, &TaskState::Paused).await;
+        assert_ne!(manager.reserve_path(&b, path.clone(), false), path);
+
+        manager.settle(&a, &TaskState::Failed).await;
+        assert_eq!(manager.reserve_path(&a, path.clone(), false), path);
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Number;
 use specta::Type;
@@ -5,8 +6,16 @@ use std::{
     collections::{HashMap, VecDeque},
     path::PathBuf,
     sync::Arc,
+    time::Duration,
 };
 
+use crate::errors::Recovery;
+use crate::services::{
+    bili::{MemberTier, Qualities},
+    subscribe::SubscriptionItem,
+};
+use crate::shared::{get_ts, random_string};
+
 // Media
 
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
@@ -89,12 +98,27 @@ pub struct MediaUrls {
     pub folder: Arc<PathBuf>,
 }
 
+/// What `prefetch_resolution` resolved ahead of time, so the task can start without
+/// asking the frontend while the urls are still fresh.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prefetched {
+    pub nfo: Arc<MediaNfo>,
+    pub urls: Option<Arc<MediaUrls>>,
+    pub folder: Arc<String>,
+    /// Seconds, stream urls stop working a while after they're issued.
+    pub ts: i64,
+}
+
 // Select
 
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
 pub struct PopupSelect {
     #[specta(optional)]
     pub res: Option<usize>,
+    /// Quality originally asked for, set when `res` was capped to the account's tier.
+    #[serde(default, rename = "requestedRes")]
+    #[specta(optional)]
+    pub requested_res: Option<usize>,
     #[specta(optional)]
     pub abr: Option<usize>,
     #[specta(optional)]
@@ -123,6 +147,70 @@ impl StringOrFalse {
     }
 }
 
+/// Bilibili audio stream ids, in the vocabulary used by the `audio_quality` preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub enum AudioQuality {
+    #[serde(rename = "hires")]
+    HiRes,
+    #[serde(rename = "dolby")]
+    Dolby,
+    #[serde(rename = "192k")]
+    K192,
+    #[serde(rename = "132k")]
+    K132,
+    #[serde(rename = "64k")]
+    K64,
+}
+
+impl AudioQuality {
+    /// Best first. The stream ids don't sort this way, Dolby's is below HiRes' and 192k's
+    /// above both.
+    pub const RANK: [AudioQuality; 5] = [
+        AudioQuality::HiRes,
+        AudioQuality::Dolby,
+        AudioQuality::K192,
+        AudioQuality::K132,
+        AudioQuality::K64,
+    ];
+    pub fn id(&self) -> usize {
+        match self {
+            AudioQuality::HiRes => 30251,
+            AudioQuality::Dolby => 30250,
+            AudioQuality::K192 => 30280,
+            AudioQuality::K132 => 30232,
+            AudioQuality::K64 => 30216,
+        }
+    }
+    pub fn from_id(id: usize) -> Option<Self> {
+        match id {
+            30251 | 30252 => Some(AudioQuality::HiRes),
+            30250 => Some(AudioQuality::Dolby),
+            30280 => Some(AudioQuality::K192),
+            30232 => Some(AudioQuality::K132),
+            30216 => Some(AudioQuality::K64),
+            _ => None,
+        }
+    }
+    pub fn requires_vip(&self) -> bool {
+        matches!(self, AudioQuality::HiRes | AudioQuality::Dolby)
+    }
+    /// Picks the first preferred quality present in `available`, falling back to the
+    /// best one by [`Self::RANK`]. Ids it doesn't know come last.
+    pub fn pick(prefs: &[AudioQuality], available: &[usize]) -> Option<usize> {
+        let rank = |id: &usize| {
+            Self::from_id(*id)
+                .and_then(|q| Self::RANK.iter().position(|v| *v == q))
+                .unwrap_or(Self::RANK.len())
+        };
+        prefs
+            .iter()
+            .flat_map(|q| available.iter().find(|id| Self::from_id(**id) == Some(*q)))
+            .next()
+            .or_else(|| available.iter().min_by_key(|id| rank(id)))
+            .copied()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 #[serde(rename_all = "lowercase")]
 pub enum StreamFormat {
@@ -156,6 +244,10 @@ pub struct PopupSelectMedia {
     pub audio: bool,
     #[serde(rename = "audioVideo")]
     pub audio_video: bool,
+    /// Audio only, tagged and named as tracks of an album of the series, see
+    /// `naming::album`.
+    #[serde(default, rename = "musicAlbum")]
+    pub music_album: bool,
 }
 
 impl PopupSelectMedia {
@@ -164,8 +256,205 @@ impl PopupSelectMedia {
     }
 }
 
+impl PopupSelect {
+    /// Subtasks the selection runs, in order. A muxed download also fetches the video
+    /// and audio streams it's made from, as the frontend does on submit.
+    pub fn task_types(&self) -> Vec<TaskType> {
+        let mut types = vec![];
+        if self.media.video || self.media.audio_video {
+            types.push(TaskType::Video);
+        }
+        if self.media.audio || self.media.audio_video {
+            types.push(TaskType::Audio);
+        }
+        if self.media.audio_video {
+            types.push(TaskType::AudioVideo);
+        }
+        if !self.thumb.is_empty() {
+            types.push(TaskType::Thumb);
+        }
+        if self.danmaku.live {
+            types.push(TaskType::LiveDanmaku);
+        }
+        if self.danmaku.history.as_str().is_some() {
+            types.push(TaskType::HistoryDanmaku);
+        }
+        if self.nfo.album {
+            types.push(TaskType::AlbumNfo);
+        }
+        if self.nfo.single {
+            types.push(TaskType::SingleNfo);
+        }
+        if self.misc.ai_summary {
+            types.push(TaskType::AiSummary);
+        }
+        if self.misc.subtitles.as_str().is_some() {
+            types.push(TaskType::Subtitles);
+        }
+        types
+    }
+    /// Whether a guest couldn't download this: a resolution above what guests may
+    /// play, VIP-only audio, or the danmaku history.
+    pub fn requires_auth(&self) -> bool {
+        let streams = self.media.any_true();
+        let res = self.res.is_some_and(|v| v > MemberTier::Guest.max_res());
+        let abr = self
+            .abr
+            .and_then(AudioQuality::from_id)
+            .is_some_and(|v| v.requires_vip());
+        streams && (res || abr) || self.danmaku.history.as_str().is_some()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+pub struct SelectPlan {
+    pub id: Arc<String>,
+    pub title: String,
+    pub index: usize,
+    #[specta(optional)]
+    pub res: Option<usize>,
+    #[specta(optional)]
+    pub abr: Option<usize>,
+    #[specta(optional)]
+    pub enc: Option<usize>,
+    #[specta(optional)]
+    pub audio_quality: Option<AudioQuality>,
+    pub select: Arc<PopupSelect>,
+    pub subtasks: Vec<TaskType>,
+    /// What the part is offered in, left out for media the probe doesn't cover (bangumi,
+    /// audio) or when it failed. Looked up once with the plan, `with_select` keeps it.
+    #[specta(optional)]
+    pub available: Option<Qualities>,
+}
+
+/// An item of a favorite folder, uploader or collection, as `get_select_children`
+/// lists it.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+pub struct SelectChild {
+    #[serde(flatten)]
+    pub item: SubscriptionItem,
+    /// Already queued, then `plan` is the queued task's.
+    pub selected: bool,
+    #[specta(optional)]
+    pub plan: Option<Arc<SelectPlan>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+pub struct SelectChildren {
+    pub items: Vec<SelectChild>,
+    /// Whether a later page may have more.
+    pub more: bool,
+}
+
+impl SelectPlan {
+    pub fn new(task: &Task) -> Self {
+        Self {
+            id: task.id.clone(),
+            title: task.item.title.clone(),
+            index: task.item.index,
+            res: task.select.res,
+            abr: task.select.abr,
+            enc: task.select.enc,
+            audio_quality: task.select.abr.and_then(AudioQuality::from_id),
+            select: task.select.clone(),
+            subtasks: task.select.task_types(),
+            available: None,
+        }
+    }
+    pub fn with_select(&self, select: Arc<PopupSelect>) -> Self {
+        Self {
+            res: select.res,
+            abr: select.abr,
+            enc: select.enc,
+            audio_quality: select.abr.and_then(AudioQuality::from_id),
+            subtasks: select.task_types(),
+            select,
+            ..self.clone()
+        }
+    }
+}
+
 // Tasks
 
+/// Range of the media to keep, in seconds from its start.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Type)]
+pub struct Clip {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Clip {
+    /// `duration` of 0 means it's unknown, then only the order is checked.
+    pub fn check(&self, duration: f64) -> Result<()> {
+        if !(self.start >= 0.0 && self.end > self.start) {
+            return Err(anyhow!(
+                "Clip end must be after its start, got {}-{}",
+                self.start,
+                self.end
+            ));
+        }
+        if duration > 0.0 && self.end > duration {
+            return Err(anyhow!(
+                "Clip end {} is past the end of the media ({duration}s)",
+                self.end
+            ));
+        }
+        Ok(())
+    }
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Resolve,
+    Download,
+    Merge,
+    Convert,
+    Move,
+}
+
+/// Milliseconds the last run of a task spent in each phase, summed over its subtasks.
+/// Downloads include the time they were paused.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    pub resolve: u64,
+    pub download: u64,
+    pub merge: u64,
+    pub convert: u64,
+    #[serde(rename = "move")]
+    pub place: u64,
+    /// Bytes of the downloaded streams.
+    pub bytes: u64,
+    /// Start to finish, set once the task completes.
+    pub wall: u64,
+    /// Average download speed in bytes per second, set with `wall`.
+    pub speed: u64,
+}
+
+impl Timings {
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        match phase {
+            Phase::Resolve => self.resolve += ms,
+            Phase::Download => self.download += ms,
+            Phase::Merge => self.merge += ms,
+            Phase::Convert => self.convert += ms,
+            Phase::Move => self.place += ms,
+        }
+    }
+    pub fn finish(&mut self, wall: Duration) {
+        self.wall = wall.as_millis() as u64;
+        self.speed = match self.download {
+            0 => 0,
+            ms => self.bytes * 1000 / ms,
+        };
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 pub struct QueueData {
     pub waiting: VecDeque<Arc<String>>,
@@ -213,6 +502,261 @@ pub struct Task {
     #[serde(rename = "type")]
     pub media_type: String,
     pub nfo: Arc<MediaNfo>,
+    /// Where the main file was placed, the muxed one when there is one.
+    #[serde(default)]
+    #[specta(optional)]
+    pub output: Option<PathBuf>,
+    /// Url or id the user entered to find this media, when the frontend passes it.
+    #[serde(default)]
+    #[specta(optional)]
+    pub source: Option<String>,
+    /// `select` as submitted, before capping, fallbacks or `update_select` changed it.
+    #[serde(default)]
+    #[specta(optional)]
+    pub submitted: Option<Arc<PopupSelect>>,
+    /// User-given categories, mirrored in `storage::labels` for filtering.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Only this part of the media is kept, cut by FFmpeg after download.
+    #[serde(default)]
+    #[specta(optional)]
+    pub clip: Option<Clip>,
+    /// Whether the video stream picked is a 60fps variant, set once it's chosen.
+    #[serde(default)]
+    #[specta(optional)]
+    pub hfr: Option<bool>,
+    /// How the last failure may be got past, cleared when the task starts again.
+    #[serde(default)]
+    #[specta(optional)]
+    pub recovery: Option<Recovery>,
+    /// Failed after running out of automatic retries, none are made until it's retried
+    /// by hand.
+    #[serde(default)]
+    pub exhausted: bool,
+    /// Milliseconds spent active over every attempt, paused time left out. Counted
+    /// against `max_task_duration` and started over when retried by hand.
+    #[serde(default)]
+    pub active: u64,
+    #[serde(default)]
+    pub timings: Timings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[specta(skip)]
+    pub prefetched: Option<Prefetched>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskSort {
+    /// Last time the task was written, which is when it last changed state.
+    #[default]
+    Updated,
+    Submitted,
+}
+
+/// A page of the archive for `get_tasks`, newest first unless `ascending` is set.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskQuery {
+    pub offset: u64,
+    pub limit: u64,
+    #[serde(default)]
+    pub sort: TaskSort,
+    #[serde(default)]
+    pub ascending: bool,
+    /// Only tasks in one of these states, all of them when empty.
+    #[serde(default)]
+    pub states: Vec<TaskState>,
+}
+
+/// Tasks to act on in bulk, every condition given has to hold.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+pub struct TaskFilter {
+    /// Any of these states, all when empty.
+    #[serde(default)]
+    pub states: Vec<TaskState>,
+    /// Every one of these labels.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Case-insensitive part of the title.
+    #[serde(default)]
+    #[specta(optional)]
+    pub title: Option<String>,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, task: &Task) -> bool {
+        (self.states.is_empty() || self.states.contains(&task.state))
+            && self.labels.iter().all(|v| task.labels.contains(v))
+            && self.title.as_ref().map_or(true, |v| {
+                task.item.title.to_lowercase().contains(&v.to_lowercase())
+            })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+pub struct TaskPage {
+    /// Tasks matching the filter across all pages.
+    pub total: u64,
+    pub tasks: Vec<Arc<Task>>,
+}
+
+/// Counts of a `queue_import`. Duplicates are tasks already queued, invalid ones didn't
+/// parse as a task of this version.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+pub struct QueueImport {
+    pub imported: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+}
+
+/// What was asked for and what the task ended up with, for bug reports and re-downloads.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskDetail {
+    pub id: Arc<String>,
+    pub state: TaskState,
+    pub ts: u64,
+    #[specta(optional)]
+    pub source: Option<String>,
+    #[specta(optional)]
+    pub submitted: Option<Arc<PopupSelect>>,
+    pub select: Arc<PopupSelect>,
+    pub item: Arc<MediaItem>,
+    pub media_type: String,
+    pub folder: Arc<PathBuf>,
+    #[specta(optional)]
+    pub output: Option<PathBuf>,
+    pub labels: Vec<String>,
+    #[specta(optional)]
+    pub clip: Option<Clip>,
+    #[specta(optional)]
+    pub hfr: Option<bool>,
+    #[specta(optional)]
+    pub recovery: Option<Recovery>,
+    pub exhausted: bool,
+    pub active: u64,
+    pub timings: Timings,
+}
+
+impl From<&Task> for TaskDetail {
+    fn from(task: &Task) -> Self {
+        Self {
+            id: task.id.clone(),
+            state: task.state.clone(),
+            ts: task.ts,
+            source: task.source.clone(),
+            submitted: task.submitted.clone(),
+            select: task.select.clone(),
+            item: task.item.clone(),
+            media_type: task.media_type.clone(),
+            folder: task.folder.clone(),
+            output: task.output.clone(),
+            labels: task.labels.clone(),
+            clip: task.clip,
+            hfr: task.hfr,
+            recovery: task.recovery,
+            exhausted: task.exhausted,
+            active: task.active,
+            timings: task.timings.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskOverrides {
+    #[specta(optional)]
+    pub res: Option<usize>,
+    #[specta(optional)]
+    pub abr: Option<usize>,
+    #[specta(optional)]
+    pub enc: Option<usize>,
+    #[specta(optional)]
+    pub fmt: Option<StreamFormat>,
+    #[serde(default)]
+    pub audio_only: bool,
+}
+
+impl Task {
+    /// Clones the task's media identity under a new id, with `overrides` applied to the
+    /// selection and fresh subtasks built the same way the frontend does on submit.
+    pub fn duplicate(&self, id: Arc<String>, seq: usize, overrides: &TaskOverrides) -> Self {
+        let mut select = (*self.select).clone();
+        select.res = overrides.res.or(select.requested_res).or(select.res);
+        select.requested_res = None;
+        select.abr = overrides.abr.or(select.abr);
+        select.enc = overrides.enc.or(select.enc);
+        if let Some(fmt) = &overrides.fmt {
+            select.fmt = fmt.clone();
+        }
+        if overrides.audio_only {
+            select.media = PopupSelectMedia {
+                video: false,
+                audio: true,
+                audio_video: false,
+                music_album: select.media.music_album,
+            };
+        }
+        let subtasks = select
+            .task_types()
+            .into_iter()
+            .map(|task_type| {
+                Arc::new(SubTask {
+                    id: Arc::new(format!("{id}{}", random_string(8))),
+                    task_type,
+                })
+            })
+            .collect::<Vec<_>>();
+        let status = subtasks
+            .iter()
+            .map(|v| {
+                (
+                    v.id.clone(),
+                    Arc::new(SubTaskStatus {
+                        chunk: 0,
+                        content: 0,
+                    }),
+                )
+            })
+            .collect();
+        Self {
+            id,
+            state: TaskState::Pending,
+            subtasks,
+            status,
+            ts: get_ts(true) as u64,
+            seq,
+            folder: Default::default(),
+            select: Arc::new(select),
+            output: None,
+            submitted: None,
+            labels: vec![],
+            hfr: None,
+            recovery: None,
+            exhausted: false,
+            active: 0,
+            timings: Timings::default(),
+            prefetched: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn identity(&self) -> String {
+        let item = &self.item;
+        format!(
+            "{}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{}",
+            self.media_type,
+            item.aid,
+            item.bvid,
+            item.cid,
+            item.epid,
+            item.ssid,
+            item.sid,
+            item.opid,
+            self.clip,
+            serde_json::to_string(&self.select).unwrap_or_default()
+        )
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Type)]
@@ -226,6 +770,15 @@ pub enum TaskState {
     Cancelled,
 }
 
+impl TaskState {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            TaskState::Completed | TaskState::Failed | TaskState::Cancelled
+        )
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
 pub struct SubTask {
     pub id: Arc<String>,
@@ -255,3 +808,44 @@ pub struct SubTaskStatus {
     pub chunk: u64,
     pub content: u64,
 }
+
+#[cfg(test)]
+pub mod fixtures {
+    use super::*;
+
+    /// A pending single-video task for `bvid`, with every output off but the muxed one.
+    pub fn task(id: &str, bvid: &str) -> Task {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "state": "pending",
+            "subtasks": [],
+            "status": {},
+            "ts": 0,
+            "seq": 0,
+            "folder": std::env::temp_dir(),
+            "select": {
+                "fmt": "dash",
+                "misc": { "aiSummary": false, "subtitles": false },
+                "nfo": { "album": false, "single": false },
+                "danmaku": { "live": false, "history": false },
+                "thumb": [],
+                "media": { "video": false, "audio": false, "audioVideo": true },
+            },
+            "item": {
+                "title": "Title",
+                "cover": "",
+                "desc": "",
+                "duration": 60,
+                "pubtime": 0,
+                "type": "video",
+                "aid": 1,
+                "cid": 1,
+                "bvid": bvid,
+                "index": 0,
+            },
+            "type": "video",
+            "nfo": { "tags": [], "stat": {}, "thumbs": [] },
+        }))
+        .unwrap()
+    }
+}
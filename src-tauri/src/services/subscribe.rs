@@ -0,0 +1,457 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+use tauri_specta::Event;
+use tokio::{sync::Mutex, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config,
+    queue::runtime::{QueueEvent, TASK_MANAGER},
+    services::{bili, network},
+    shared::{get_app_handle, get_ts, random_string},
+    storage::{
+        cursors::{self, Cursor},
+        subscriptions::{self, Subscription, SubscriptionKind},
+    },
+    TauriResult,
+};
+
+// Enumeration stops early once it reaches items older than the marker, this bounds
+// a single run of a large favorite folder or channel. The next one carries on after it.
+const MAX_PAGES: usize = 20;
+
+const PAGE_SIZE: usize = 20;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Running enumerations by subscription id, for `cancel_enumeration`.
+static ENUMERATIONS: LazyLock<Mutex<HashMap<String, Arc<CancellationToken>>>> =
+    LazyLock::new(Default::default);
+
+/// Emitted by the background checker when a subscription has new items. The frontend
+/// resolves and enqueues them when `enqueue` is set, and only notifies otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionEvent {
+    pub id: String,
+    pub title: String,
+    pub items: Vec<SubscriptionItem>,
+    pub enqueue: bool,
+}
+
+/// Emitted after each page while a subscription is enumerated.
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct EnumerationProgress {
+    pub id: String,
+    pub page: usize,
+    /// New items found so far, including those from before a resume.
+    pub found: usize,
+    pub done: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionItem {
+    pub aid: u64,
+    pub bvid: String,
+    pub title: String,
+    /// Favorite time for folders, publish time otherwise.
+    pub ts: i64,
+}
+
+#[derive(Deserialize)]
+struct FavMedia {
+    id: u64,
+    bvid: String,
+    title: String,
+    fav_time: i64,
+    // 2 is a video, everything else (audio, collections) can't be enqueued as one
+    #[serde(rename = "type")]
+    media_type: u8,
+}
+
+#[derive(Deserialize)]
+struct FavList {
+    medias: Option<Vec<FavMedia>>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct Archive {
+    aid: u64,
+    bvid: String,
+    title: String,
+    pubdate: i64,
+}
+
+#[derive(Deserialize)]
+struct ArchiveList {
+    #[serde(default)]
+    archives: Vec<Archive>,
+}
+
+impl From<Archive> for SubscriptionItem {
+    fn from(v: Archive) -> Self {
+        Self {
+            aid: v.aid,
+            bvid: v.bvid,
+            title: v.title,
+            ts: v.pubdate,
+        }
+    }
+}
+
+async fn page_favorite(
+    media_id: &str,
+    marker: i64,
+    pn: usize,
+) -> TauriResult<(Vec<SubscriptionItem>, bool)> {
+    let list: FavList = bili::get(
+        "https://api.bilibili.com/x/v3/fav/resource/list",
+        &[
+            ("media_id", media_id.into()),
+            ("pn", pn.to_string()),
+            ("ps", PAGE_SIZE.to_string()),
+            ("order", "mtime".into()),
+            ("platform", "web".into()),
+        ],
+    )
+    .await?;
+    let medias = list.medias.unwrap_or_default();
+    let reached = medias.iter().any(|v| v.fav_time <= marker);
+    let items = medias
+        .into_iter()
+        .filter(|v| v.media_type == 2 && v.fav_time > marker)
+        .map(|v| SubscriptionItem {
+            aid: v.id,
+            bvid: v.bvid,
+            title: v.title,
+            ts: v.fav_time,
+        })
+        .collect();
+    Ok((items, !reached && list.has_more))
+}
+
+async fn page_uploader(
+    mid: &str,
+    marker: i64,
+    pn: usize,
+) -> TauriResult<(Vec<SubscriptionItem>, bool)> {
+    let list: ArchiveList = bili::get(
+        "https://api.bilibili.com/x/series/recArchivesByKeywords",
+        &[
+            ("mid", mid.into()),
+            ("keywords", String::new()),
+            ("pn", pn.to_string()),
+            ("ps", PAGE_SIZE.to_string()),
+        ],
+    )
+    .await?;
+    let len = list.archives.len();
+    let reached = list.archives.iter().any(|v| v.pubdate <= marker);
+    let items = list
+        .archives
+        .into_iter()
+        .filter(|v| v.pubdate > marker)
+        .map(Into::into)
+        .collect();
+    Ok((items, !reached && len == PAGE_SIZE))
+}
+
+async fn page_collection(target: &str, pn: usize) -> TauriResult<(Vec<SubscriptionItem>, bool)> {
+    let (mid, season_id) = target.split_once(':').ok_or(anyhow!(
+        "Collection target must be mid:season_id, got {target}"
+    ))?;
+    let list: ArchiveList = bili::get(
+        "https://api.bilibili.com/x/polymer/web-space/seasons_archives_list",
+        &[
+            ("mid", mid.into()),
+            ("season_id", season_id.into()),
+            ("page_num", pn.to_string()),
+            ("page_size", PAGE_SIZE.to_string()),
+        ],
+    )
+    .await?;
+    let len = list.archives.len();
+    let items = list.archives.into_iter().map(Into::into).collect();
+    // Collections are ordered by episode rather than time, and episodes added later
+    // can be older than the marker, so every page is checked and `refresh` tells new
+    // ones apart by id.
+    Ok((items, len == PAGE_SIZE))
+}
+
+/// Items of page `pn` newer than the marker, every item for collections, and whether
+/// later pages may have more.
+async fn page(sub: &Subscription, pn: usize) -> TauriResult<(Vec<SubscriptionItem>, bool)> {
+    match sub.kind {
+        SubscriptionKind::Favorite => page_favorite(&sub.target, sub.marker, pn).await,
+        SubscriptionKind::Uploader => page_uploader(&sub.target, sub.marker, pn).await,
+        SubscriptionKind::Collection => page_collection(&sub.target, pn).await,
+    }
+}
+
+/// Every item of page `pn` of a folder, uploader or collection, whether subscribed to
+/// or not, and whether later pages may have more.
+pub async fn list_page(
+    kind: SubscriptionKind,
+    target: &str,
+    pn: usize,
+) -> TauriResult<(Vec<SubscriptionItem>, bool)> {
+    match kind {
+        SubscriptionKind::Favorite => page_favorite(target, 0, pn).await,
+        SubscriptionKind::Uploader => page_uploader(target, 0, pn).await,
+        SubscriptionKind::Collection => page_collection(target, pn).await,
+    }
+}
+
+fn source(sub: &Subscription) -> String {
+    format!("{}:{}", sub.kind.as_str(), sub.target)
+}
+
+/// Walks up to `MAX_PAGES` pages of a subscription, saving a cursor after each one so an
+/// interrupted run resumes where it stopped. Returns the cursor it got to and whether
+/// that was the last page, leaving `refresh` to drop or keep the cursor.
+async fn enumerate(sub: &Subscription) -> TauriResult<(Cursor, bool)> {
+    let token = Arc::new(CancellationToken::new());
+    ENUMERATIONS
+        .lock()
+        .await
+        .insert(sub.id.clone(), token.clone());
+    let result = enumerate_pages(sub, &token).await;
+    let mut running = ENUMERATIONS.lock().await;
+    // A later run of the same subscription may have replaced it meanwhile
+    if running.get(&sub.id).is_some_and(|v| Arc::ptr_eq(v, &token)) {
+        running.remove(&sub.id);
+    }
+    result
+}
+
+async fn enumerate_pages(
+    sub: &Subscription,
+    token: &CancellationToken,
+) -> TauriResult<(Cursor, bool)> {
+    let source = source(sub);
+    let mut cursor = cursors::get(&source).await?.unwrap_or(Cursor {
+        page: 0,
+        items: vec![],
+    });
+    if cursor.page > 0 {
+        log::info!("Resuming {source} after page {}", cursor.page);
+    }
+    let last = cursor.page + MAX_PAGES;
+    loop {
+        let (items, more) = tokio::select! {
+            res = page(sub, cursor.page + 1) => res?,
+            _ = token.cancelled() => {
+                log::info!("Enumeration of {source} cancelled after page {}", cursor.page);
+                QueueEvent::EnumerationCancelled {
+                    id: sub.id.clone(),
+                    found: cursor.items.len(),
+                }
+                .emit(get_app_handle())?;
+                // The pages it didn't reach are left to the next run
+                return Ok((cursor, false));
+            }
+        };
+        cursor.page += 1;
+        // Items added since an interruption shift the newest-first listing, so a resumed
+        // run sees the previous page's tail again
+        for item in items {
+            if !cursor.items.iter().any(|v| v.bvid == item.bvid) {
+                cursor.items.push(item);
+            }
+        }
+        let done = !more || cursor.page >= last;
+        EnumerationProgress {
+            id: sub.id.clone(),
+            page: cursor.page,
+            found: cursor.items.len(),
+            done,
+        }
+        .emit(get_app_handle())?;
+        if done {
+            return Ok((cursor, !more));
+        }
+        cursors::save(&source, &cursor).await?;
+    }
+}
+
+/// Lists items newer than the subscription's marker that it didn't return before. The
+/// marker only moves past them once a run got to the last new page, one cut short by
+/// `MAX_PAGES` or a cancel leaves a cursor for the next run to carry on from.
+///
+/// Items are kept as only notified unless `enqueue` is set, and returned again by the
+/// first run that enqueues.
+pub async fn refresh(sub: &Subscription, enqueue: bool) -> TauriResult<Vec<SubscriptionItem>> {
+    let (cursor, complete) = enumerate(sub).await?;
+    let seen = subscriptions::seen(&sub.id).await?;
+    let mut items: Vec<_> = cursor
+        .items
+        .into_iter()
+        .filter(|v| !seen.contains(&v.bvid))
+        .collect();
+    if enqueue {
+        items.extend(subscriptions::notified(&sub.id).await?);
+    }
+    items.sort_by_key(|v| v.ts);
+    subscriptions::mark_seen(&sub.id, &items, enqueue).await?;
+    let source = source(sub);
+    let mut marker = sub.marker;
+    if complete {
+        cursors::delete(&source).await?;
+        marker = subscriptions::newest_seen(&sub.id)
+            .await?
+            .map_or(marker, |v| v.max(marker));
+        if sub.kind != SubscriptionKind::Collection {
+            subscriptions::prune_seen(&sub.id, marker).await?;
+        }
+    } else {
+        // Its items were returned now, only where it stopped is left for the next run
+        let next = Cursor {
+            page: cursor.page,
+            items: vec![],
+        };
+        cursors::save(&source, &next).await?;
+    }
+    subscriptions::update_marker(&sub.id, marker).await?;
+    log::info!(
+        "Subscription {} ({:?} {}): {} new items",
+        sub.id,
+        sub.kind,
+        sub.target,
+        items.len()
+    );
+    Ok(items)
+}
+
+async fn should_enqueue() -> bool {
+    let cfg = config::read();
+    if !cfg.auto_download {
+        return false;
+    }
+    cfg.subscription_on_metered || network::is_metered().await != Some(true)
+}
+
+/// Periodically refreshes due subscriptions, using `last_run` from storage so the
+/// schedule carries over restarts.
+pub async fn watch() -> TauriResult<()> {
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        let interval = config::read().subscription_interval as i64 * 60;
+        if interval == 0 {
+            continue;
+        }
+        let now = get_ts(true);
+        let subs = match subscriptions::load().await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to load subscriptions: {e:#}");
+                continue;
+            }
+        };
+        for sub in subs {
+            if sub.last_run + interval > now {
+                continue;
+            }
+            let enqueue = should_enqueue().await;
+            let items = match TASK_MANAGER.resolve.run(refresh(&sub, enqueue)).await {
+                Ok(v) => v,
+                Err(e) => {
+                    log::warn!("Failed to check subscription {}: {e}", sub.id);
+                    continue;
+                }
+            };
+            if items.is_empty() {
+                continue;
+            }
+            let event = SubscriptionEvent {
+                id: sub.id,
+                title: sub.title,
+                items,
+                enqueue,
+            };
+            if let Err(e) = event.emit(get_app_handle()) {
+                log::warn!("Failed to send subscription event: {e}");
+            }
+        }
+    }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn subscription_add(
+    kind: SubscriptionKind,
+    target: String,
+    title: String,
+) -> TauriResult<Subscription> {
+    let sub = Subscription {
+        id: random_string(12),
+        kind,
+        target,
+        title,
+        marker: 0,
+        last_run: 0,
+        created_at: get_ts(true),
+    };
+    subscriptions::upsert(&sub).await?;
+    Ok(sub)
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn subscription_list() -> TauriResult<Vec<Subscription>> {
+    Ok(subscriptions::load().await?)
+}
+
+/// Checks subscription `id` now. Pass `enqueue` when the items returned are going to be
+/// enqueued, they're otherwise offered again by the next run that does.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn subscription_refresh(id: String, enqueue: bool) -> TauriResult<Vec<SubscriptionItem>> {
+    let sub = subscriptions::get(&id)
+        .await?
+        .ok_or(anyhow!("No subscription found for {id}"))?;
+    refresh(&sub, enqueue).await
+}
+
+/// Stops the running enumeration of subscription `source_id`, dropping the page it was
+/// fetching. Items it already found are kept. Returns whether one was running.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn cancel_enumeration(source_id: String) -> TauriResult<bool> {
+    match ENUMERATIONS.lock().await.remove(&source_id) {
+        Some(token) => {
+            token.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn subscription_remove(id: String) -> TauriResult<()> {
+    if let Some(sub) = subscriptions::get(&id).await? {
+        cursors::delete(&source(&sub)).await?;
+    }
+    subscriptions::delete(&id).await?;
+    Ok(())
+}
+
+/// Drops the saved enumeration cursor so the next refresh starts again from page 1.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn subscription_clear_cursor(id: String) -> TauriResult<()> {
+    let sub = subscriptions::get(&id)
+        .await?
+        .ok_or(anyhow!("No subscription found for {id}"))?;
+    cursors::delete(&source(&sub)).await?;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::time::Duration;
+use tauri::Url;
+use tauri_plugin_http::reqwest::{header, Client};
+use tauri_plugin_updater::UpdaterExt;
+
+use crate::{
+    config,
+    shared::{get_app_handle, UpdateChannel},
+    TauriResult,
+};
+
+// Prereleases are published under a rolling `beta` tag next to the stable releases
+const BETA_ENDPOINTS: &[&str] = &[
+    "https://api.btjawa.top/v1/beta/bilitools",
+    "https://github.com/btjawa/BiliTools/releases/download/beta/latest.json",
+];
+
+const SIZE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateInfo {
+    pub available: bool,
+    pub current: String,
+    #[specta(optional)]
+    pub version: Option<String>,
+    #[specta(optional)]
+    pub notes: Option<String>,
+    /// Bytes of the installer, when the server tells.
+    #[specta(optional)]
+    pub size: Option<u64>,
+}
+
+/// Size of the installer at `url` from a `HEAD` request. Sent without the Bilibili
+/// headers and cookies the shared clients carry.
+async fn download_size(url: Url) -> Option<u64> {
+    let response = Client::new()
+        .head(url)
+        .timeout(SIZE_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    response
+        .headers()
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Asks the endpoints of the `update_channel` setting for a newer release. Only reports
+/// it, installing is left to the frontend once the user agrees.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn check_update_now() -> TauriResult<UpdateInfo> {
+    let app = get_app_handle();
+    let mut builder = app.updater_builder();
+    if config::read().update_channel == UpdateChannel::Beta {
+        let endpoints = BETA_ENDPOINTS
+            .iter()
+            .map(|v| Url::parse(v))
+            .collect::<Result<Vec<_>, _>>()?;
+        builder = builder.endpoints(endpoints)?;
+    }
+    let current = app.package_info().version.to_string();
+    let Some(update) = builder.build()?.check().await? else {
+        return Ok(UpdateInfo {
+            available: false,
+            current,
+            version: None,
+            notes: None,
+            size: None,
+        });
+    };
+    log::info!("Update {} available ({current})", update.version);
+    Ok(UpdateInfo {
+        available: true,
+        current,
+        size: download_size(update.download_url.clone()).await,
+        version: Some(update.version),
+        notes: update.body,
+    })
+}
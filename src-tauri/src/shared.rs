@@ -1,19 +1,21 @@
-use anyhow::Result;
-use arc_swap::ArcSwap;
+use anyhow::{anyhow, Result};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use rand::{distr::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 use specta::Type;
 use std::{
     collections::BTreeMap,
-    path::PathBuf,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     sync::{Arc, LazyLock},
 };
 use tauri::{
+    async_runtime,
     http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
     utils::WindowEffect as TauriWindowEffect,
     AppHandle, Manager, Theme as TauriTheme, Wry,
 };
-use tauri_plugin_http::reqwest::{Client, Proxy};
+use tauri_plugin_http::reqwest::{Client, Proxy, Url};
 use tauri_specta::Event;
 use time::OffsetDateTime;
 use tokio::sync::{OnceCell, RwLock};
@@ -26,37 +28,66 @@ use crate::storage::{
     cookies,
 };
 
-use crate::{TauriError, TauriResult};
+use crate::{
+    errors::{ErrorKind, ErrorParams, Recovery},
+    queue::{runtime::TASK_MANAGER, types::AudioQuality},
+    services::network::CONNECTIONS,
+    TauriError, TauriResult,
+};
 
 pub static APP_HANDLE: LazyLock<Arc<OnceCell<AppHandle<Wry>>>> =
     LazyLock::new(|| Arc::new(OnceCell::new()));
 pub static CONFIG: LazyLock<ArcSwap<Settings>> = LazyLock::new(|| {
     ArcSwap::from_pointee(Settings {
         add_metadata: true,
+        api_hosts: BTreeMap::new(),
+        audio_lyrics: false,
+        auth_retry: true,
+        auto_conc: false,
         auto_check_update: true,
         auto_download: false,
         block_pcdn: true,
+        chapter_split: ChapterSplit::Off,
         check_update: true,
+        clip_reencode: false,
         clipboard: false,
+        control_port: 0,
+        control_token: String::new(),
         convert: SettingsConvert {
             danmaku: true,
             mp3: false,
         },
+        db_pool_size: 6,
+        db_synchronous: DbSync::Normal,
         default: SettingsDefault {
             res: 80,
             abr: 30280,
             enc: 7,
         },
+        delete_transcoded: false,
+        dns_overrides: vec![],
         down_dir: get_app_handle()
             .path()
             .desktop_dir()
             .expect("Failed to get desktop_dir"),
         drag_search: true,
+        existing_file: ExistingPolicy::Skip,
+        ffmpeg_fallback: FfmpegFallback::Separate,
+        file_mode: None,
         format: SettingsFormat {
             series: "{container} - {showtitle} ({downtime:YYYY-MM-DD_HH-mm-ss})".into(),
             item: "({index}) {mediaType} - {title}".into(),
             file: "{taskType} - {title}".into(),
+            audio_quality: vec![
+                AudioQuality::HiRes,
+                AudioQuality::Dolby,
+                AudioQuality::K192,
+                AudioQuality::K132,
+                AudioQuality::K64,
+            ],
         },
+        interactive_graph: false,
+        keep_intermediates: false,
         language: sys_locale::get_locale()
             .map(|c| {
                 let code = c.to_lowercase();
@@ -73,16 +104,49 @@ pub static CONFIG: LazyLock<ArcSwap<Settings>> = LazyLock::new(|| {
                 }
             })
             .unwrap_or("en-US".into()),
+        long_paths: false,
         max_conc: 3,
+        max_connections: 0,
+        max_task_duration: 0,
+        min_conc: 1,
         notify: true,
+        pause_on_battery: 0,
+        pause_on_metered: false,
+        pause_on_sleep: true,
+        post_conc: std::thread::available_parallelism().map_or(1, |v| v.get()),
+        prefer_hfr: false,
+        progress_interval: 250,
+        quiet_hours: None,
+        reconnect_retry: true,
+        region_direct: false,
+        region_retry: false,
+        resolve_conc: 2,
+        second_launch: SecondLaunch::Focus,
+        slow_start: false,
+        stall_timeout: 60,
+        storyboard_interval: 0,
+        stream_order: StreamOrder::Parallel,
+        strict_quality: false,
+        subscription_interval: 0,
+        subscription_on_metered: false,
+        subtitle_encoding: TextEncoding::Utf8,
         temp_dir: get_app_handle()
             .path()
             .temp_dir()
             .expect("Failed to get temp_dir"),
+        temp_near_output: false,
         theme: Theme::Auto,
+        update_channel: UpdateChannel::Stable,
+        url_scheme: false,
+        webview_cookies: true,
         window_effect: WindowEffect::Auto,
+        window_state: WindowState::default(),
+        write_buffer: 16,
+        write_mode: WriteMode::Parallel,
         organize: SettingsOrganize {
             auto_rename: true,
+            info_json: false,
+            playlist: false,
             top_folder: true,
             sub_folder: true,
         },
@@ -95,6 +159,8 @@ pub static CONFIG: LazyLock<ArcSwap<Settings>> = LazyLock::new(|| {
 });
 pub static HEADERS: LazyLock<Headers> = LazyLock::new(Headers::new);
 pub static READY: LazyLock<OnceCell<()>> = LazyLock::new(OnceCell::new);
+pub static INIT_STATUS: LazyLock<ArcSwap<InitStatus>> =
+    LazyLock::new(|| ArcSwap::from_pointee(InitStatus::Pending));
 pub static DATABASE_URL: LazyLock<String> =
     LazyLock::new(|| format!("sqlite://{}", STORAGE_PATH.to_string_lossy()));
 pub static STORAGE_PATH: LazyLock<PathBuf> = LazyLock::new(|| WORKING_PATH.join("Storage"));
@@ -180,10 +246,206 @@ impl WindowEffect {
     }
 }
 
+// Whether and how muxed videos are also cut into one file per chapter
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChapterSplit {
+    #[default]
+    Off,
+    /// Stream copy, fast but each cut lands on the keyframe before the chapter start
+    Copy,
+    /// Re-encode so every cut is exact, slower
+    Reencode,
+}
+
+// What a second launch does with the links passed on its command line
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SecondLaunch {
+    /// Only focus the running window
+    #[default]
+    Focus,
+    /// Also hand the links to the running instance, e.g. from a browser's "open with"
+    Submit,
+}
+
+// Which releases `check_update_now` looks for
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+// Whether a task's video and audio streams download at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamOrder {
+    #[default]
+    Parallel,
+    /// Video first, then audio, for connection limits both at once would trip
+    Sequential,
+}
+
+/// Daily window in which the queue holds off, `start` and `end` as `HH:MM`. A window
+/// whose end comes before its start runs past midnight.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct QuietHours {
+    pub start: String,
+    pub end: String,
+    /// Minutes east of UTC the times are in, the system's local offset when unset.
+    #[specta(optional)]
+    pub utc_offset: Option<i16>,
+}
+
+// When SQLite waits for writes to reach the disk, its `synchronous` pragma
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DbSync {
+    /// Syncs on every commit, nothing committed is lost even on power loss
+    Full,
+    /// Syncs at WAL checkpoints, a power loss may drop the last commits but never
+    /// corrupts the database
+    #[default]
+    Normal,
+    /// Leaves it to the OS, an OS crash or power loss may corrupt the database
+    Off,
+}
+
+// How downloads are written to disk
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    /// Several connections writing segments in parallel, best on SSDs
+    #[default]
+    Parallel,
+    /// A single connection into a preallocated file, avoids seek thrashing on HDDs
+    Sequential,
+}
+
+impl WriteMode {
+    /// Per-download aria2 options for this mode.
+    pub fn aria2_options(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            WriteMode::Parallel => vec![],
+            WriteMode::Sequential => vec![
+                ("split", "1"),
+                ("max-connection-per-server", "1"),
+                // posix_fallocate is only reliable on Linux, elsewhere zero-fill instead
+                (
+                    "file-allocation",
+                    if cfg!(target_os = "linux") {
+                        "falloc"
+                    } else {
+                        "prealloc"
+                    },
+                ),
+            ],
+        }
+    }
+}
+
+// How subtitle and danmaku text files are written
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    /// UTF-8 with a byte order mark, for players that otherwise guess a legacy codepage
+    Utf8Bom,
+    /// For older Chinese setups that only read the legacy national encoding
+    Gb18030,
+}
+
+impl TextEncoding {
+    const BOM: &'static [u8] = b"\xEF\xBB\xBF";
+
+    /// Re-encodes UTF-8 `text`, with or without a BOM of its own, into this encoding.
+    pub fn encode(&self, text: &[u8]) -> Vec<u8> {
+        let text = text.strip_prefix(Self::BOM).unwrap_or(text);
+        match self {
+            TextEncoding::Utf8 => text.to_vec(),
+            TextEncoding::Utf8Bom => [Self::BOM, text].concat(),
+            TextEncoding::Gb18030 => {
+                let text = String::from_utf8_lossy(text);
+                encoding_rs::GB18030.encode(&text).0.into_owned()
+            }
+        }
+    }
+}
+
+// What to do with tasks that need muxing when FFmpeg can't be run
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FfmpegFallback {
+    /// Keep the video and audio streams as separate files and warn
+    #[default]
+    Separate,
+    Fail,
+}
+
+// What to do when a task's output file is already on disk, e.g. from a previous session
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingPolicy {
+    /// Mark the subtask complete without downloading
+    #[default]
+    Skip,
+    Overwrite,
+    /// Always pick a free name, regardless of `organize.auto_rename`
+    Rename,
+}
+
+impl ExistingPolicy {
+    /// Whether a subtask writing to `path` can be skipped before it downloads anything.
+    pub fn skips(&self, path: &Path) -> bool {
+        *self == ExistingPolicy::Skip && path.exists()
+    }
+    /// Final path to place a finished file at.
+    pub fn target(&self, owner: &Arc<String>, path: PathBuf) -> PathBuf {
+        match self {
+            // Only reached if the file showed up while downloading
+            ExistingPolicy::Skip => get_unique_path(owner, path),
+            ExistingPolicy::Overwrite => TASK_MANAGER.reserve_path(owner, path, false),
+            ExistingPolicy::Rename => next_free_path(owner, path),
+        }
+    }
+}
+
+/// Outcome of the storage and services startup spawned in `setup`, emitted once it
+/// settles. Windows opened later can read it with `is_ready`.
+#[derive(Clone, Debug, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum InitStatus {
+    Pending,
+    Ready,
+    Failed { error: String },
+}
+
+pub fn finish_init(res: TauriResult<()>) {
+    let status = match res {
+        Ok(_) => InitStatus::Ready,
+        Err(e) => {
+            log::error!("Initialization failed: {}", e.message);
+            InitStatus::Failed { error: e.message }
+        }
+    };
+    INIT_STATUS.store(Arc::new(status.clone()));
+    if let Err(e) = status.emit(get_app_handle()) {
+        log::error!("Failed to emit init status: {e}");
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Type, Event)]
 pub struct ProcessError {
     pub name: String,
     pub error: String,
+    pub kind: Option<ErrorKind>,
+    pub params: ErrorParams,
+    /// Set for backend errors, see [`TauriError::recovery`].
+    pub recovery: Option<Recovery>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Type, Event)]
@@ -221,8 +483,9 @@ impl Headers {
     }
     pub async fn refresh(&self) -> Result<()> {
         let mut map = self.map.write().await;
-        let cookies = cookies::load()
-            .await?
+        let stored = cookies::load().await?;
+        let logged_in = stored.contains_key("SESSDATA");
+        let cookies = stored
             .iter()
             .map(|(name, value)| {
                 format!(
@@ -234,16 +497,34 @@ impl Headers {
             .collect::<Vec<_>>()
             .join("; ");
         map.insert("Cookie".into(), cookies);
+        cookies::refresh_jar().await?;
         let headers: HeadersData = serde_json::from_value(serde_json::to_value(&*map)?)?;
         drop(map);
         let app = get_app_handle();
         headers.emit(app)?;
+        if !logged_in {
+            return Ok(());
+        }
+        async_runtime::spawn(async {
+            let _ = TASK_MANAGER
+                .auth
+                .relogin()
+                .await
+                .map_err(|e| process_err(e, "auth"));
+        });
         Ok(())
     }
+    pub async fn snapshot(&self) -> BTreeMap<String, String> {
+        self.map.read().await.clone()
+    }
     pub async fn to_header_map(&self) -> Result<HeaderMap> {
         let mut headers = HeaderMap::new();
         let map = self.map.read().await;
         for (key, value) in &*map {
+            // Cookies are scoped per request by `cookies::CookieJar`
+            if key == "Cookie" {
+                continue;
+            }
             headers.insert(
                 HeaderName::from_bytes(key.as_bytes())?,
                 HeaderValue::from_str(value)?,
@@ -261,9 +542,115 @@ pub async fn init_client_no_proxy() -> Result<Client> {
     init_client_inner(false).await
 }
 
+/// Hosts whose base url can be replaced through the `api_hosts` setting.
+pub const API_HOSTS: &[&str] = &[
+    "api.bilibili.com",
+    "passport.bilibili.com",
+    "www.bilibili.com",
+];
+
+pub fn check_api_hosts(hosts: &BTreeMap<String, String>) -> Result<()> {
+    for (host, base) in hosts {
+        if !API_HOSTS.contains(&host.as_str()) {
+            return Err(anyhow!(
+                "Unknown API host {host}, expected one of {}",
+                API_HOSTS.join(", ")
+            ));
+        }
+        let url = Url::parse(base).map_err(|e| anyhow!("Invalid base url for {host}: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(anyhow!(
+                "Invalid base url for {host}: {base} is not http(s)"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Points an official Bilibili url at the base configured for its host in `api_hosts`.
+pub fn api_url(url: &str) -> String {
+    for (host, base) in &config::read().api_hosts {
+        let Some(rest) = url.strip_prefix(&format!("https://{host}")) else {
+            continue;
+        };
+        if rest.is_empty() || rest.starts_with(['/', '?']) {
+            return format!("{}{rest}", base.trim_end_matches('/'));
+        }
+    }
+    url.into()
+}
+
+/// Maps a url on an `api_hosts` base back to the official host, so cookies scoped to
+/// bilibili.com still apply to it.
+pub fn official_url(url: &Url) -> Url {
+    let url_str = url.as_str();
+    for (host, base) in &config::read().api_hosts {
+        let base = base.trim_end_matches('/');
+        let Some(rest) = url_str.strip_prefix(base) else {
+            continue;
+        };
+        if rest.is_empty() || rest.starts_with(['/', '?']) {
+            if let Ok(v) = Url::parse(&format!("https://{host}{rest}")) {
+                return v;
+            }
+        }
+    }
+    url.clone()
+}
+
+struct CachedClient {
+    key: String,
+    client: Client,
+}
+
+/// Clients handed out by `init_client`, indexed by `use_proxy`. Each is swapped for a new
+/// one once the settings it was built from change; requests already holding the old
+/// client finish on it.
+static CLIENTS: [ArcSwapOption<CachedClient>; 2] =
+    [ArcSwapOption::const_empty(), ArcSwapOption::const_empty()];
+
 pub async fn init_client_inner(use_proxy: bool) -> Result<Client> {
+    let headers = HEADERS.to_header_map().await?;
+    let cfg = config::read();
+    let key = serde_json::to_string(&(
+        use_proxy.then_some(&cfg.proxy),
+        &cfg.dns_overrides,
+        &cfg.api_hosts,
+        format!("{headers:?}"),
+    ))?;
+    let slot = &CLIENTS[use_proxy as usize];
+    if let Some(cached) = &*slot.load() {
+        if cached.key == key {
+            return Ok(cached.client.clone());
+        }
+    }
+    let client = build_client(use_proxy, headers)?;
+    log::info!("Built HTTP client (proxy: {use_proxy})");
+    slot.store(Some(Arc::new(CachedClient {
+        key,
+        client: client.clone(),
+    })));
+    Ok(client)
+}
+
+fn build_client(use_proxy: bool, headers: HeaderMap) -> Result<Client> {
+    check_api_hosts(&config::read().api_hosts)?;
     let proxy = &config::read().proxy;
-    let client_builder = Client::builder().default_headers(HEADERS.to_header_map().await?);
+    let client_builder = Client::builder()
+        .default_headers(headers)
+        .cookie_provider(Arc::new(cookies::CookieJar));
+    let mut client_builder = client_builder;
+    for (host, ip) in &config::read().dns_overrides {
+        let ip: IpAddr = ip
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid DNS override for {host}: {ip} is not an IP address"))?;
+        if host.trim().is_empty() {
+            return Err(anyhow!("Invalid DNS override for {ip}: empty host"));
+        }
+        // Port 0 keeps the port from the request url; TLS SNI still uses the host
+        client_builder = client_builder.resolve(host.trim(), SocketAddr::new(ip, 0));
+    }
     let client_builder = if !proxy.address.is_empty() && use_proxy {
         client_builder
             .proxy(Proxy::all(&proxy.address)?.basic_auth(&proxy.username, &proxy.password))
@@ -295,25 +682,28 @@ pub fn random_string(len: usize) -> String {
         .collect()
 }
 
-pub fn get_unique_path(mut path: PathBuf) -> PathBuf {
-    if !config::read().organize.auto_rename {
-        return path;
-    }
-    let mut count = 1;
+/// Even with `auto_rename` off, a path another running task is about to write gets a
+/// numbered name, see `TaskManager::reserve_path`.
+pub fn get_unique_path(owner: &Arc<String>, path: PathBuf) -> PathBuf {
+    let rename = config::read().organize.auto_rename;
+    TASK_MANAGER.reserve_path(owner, path, rename)
+}
+
+fn next_free_path(owner: &Arc<String>, path: PathBuf) -> PathBuf {
+    TASK_MANAGER.reserve_path(owner, path, true)
+}
+
+/// `name_1.ext`, `name_2.ext`, ...
+pub fn numbered_path(path: &Path, count: usize) -> PathBuf {
     let stem = path
         .file_stem()
         .map(|s| s.to_string_lossy().to_string())
         .unwrap_or("file".into());
-
     let ext = path.extension().map(|e| e.to_string_lossy().to_string());
-    while path.exists() {
-        path.set_file_name(match &ext {
-            Some(ext) => format!("{}_{}.{}", stem, count, ext),
-            None => format!("{}_{}", stem, count),
-        });
-        count += 1;
-    }
-    path
+    path.with_file_name(match &ext {
+        Some(ext) => format!("{}_{}.{}", stem, count, ext),
+        None => format!("{}_{}", stem, count),
+    })
 }
 
 pub fn process_err<T: ToString>(e: T, name: &str) -> T {
@@ -322,13 +712,156 @@ pub fn process_err<T: ToString>(e: T, name: &str) -> T {
     let _ = ProcessError {
         name: name.into(),
         error: e.to_string(),
+        kind: None,
+        params: ErrorParams::new(),
+        recovery: None,
     }
     .emit(app);
     e
 }
 
+pub fn process_tauri_err(e: TauriError, name: &str) -> TauriError {
+    let app = get_app_handle();
+    log::error!("{name}: {e}");
+    let _ = ProcessError {
+        name: name.into(),
+        error: e.message.clone(),
+        kind: e.kind,
+        params: e.params.clone(),
+        recovery: Some(e.recovery()),
+    }
+    .emit(app);
+    e
+}
+
+pub async fn check_dir(path: &Path, name: &str, required: u64) -> TauriResult<()> {
+    let display = path.display();
+    match tokio::fs::metadata(path).await {
+        Ok(meta) if meta.is_dir() => (),
+        Ok(_) => {
+            return Err(TauriError::new(
+                format!("{name} ({display}) is not a directory"),
+                Option::<isize>::None,
+            )
+            .with_kind(ErrorKind::DirUnavailable)
+            .with_param("name", name)
+            .with_param("path", &display))
+        }
+        Err(e) => {
+            return Err(TauriError::new(
+                format!("{name} ({display}) is not accessible: {e}"),
+                e.raw_os_error(),
+            )
+            .with_kind(ErrorKind::DirUnavailable)
+            .with_param("name", name)
+            .with_param("path", &display))
+        }
+    }
+    let probe = path.join(format!(".bilitools-{}", random_string(8)));
+    if let Err(e) = tokio::fs::write(&probe, []).await {
+        return Err(TauriError::new(
+            format!("{name} ({display}) is not writable: {e}"),
+            e.raw_os_error(),
+        )
+        .with_kind(ErrorKind::DirNotWritable)
+        .with_param("name", name)
+        .with_param("path", &display));
+    }
+    let _ = tokio::fs::remove_file(&probe).await;
+    let available = fs4::available_space(path)?;
+    if available < required {
+        return Err(TauriError::new(
+            format!("{name} ({display}) needs {required} bytes but only {available} are available"),
+            Option::<isize>::None,
+        )
+        .with_kind(ErrorKind::InsufficientSpace)
+        .with_param("name", name)
+        .with_param("path", &display)
+        .with_param("required", required)
+        .with_param("available", available));
+    }
+    Ok(())
+}
+
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+pub async fn place_file(src: &Path, dst: &Path) -> TauriResult<()> {
+    let result = place_file_inner(src, dst).await;
+    TASK_MANAGER.release_path(dst);
+    result
+}
+
+/// Like `place_file` but consumes `src`, renaming it into place when both are on the same
+/// volume and copying otherwise.
+pub async fn move_file(src: &Path, dst: &Path) -> TauriResult<()> {
+    let result = match tokio::fs::rename(src, dst).await {
+        Ok(_) => set_mode(dst).await,
+        Err(_) => match place_file_inner(src, dst).await {
+            Ok(_) => tokio::fs::remove_file(src).await.map_err(Into::into),
+            Err(e) => Err(e),
+        },
+    };
+    TASK_MANAGER.release_path(dst);
+    result
+}
+
+async fn set_mode(path: &Path) -> TauriResult<()> {
+    #[cfg(unix)]
+    if let Some(mode) = config::read().file_mode {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+async fn place_file_inner(src: &Path, dst: &Path) -> TauriResult<()> {
+    let part = part_path(dst);
+    tokio::fs::copy(src, &part).await?;
+    set_mode(&part).await?;
+    tokio::fs::rename(&part, dst).await?;
+    Ok(())
+}
+
+/// Total size of the files directly inside `dir`, or 0 if it doesn't exist.
+pub async fn dir_size(dir: &Path) -> u64 {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return 0;
+    };
+    let mut size = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Ok(meta) = entry.metadata().await {
+            size += meta.len();
+        }
+    }
+    size
+}
+
+/// Moves a flat directory, copying its files when `rename` can't cross filesystems.
+pub async fn move_dir(src: &Path, dst: &Path) -> TauriResult<()> {
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if tokio::fs::rename(src, dst).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::create_dir_all(dst).await?;
+    let mut entries = tokio::fs::read_dir(src).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        tokio::fs::copy(entry.path(), dst.join(entry.file_name())).await?;
+    }
+    tokio::fs::remove_dir_all(src).await?;
+    Ok(())
+}
+
 pub async fn get_image(path: &PathBuf, url: &String) -> TauriResult<()> {
     let client = init_client().await?;
+    let _conn = CONNECTIONS.acquire(1).await?;
     let response = client.get(url).send().await?;
     if response.status() != StatusCode::OK {
         return Err(TauriError::new(
@@ -397,3 +930,181 @@ pub fn set_window(
         }
     }
 }
+
+/// Outer position and inner size in physical pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct WindowBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Main window placement, saved on close and restored once settings are loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowState {
+    pub always_on_top: bool,
+    pub maximized: bool,
+    /// Size before maximizing, so restoring a maximized window still has somewhere to go.
+    pub bounds: Option<WindowBounds>,
+}
+
+fn read_window_state(window: &tauri::Window) -> TauriResult<WindowState> {
+    let maximized = window.is_maximized()?;
+    let bounds = if maximized || window.is_minimized()? {
+        config::read().window_state.bounds
+    } else {
+        let pos = window.outer_position()?;
+        let size = window.inner_size()?;
+        Some(WindowBounds {
+            x: pos.x,
+            y: pos.y,
+            width: size.width,
+            height: size.height,
+        })
+    };
+    Ok(WindowState {
+        always_on_top: window.is_always_on_top()?,
+        maximized,
+        bounds,
+    })
+}
+
+fn apply_window_state(window: &tauri::Window, state: &WindowState) -> TauriResult<()> {
+    use tauri::{PhysicalPosition, PhysicalSize};
+    window.set_always_on_top(state.always_on_top)?;
+    if let Some(b) = state.bounds {
+        window.set_size(PhysicalSize::new(b.width, b.height))?;
+        // A monitor may have been unplugged since, keep the window reachable
+        let visible = window.available_monitors()?.iter().any(|m| {
+            let (pos, size) = (m.position(), m.size());
+            b.x >= pos.x
+                && b.y >= pos.y
+                && b.x < pos.x + size.width as i32
+                && b.y < pos.y + size.height as i32
+        });
+        if visible {
+            window.set_position(PhysicalPosition::new(b.x, b.y))?;
+        }
+    }
+    if state.maximized {
+        window.maximize()?;
+    }
+    Ok(())
+}
+
+async fn save_window_state(state: &WindowState) -> TauriResult<()> {
+    let mut settings = serde_json::Map::new();
+    settings.insert("window_state".into(), serde_json::to_value(state)?);
+    config::write(settings).await?;
+    Ok(())
+}
+
+/// Saves the main window's placement, called when it's about to close.
+pub fn persist_window(window: &tauri::Window) {
+    let result =
+        read_window_state(window).map(|state| async_runtime::block_on(save_window_state(&state)));
+    if let Err(e) = result.and_then(|v| v) {
+        log::warn!("Failed to save window state: {}", e.message);
+    }
+}
+
+/// Puts the main window back where it was last closed.
+pub fn restore_window() {
+    let Some(window) = get_app_handle()
+        .get_webview_window("main")
+        .map(|v| v.as_ref().window())
+    else {
+        return;
+    };
+    if let Err(e) = apply_window_state(&window, &config::read().window_state) {
+        log::warn!("Failed to restore window state: {}", e.message);
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_window_state(window: tauri::Window) -> TauriResult<WindowState> {
+    read_window_state(&window)
+}
+
+/// Applies and persists `state`. `window_effect`, when given, is saved as the setting and
+/// applied with the current theme, like `set_window`.
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn set_window_state(
+    window: tauri::WebviewWindow,
+    state: WindowState,
+    window_effect: Option<WindowEffect>,
+) -> TauriResult<WindowState> {
+    let win = window.as_ref().window();
+    apply_window_state(&win, &state)?;
+    if let Some(effect) = window_effect {
+        let mut settings = serde_json::Map::new();
+        settings.insert("window_effect".into(), serde_json::to_value(&effect)?);
+        config::write(settings).await?;
+        set_window(window.clone(), config::read().theme.clone(), effect)?;
+    }
+    let state = read_window_state(&win)?;
+    save_window_state(&state).await?;
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached() -> Option<Arc<CachedClient>> {
+        CLIENTS[true as usize].load_full()
+    }
+
+    #[tokio::test]
+    async fn client_is_rebuilt_only_when_its_settings_change() {
+        let _guard = config::TEST_LOCK.lock().await;
+        let before = CONFIG.load_full();
+        init_client().await.unwrap();
+        let first = cached().unwrap();
+
+        let mut cfg = (*before).clone();
+        cfg.max_conc += 1;
+        CONFIG.store(Arc::new(cfg.clone()));
+        init_client().await.unwrap();
+        assert!(Arc::ptr_eq(&first, &cached().unwrap()));
+
+        cfg.proxy.address = "http://127.0.0.1:7890".into();
+        CONFIG.store(Arc::new(cfg));
+        init_client().await.unwrap();
+        let second = cached().unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+
+        // The client without a proxy doesn't depend on it
+        init_client_no_proxy().await.unwrap();
+        let direct = CLIENTS[false as usize].load_full().unwrap();
+        CONFIG.store(before);
+        init_client_no_proxy().await.unwrap();
+        assert!(Arc::ptr_eq(
+            &direct,
+            &CLIENTS[false as usize].load_full().unwrap()
+        ));
+    }
+
+    #[test]
+    fn text_encoding_prefixes() {
+        let text = "1\n中文".as_bytes();
+        let bom = [TextEncoding::BOM, text].concat();
+        assert_eq!(TextEncoding::Utf8.encode(text), text);
+        assert_eq!(TextEncoding::Utf8Bom.encode(text), bom);
+        assert_eq!(TextEncoding::Gb18030.encode(text), b"1\n\xD6\xD0\xCE\xC4");
+    }
+
+    #[test]
+    fn text_encoding_never_doubles_a_bom() {
+        let text = "\u{FEFF}中文".as_bytes();
+        assert_eq!(TextEncoding::Utf8.encode(text), "中文".as_bytes());
+        assert_eq!(TextEncoding::Utf8Bom.encode(text), text);
+        assert!(!TextEncoding::Gb18030
+            .encode(text)
+            .starts_with(TextEncoding::BOM));
+    }
+}
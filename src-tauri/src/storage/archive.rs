@@ -1,21 +1,26 @@
 use anyhow::Result;
 use sea_query::{
-    ColumnDef, Expr, Iden, OnConflict, Query, SqliteQueryBuilder, Table, TableCreateStatement,
+    ColumnDef, Cond, Expr, Func, Iden, OnConflict, Order, Query, SqliteQueryBuilder, Table,
+    TableCreateStatement,
 };
 use sea_query_binder::SqlxBinder;
-use sqlx::Row;
-use std::sync::Arc;
+use serde_json::Value;
+use sqlx::{Row, Sqlite, Transaction};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 use tokio::sync::RwLock;
 
 use crate::{
     queue::{
         runtime::TASK_MANAGER,
-        types::{Task, TaskState},
+        types::{Task, TaskQuery, TaskSort, TaskState},
     },
     shared::get_ts,
 };
 
-use super::db::{get_db, TableSpec};
+use super::{
+    db::{get_db, TableSpec},
+    labels,
+};
 
 #[derive(Iden)]
 pub enum Archive {
@@ -25,11 +30,20 @@ pub enum Archive {
     UpdatedAt,
 }
 
+#[derive(Iden)]
+pub enum Quarantine {
+    Table,
+    Name,
+    Value,
+    Error,
+    CreatedAt,
+}
+
 pub struct ArchiveTable;
 
 impl TableSpec for ArchiveTable {
     const NAME: &'static str = "archive";
-    const LATEST: i32 = 2;
+    const LATEST: i32 = 3;
     
     fn create_stmt() -> TableCreateStatement {
         Table::create()
@@ -45,45 +59,225 @@ impl TableSpec for ArchiveTable {
             .col(ColumnDef::new(Archive::UpdatedAt).integer().not_null())
             .to_owned()
     }
+
+    // v3 records the submitted selection, older rows take their current `select` for it
+    async fn migrate_data(old_table: &str, tx: &mut Transaction<'_, Sqlite>) -> Result<()> {
+        let exists: Option<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+                .bind(old_table)
+                .fetch_optional(&mut **tx)
+                .await?;
+        if exists.is_none() {
+            return Ok(());
+        }
+        let sql = format!("SELECT name, value, updated_at FROM \"{old_table}\"");
+        let rows = sqlx::query(&sql).fetch_all(&mut **tx).await?;
+        for r in rows {
+            let name: String = r.try_get("name")?;
+            let mut value: String = r.try_get("value")?;
+            let updated_at: i64 = r.try_get("updated_at")?;
+            // Unreadable rows are copied as is and quarantined by `load`
+            if let Ok(Value::Object(mut task)) = serde_json::from_str(&value) {
+                if !task.contains_key("submitted") {
+                    if let Some(select) = task.get("select").cloned() {
+                        task.insert("submitted".into(), select);
+                    }
+                    value = serde_json::to_string(&task)?;
+                }
+            }
+            let (sql, values) = Query::insert()
+                .into_table(Archive::Table)
+                .columns([Archive::Name, Archive::Value, Archive::UpdatedAt])
+                .values_panic([name.into(), value.into(), updated_at.into()])
+                .on_conflict(OnConflict::column(Archive::Name).do_nothing().to_owned())
+                .build_sqlx(SqliteQueryBuilder);
+            sqlx::query_with(&sql, values).execute(&mut **tx).await?;
+        }
+        Ok(())
+    }
 }
 
-pub async fn load() -> Result<()> {
-    // 优化点2：提前获取数据库连接
-    let pool = get_db().await?;
-    
-    let (sql, values) = Query::select()
-        .columns([Archive::Value])
-        .from(Archive::Table)
-        .build_sqlx(SqliteQueryBuilder);
+/// Archive rows that failed to deserialize, kept for later inspection.
+pub struct QuarantineTable;
 
-    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
-    
-    // 优化点3：单次写操作清空任务列表
-    let mut tasks = TASK_MANAGER.tasks.write().await;
-    tasks.clear();
-    
-    // 优化点4：预先分配内存空间
-    if !rows.is_empty() {
-        tasks.reserve(rows.len());
+impl TableSpec for QuarantineTable {
+    const NAME: &'static str = "archive_quarantine";
+    const LATEST: i32 = 1;
+
+    fn create_stmt() -> TableCreateStatement {
+        Table::create()
+            .table(Quarantine::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Quarantine::Name)
+                    .text()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Quarantine::Value).text().not_null())
+            .col(ColumnDef::new(Quarantine::Error).text().not_null())
+            .col(ColumnDef::new(Quarantine::CreatedAt).integer().not_null())
+            .to_owned()
     }
-    
-    for r in rows {
-        // 优化点5：简化值获取
-        let value_str: String = r.try_get("value")?;
-        let mut task: Task = serde_json::from_str(&value_str)?;
+}
+
+type Parsed = (String, String, serde_json::Result<Task>);
+
+/// Deserializes the rows on the blocking pool, one batch per core. Each row stands on
+/// its own, so the batches can finish in any order.
+async fn parse(rows: Vec<(String, String)>) -> Result<Vec<Parsed>> {
+    let threads = std::thread::available_parallelism().map_or(1, |v| v.get());
+    let size = rows.len().div_ceil(threads).max(1);
+    let mut rows = rows.into_iter();
+    let mut batches = vec![];
+    loop {
+        let batch = rows.by_ref().take(size).collect::<Vec<_>>();
+        if batch.is_empty() {
+            break;
+        }
+        batches.push(tokio::task::spawn_blocking(move || {
+            batch
+                .into_iter()
+                .map(|(name, value)| {
+                    let task = serde_json::from_str(&value);
+                    (name, value, task)
+                })
+                .collect::<Vec<_>>()
+        }));
+    }
+    let mut parsed = vec![];
+    for batch in batches {
+        parsed.extend(batch.await?);
+    }
+    Ok(parsed)
+}
+
+pub async fn load() -> Result<()> {
+    let started = Instant::now();
+    // 优化点3：单次写操作替换任务列表
+    let parsed = parse(rows().await?).await?;
+    let mut loaded = HashMap::with_capacity(parsed.len());
+
+    for (name, value_str, task) in parsed {
+        let mut task = match task {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Skipping corrupt archive row {name}: {e}");
+                if let Err(e) = quarantine(&name, &value_str, &e.to_string()).await {
+                    log::error!("Failed to quarantine archive row {name}: {e:#}");
+                }
+                continue;
+            }
+        };
         
         // 优化点6：简化状态转换逻辑
         if task.state == TaskState::Active {
             task.state = TaskState::Paused;
         }
         
+        // Keeps the label index in step with the tasks, should it have been reset
+        if !task.labels.is_empty() {
+            labels::set(&task.id, &task.labels).await?;
+        }
         let id = task.id.clone();
-        tasks.insert(id, Arc::new(RwLock::new(task)));
+        loaded.insert(id, Arc::new(RwLock::new(task)));
     }
-    
+
+    log::info!(
+        "Loaded {} archived tasks in {:?}",
+        loaded.len(),
+        started.elapsed()
+    );
+    *TASK_MANAGER.tasks.write().await = loaded;
     Ok(())
 }
 
+/// Raw `(name, value)` rows, for checking the table against the tasks in memory.
+pub async fn rows() -> Result<Vec<(String, String)>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns([Archive::Name, Archive::Value])
+        .from(Archive::Table)
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    let mut result = Vec::with_capacity(rows.len());
+    for r in rows {
+        result.push((r.try_get("name")?, r.try_get("value")?));
+    }
+    Ok(result)
+}
+
+/// Ids of one page of rows matching `query`, and how many match in total. The state
+/// and submit time are read out of the stored JSON.
+pub async fn page(query: &TaskQuery) -> Result<(Vec<String>, u64)> {
+    let pool = get_db()?;
+    let mut cond = Cond::any();
+    for state in &query.states {
+        let state = serde_json::to_value(state)?;
+        cond = cond.add(Expr::cust_with_values(
+            "json_extract(value, '$.state') = ?",
+            [state.as_str().unwrap_or_default()],
+        ));
+    }
+    let cond = Cond::all().add_option((!query.states.is_empty()).then_some(cond));
+
+    let (sql, values) = Query::select()
+        .expr(Func::count(Expr::col(Archive::Name)))
+        .from(Archive::Table)
+        .cond_where(cond.clone())
+        .build_sqlx(SqliteQueryBuilder);
+    let total: i64 = sqlx::query_scalar_with(&sql, values)
+        .fetch_one(&pool)
+        .await?;
+
+    let order = match query.ascending {
+        true => Order::Asc,
+        false => Order::Desc,
+    };
+    let sort = match query.sort {
+        TaskSort::Updated => Expr::col(Archive::UpdatedAt).into(),
+        TaskSort::Submitted => Expr::cust("json_extract(value, '$.ts')"),
+    };
+    let (sql, values) = Query::select()
+        .column(Archive::Name)
+        .from(Archive::Table)
+        .cond_where(cond)
+        .order_by_expr(sort, order)
+        // Ties keep a stable order across pages
+        .order_by(Archive::Name, Order::Asc)
+        .limit(query.limit)
+        .offset(query.offset)
+        .build_sqlx(SqliteQueryBuilder);
+    let ids = sqlx::query_scalar_with(&sql, values)
+        .fetch_all(&pool)
+        .await?;
+    Ok((ids, total as u64))
+}
+
+/// Moves an unreadable row out of `archive` so it no longer fails every launch.
+pub async fn quarantine(name: &str, value: &str, error: &str) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::insert()
+        .into_table(Quarantine::Table)
+        .columns([
+            Quarantine::Name,
+            Quarantine::Value,
+            Quarantine::Error,
+            Quarantine::CreatedAt,
+        ])
+        .values_panic([name.into(), value.into(), error.into(), get_ts(true).into()])
+        .on_conflict(
+            OnConflict::column(Quarantine::Name)
+                .update_columns([Quarantine::Value, Quarantine::Error, Quarantine::CreatedAt])
+                .to_owned(),
+        )
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    delete(name).await
+}
+
 pub async fn upsert(task: &Task) -> Result<()> {
     let pool = get_db().await?;
     let now = get_ts(true);
@@ -106,6 +300,21 @@ pub async fn upsert(task: &Task) -> Result<()> {
     Ok(())
 }
 
+/// Deletes the rows of `names` and their labels in one transaction.
+pub async fn delete_many(names: &[String]) -> Result<()> {
+    let pool = get_db()?;
+    let mut tx = pool.begin().await?;
+    let (sql, values) = Query::delete()
+        .from_table(Archive::Table)
+        .cond_where(Expr::col(Archive::Name).is_in(names.iter().cloned()))
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+    labels::clear(&mut tx, names).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
 pub async fn delete(name: &str) -> Result<()> {
     // 优化点8：简化删除操作
     let pool = get_db().await?;
@@ -115,5 +324,5 @@ pub async fn delete(name: &str) -> Result<()> {
         .build_sqlx(SqliteQueryBuilder);
 
     sqlx::query_with(&sql, values).execute(&pool).await?;
-    Ok(())
+    labels::set(name, &[]).await
 }
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specta::Type;
+use std::{
+    collections::HashMap,
+    io::{BufReader, BufWriter, Read},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    queue::{
+        runtime::TASK_MANAGER,
+        types::{Task, TaskState},
+    },
+    shared::get_ts,
+    storage::{archive, config, cookies, labels},
+};
+
+const MANIFEST: &str = "manifest.json";
+const VERSION: u32 = 1;
+
+// Paths and credentials only make sense on the machine they came from
+const LOCAL_KEYS: &[&str] = &["down_dir", "temp_dir", "proxy", "control_token"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum DataCategory {
+    Config,
+    /// Login credentials, only exported with an explicit opt-in.
+    Cookies,
+    Archive,
+}
+
+impl DataCategory {
+    fn file(&self) -> &'static str {
+        match self {
+            DataCategory::Config => "config.json",
+            DataCategory::Cookies => "cookies.json",
+            DataCategory::Archive => "archive.json",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    app: String,
+    created_at: i64,
+    categories: Vec<DataCategory>,
+}
+
+async fn dump(category: DataCategory) -> Result<Vec<u8>> {
+    let value = match category {
+        DataCategory::Config => {
+            let mut value = serde_json::to_value(config::read())?;
+            let map = value
+                .as_object_mut()
+                .ok_or(anyhow!("Failed to read config"))?;
+            for key in LOCAL_KEYS {
+                map.remove(*key);
+            }
+            value
+        }
+        DataCategory::Cookies => serde_json::to_value(cookies::load_rows().await?)?,
+        DataCategory::Archive => {
+            let mut tasks = vec![];
+            for task in TASK_MANAGER.tasks.read().await.values() {
+                tasks.push(task.read().await.clone());
+            }
+            serde_json::to_value(tasks)?
+        }
+    };
+    Ok(serde_json::to_vec_pretty(&value)?)
+}
+
+async fn restore(category: DataCategory, data: &[u8]) -> Result<()> {
+    match category {
+        DataCategory::Config => {
+            let mut settings: serde_json::Map<String, Value> = serde_json::from_slice(data)?;
+            for key in LOCAL_KEYS {
+                settings.remove(*key);
+            }
+            config::write(settings).await?;
+        }
+        DataCategory::Cookies => {
+            let rows: Vec<cookies::CookieRow> = serde_json::from_slice(data)?;
+            for row in rows {
+                cookies::upsert(row).await?;
+            }
+            cookies::refresh_jar().await?;
+        }
+        DataCategory::Archive => {
+            let list: Vec<Task> = serde_json::from_slice(data)?;
+            let mut tasks = TASK_MANAGER.tasks.write().await;
+            for mut task in list {
+                // Tasks already in the queue win over their imported copy
+                if tasks.contains_key(&task.id) {
+                    continue;
+                }
+                if task.state == TaskState::Active {
+                    task.state = TaskState::Paused;
+                }
+                archive::upsert(&task).await?;
+                labels::set(&task.id, &task.labels).await?;
+                tasks.insert(task.id.clone(), Arc::new(RwLock::new(task)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the chosen categories into a gzipped tarball with a manifest. Asking for
+/// cookies fails unless `include_cookies` is also set.
+pub async fn export(
+    output: PathBuf,
+    categories: Vec<DataCategory>,
+    include_cookies: bool,
+    app: String,
+) -> Result<()> {
+    if categories.contains(&DataCategory::Cookies) {
+        if !include_cookies {
+            return Err(anyhow!(
+                "Cookies are login credentials and need include_cookies to be exported"
+            ));
+        }
+        log::warn!(
+            "Exporting cookies to {}, keep this file private",
+            output.display()
+        );
+    }
+    let categories = categories.into_iter().fold(vec![], |mut acc, v| {
+        if !acc.contains(&v) {
+            acc.push(v);
+        }
+        acc
+    });
+    let manifest = Manifest {
+        version: VERSION,
+        app,
+        created_at: get_ts(true),
+        categories: categories.clone(),
+    };
+    let mut files = vec![(MANIFEST, serde_json::to_vec_pretty(&manifest)?)];
+    for category in categories {
+        files.push((category.file(), dump(category).await?));
+    }
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let writer = BufWriter::new(std::fs::File::create(output)?);
+        let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_mtime(get_ts(true) as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, name, data.as_slice())?;
+        }
+        tar.into_inner()?.finish()?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Restores whatever categories the bundle's manifest lists and returns them.
+pub async fn import(input: PathBuf) -> Result<Vec<DataCategory>> {
+    let mut files = tokio::task::spawn_blocking(move || -> Result<HashMap<String, Vec<u8>>> {
+        let reader = BufReader::new(std::fs::File::open(input)?);
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+        let mut files = HashMap::new();
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            files.insert(name, data);
+        }
+        Ok(files)
+    })
+    .await??;
+
+    let manifest: Manifest = serde_json::from_slice(
+        &files
+            .remove(MANIFEST)
+            .ok_or(anyhow!("Not a data bundle: no {MANIFEST} found"))?,
+    )?;
+    if manifest.version > VERSION {
+        return Err(anyhow!(
+            "Bundle version {} is newer than supported ({VERSION})",
+            manifest.version
+        ));
+    }
+    for category in &manifest.categories {
+        let data = files.get(category.file()).ok_or(anyhow!(
+            "Bundle lists {category:?} but has no {}",
+            category.file()
+        ))?;
+        restore(*category, data)
+            .await
+            .with_context(|| format!("Failed to import {category:?}"))?;
+    }
+    Ok(manifest.categories)
+}
@@ -1,15 +1,31 @@
 use anyhow::{anyhow, Context, Result};
 use regex::Regex;
 use sea_query::{
-    ColumnDef, Expr, Iden, OnConflict, Query, SqliteQueryBuilder, Table, TableCreateStatement,
+    ColumnDef, Expr, Iden, Index, OnConflict, Query, SqliteQueryBuilder, Table,
+    TableCreateStatement,
 };
 use sea_query_binder::SqlxBinder;
 use serde::{Deserialize, Serialize};
-use sqlx::Row;
-use std::collections::BTreeMap;
+use specta::Type;
+use sqlx::{Row, Sqlite, Transaction};
+use std::{collections::BTreeMap, sync::RwLock};
+use tauri::http::HeaderValue;
+use tauri_plugin_http::reqwest::{cookie::CookieStore, Url};
 use time::{macros::format_description, PrimitiveDateTime};
 
-use crate::storage::db::{get_db, TableSpec};
+use crate::{
+    shared::{get_ts, official_url},
+    storage::db::{get_db, TableSpec},
+};
+
+// Cookies stored without a domain came from Bilibili's own login endpoints.
+const DEFAULT_DOMAIN: &str = "bilibili.com";
+
+/// Cookies a login needs: the session, the CSRF token for any write and the user id.
+const REQUIRED: [&str; 3] = ["SESSDATA", "bili_jct", "DedeUserID"];
+
+/// In-memory copy of the cookies table, used by [`CookieJar`] which has to answer synchronously.
+static JAR: RwLock<Vec<CookieRow>> = RwLock::new(Vec::new());
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CookieRow {
@@ -22,6 +38,67 @@ pub struct CookieRow {
     pub secure: bool,
 }
 
+/// Something off about a pasted or received cookie value. Values are stored regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieIssue {
+    /// Leading or trailing whitespace, trimmed before storing.
+    Whitespace,
+    /// Wrapped in quotes, usually copied out of a JSON export.
+    Quoted,
+    /// `SESSDATA` is sent percent-encoded as Bilibili set it, so a decoded `,`/`*` or a
+    /// double-encoded `%252C` won't be accepted. `%2C` is how every valid one looks
+    /// (`<hex>%2C<expiry>%2C<hash>`), what breaks a login is that being decoded or
+    /// encoded a second time.
+    Encoding,
+    /// `bili_jct` should be 32 hex digits and `DedeUserID` a number.
+    Format,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct CookieWarning {
+    pub name: String,
+    pub issue: CookieIssue,
+}
+
+/// Whether the stored cookies add up to a login, for the UI to explain failed auth.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginReport {
+    pub complete: bool,
+    /// Required cookies that aren't stored.
+    pub missing: Vec<String>,
+    pub warnings: Vec<CookieWarning>,
+}
+
+/// Looks for the usual mistakes in a cookie value as it was given, before trimming.
+pub fn check(name: &str, raw: &str) -> Vec<CookieIssue> {
+    let mut issues = vec![];
+    if raw.trim() != raw {
+        issues.push(CookieIssue::Whitespace);
+    }
+    let value = raw.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        issues.push(CookieIssue::Quoted);
+    }
+    let value = value.trim_matches('"');
+    let malformed = match name {
+        "SESSDATA" => {
+            if value.contains([',', '*']) || value.to_ascii_uppercase().contains("%25") {
+                issues.push(CookieIssue::Encoding);
+            }
+            false
+        }
+        "bili_jct" => value.len() != 32 || !value.chars().all(|c| c.is_ascii_hexdigit()),
+        "DedeUserID" => value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()),
+        _ => false,
+    };
+    if malformed {
+        issues.push(CookieIssue::Format);
+    }
+    issues
+}
+
 #[derive(Iden)]
 pub enum Cookies {
     Table,
@@ -38,7 +115,7 @@ pub struct CookiesTable;
 
 impl TableSpec for CookiesTable {
     const NAME: &'static str = "cookies";
-    const LATEST: i32 = 1;
+    const LATEST: i32 = 2;
     
     fn create_stmt() -> TableCreateStatement {
         Table::create()
@@ -58,6 +135,41 @@ impl TableSpec for CookiesTable {
             .col(ColumnDef::new(Cookies::Secure).boolean().not_null())
             .to_owned()
     }
+
+    async fn migrate_data(old_table: &str, tx: &mut Transaction<'_, Sqlite>) -> Result<()> {
+        let exists: Option<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+                .bind(old_table)
+                .fetch_optional(&mut **tx)
+                .await?;
+        if exists.is_some() {
+            let cols = "name, value, path, domain, expires, httponly, secure";
+            let sql = format!(
+                "INSERT OR IGNORE INTO {} ({cols}) SELECT {cols} FROM \"{old_table}\"",
+                Self::NAME
+            );
+            sqlx::query(&sql).execute(&mut **tx).await?;
+        }
+        // Index names are global, so a renamed older table may still hold them
+        for (name, col) in [
+            ("idx_cookies_domain", Cookies::Domain),
+            ("idx_cookies_expires", Cookies::Expires),
+        ] {
+            let drop = Index::drop()
+                .if_exists()
+                .name(name)
+                .to_string(SqliteQueryBuilder);
+            sqlx::query(&drop).execute(&mut **tx).await?;
+            let create = Index::create()
+                .if_not_exists()
+                .name(name)
+                .table(Cookies::Table)
+                .col(col)
+                .to_string(SqliteQueryBuilder);
+            sqlx::query(&create).execute(&mut **tx).await?;
+        }
+        Ok(())
+    }
 }
 
 pub async fn load() -> Result<BTreeMap<String, String>> {
@@ -80,7 +192,121 @@ pub async fn load() -> Result<BTreeMap<String, String>> {
     Ok(result)
 }
 
-pub async fn insert(cookie: String) -> Result<()> {
+pub async fn load_rows() -> Result<Vec<CookieRow>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns([
+            Cookies::Name,
+            Cookies::Value,
+            Cookies::Path,
+            Cookies::Domain,
+            Cookies::Expires,
+            Cookies::Httponly,
+            Cookies::Secure,
+        ])
+        .from(Cookies::Table)
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    let mut result = Vec::with_capacity(rows.len());
+    for r in rows {
+        result.push(CookieRow {
+            name: r.try_get("name")?,
+            value: r.try_get("value")?,
+            path: r.try_get("path")?,
+            domain: r.try_get("domain")?,
+            expires: r.try_get("expires")?,
+            httponly: r.try_get("httponly")?,
+            secure: r.try_get("secure")?,
+        });
+    }
+    Ok(result)
+}
+
+impl CookieRow {
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let domain = self
+            .domain
+            .as_deref()
+            .map(|v| v.trim_start_matches('.'))
+            .filter(|v| !v.is_empty())
+            .unwrap_or(DEFAULT_DOMAIN);
+        if host != domain && !host.ends_with(&format!(".{domain}")) {
+            return false;
+        }
+        let path = self
+            .path
+            .as_deref()
+            .filter(|v| !v.is_empty())
+            .unwrap_or("/");
+        let req = url.path();
+        let path_ok = req == path
+            || (req.starts_with(path)
+                && (path.ends_with('/') || req[path.len()..].starts_with('/')));
+        if !path_ok {
+            return false;
+        }
+        if self.secure && url.scheme() != "https" {
+            return false;
+        }
+        match self.expires {
+            Some(ts) => ts > get_ts(true),
+            None => true,
+        }
+    }
+}
+
+fn header_for(rows: &[CookieRow], url: &Url) -> String {
+    let url = official_url(url);
+    rows.iter()
+        .filter(|v| v.matches(&url))
+        .map(|v| {
+            format!(
+                "{}={}",
+                v.name,
+                v.value.replace("\\\"", "").trim_matches('"')
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Builds the `Cookie` header value for `url` from the stored cookies' domain, path and
+/// secure attributes.
+pub async fn cookies_for(url: &str) -> Result<String> {
+    let url = Url::parse(url)?;
+    Ok(header_for(&load_rows().await?, &url))
+}
+
+/// Reloads the cookies served by [`CookieJar`].
+pub async fn refresh_jar() -> Result<()> {
+    let rows = load_rows().await?;
+    *JAR.write().map_err(|e| anyhow!("{e}"))? = rows;
+    Ok(())
+}
+
+/// Read-only cookie provider for the HTTP client; logins write cookies through [`insert`].
+pub struct CookieJar;
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, _: &mut dyn Iterator<Item = &HeaderValue>, _: &Url) {}
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let rows = JAR.read().ok()?;
+        let header = header_for(&rows, url);
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+/// Parses and stores a `Set-Cookie` style string, returning what [`check`] found in its
+/// value.
+pub async fn insert(cookie: String) -> Result<Vec<CookieWarning>> {
     // 优化点5：预编译正则表达式（假设多次调用insert）
     lazy_static::lazy_static! {
         static ref RE_NAME_VALUE: Regex = Regex::new(r"^([^=]+)=([^;]+)").unwrap();
@@ -97,12 +323,19 @@ pub async fn insert(cookie: String) -> Result<()> {
         .as_str()
         .trim()
         .to_string();
-    
-    let value = captures.get(2)
+
+    let raw = captures
+        .get(2)
         .ok_or(anyhow!("Failed to get value from cookie"))?
-        .as_str()
-        .trim()
-        .to_string();
+        .as_str();
+    let warnings = check(&name, raw)
+        .into_iter()
+        .map(|issue| CookieWarning {
+            name: name.clone(),
+            issue,
+        })
+        .collect();
+    let value = raw.trim().to_string();
 
     let mut row = CookieRow {
         name,
@@ -138,6 +371,34 @@ pub async fn insert(cookie: String) -> Result<()> {
         }
     }
 
+    upsert(row).await?;
+    Ok(warnings)
+}
+
+/// Stores every cookie of a login and reports whether the result is a complete one.
+pub async fn insert_many(cookies: impl IntoIterator<Item = String>) -> Result<LoginReport> {
+    let mut warnings = vec![];
+    for cookie in cookies {
+        warnings.extend(insert(cookie).await?);
+    }
+    Ok(report(&load().await?, warnings))
+}
+
+fn report(stored: &BTreeMap<String, String>, warnings: Vec<CookieWarning>) -> LoginReport {
+    let missing: Vec<String> = REQUIRED
+        .iter()
+        .filter(|v| !stored.contains_key(**v))
+        .map(|v| String::from(*v))
+        .collect();
+    LoginReport {
+        complete: missing.is_empty(),
+        missing,
+        warnings,
+    }
+}
+
+/// Stores an already parsed cookie, replacing any with the same name.
+pub async fn upsert(row: CookieRow) -> Result<()> {
     // 优化点8：简化SQL构建
     let (sql, values) = Query::insert()
         .into_table(Cookies::Table)
@@ -170,6 +431,16 @@ pub async fn insert(cookie: String) -> Result<()> {
     Ok(())
 }
 
+pub async fn clear() -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::delete()
+        .from_table(Cookies::Table)
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
 pub async fn delete(name: String) -> Result<()> {
     // 优化点9：简化删除操作
     let pool = get_db().await?;
@@ -181,3 +452,208 @@ pub async fn delete(name: String) -> Result<()> {
     sqlx::query_with(&sql, values).execute(&pool).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, domain: Option<&str>, path: Option<&str>, secure: bool) -> CookieRow {
+        CookieRow {
+            name: name.into(),
+            value: "v".into(),
+            path: path.map(Into::into),
+            domain: domain.map(Into::into),
+            expires: None,
+            httponly: false,
+            secure,
+        }
+    }
+
+    fn url(v: &str) -> Url {
+        Url::parse(v).unwrap()
+    }
+
+    #[test]
+    fn domain_matches_itself_and_subdomains() {
+        let cookie = row("a", Some(".bilibili.com"), None, false);
+        assert!(cookie.matches(&url("https://bilibili.com/")));
+        assert!(cookie.matches(&url("https://api.bilibili.com/x/web-interface/nav")));
+        assert!(!cookie.matches(&url("https://notbilibili.com/")));
+        assert!(!cookie.matches(&url("https://bilibili.com.evil.net/")));
+        assert!(!cookie.matches(&url("https://example.com/")));
+    }
+
+    #[test]
+    fn missing_domain_defaults_to_bilibili() {
+        let cookie = row("a", None, None, false);
+        assert!(cookie.matches(&url("https://www.bilibili.com/")));
+        assert!(!cookie.matches(&url("https://example.com/")));
+    }
+
+    #[test]
+    fn path_matches_on_segment_boundaries() {
+        let cookie = row("a", None, Some("/x"), false);
+        assert!(cookie.matches(&url("https://api.bilibili.com/x")));
+        assert!(cookie.matches(&url("https://api.bilibili.com/x/player")));
+        assert!(!cookie.matches(&url("https://api.bilibili.com/xyz")));
+        assert!(!cookie.matches(&url("https://api.bilibili.com/")));
+
+        let cookie = row("a", None, Some("/x/"), false);
+        assert!(cookie.matches(&url("https://api.bilibili.com/x/player")));
+        assert!(!cookie.matches(&url("https://api.bilibili.com/x")));
+    }
+
+    #[test]
+    fn secure_cookies_skip_http() {
+        let cookie = row("a", None, None, true);
+        assert!(cookie.matches(&url("https://www.bilibili.com/")));
+        assert!(!cookie.matches(&url("http://www.bilibili.com/")));
+        assert!(row("a", None, None, false).matches(&url("http://www.bilibili.com/")));
+    }
+
+    #[test]
+    fn expired_cookies_are_left_out() {
+        let mut cookie = row("a", None, None, false);
+        cookie.expires = Some(get_ts(true) - 1);
+        assert!(!cookie.matches(&url("https://www.bilibili.com/")));
+        cookie.expires = Some(get_ts(true) + 60);
+        assert!(cookie.matches(&url("https://www.bilibili.com/")));
+    }
+
+    #[test]
+    fn header_joins_only_matching_cookies() {
+        let rows = [
+            row("SESSDATA", Some(".bilibili.com"), None, true),
+            row("buvid3", Some(".bilibili.com"), Some("/x"), false),
+            row("other", Some("example.com"), None, false),
+        ];
+        let header = header_for(&rows, &url("https://api.bilibili.com/x/player"));
+        assert_eq!(header, "SESSDATA=v; buvid3=v");
+        let header = header_for(&rows, &url("http://www.bilibili.com/video"));
+        assert_eq!(header, "");
+    }
+
+    const SESSDATA: &str = "1a2b3c4d%2C1767225600%2Cabcde%2A41";
+    const BILI_JCT: &str = "0123456789abcdef0123456789ABCDEF";
+
+    #[test]
+    fn clean_values_pass() {
+        assert!(check("SESSDATA", SESSDATA).is_empty());
+        assert!(check("bili_jct", BILI_JCT).is_empty());
+        assert!(check("DedeUserID", "12345").is_empty());
+        assert!(check("buvid3", " anything ").contains(&CookieIssue::Whitespace));
+    }
+
+    #[test]
+    fn sessdata_issues() {
+        let issues = |v: &str| check("SESSDATA", v);
+        assert_eq!(issues(&format!("{SESSDATA} ")), [CookieIssue::Whitespace]);
+        assert_eq!(issues(&format!("\t{SESSDATA}")), [CookieIssue::Whitespace]);
+        assert_eq!(issues(&format!("\"{SESSDATA}\"")), [CookieIssue::Quoted]);
+        assert_eq!(
+            issues("1a2b3c4d,1767225600,abcde*41"),
+            [CookieIssue::Encoding]
+        );
+        assert_eq!(
+            issues("1a2b3c4d%252C1767225600%252Cabcde"),
+            [CookieIssue::Encoding]
+        );
+        assert_eq!(
+            issues(&format!(" \"{SESSDATA}\"")),
+            [CookieIssue::Whitespace, CookieIssue::Quoted]
+        );
+    }
+
+    #[test]
+    fn bili_jct_must_be_32_hex_digits() {
+        assert_eq!(check("bili_jct", "0123456789abcdef"), [CookieIssue::Format]);
+        assert_eq!(
+            check("bili_jct", "0123456789abcdef0123456789abcdeg"),
+            [CookieIssue::Format]
+        );
+        assert_eq!(check("bili_jct", ""), [CookieIssue::Format]);
+    }
+
+    #[test]
+    fn dede_user_id_must_be_numeric() {
+        assert_eq!(check("DedeUserID", "12a45"), [CookieIssue::Format]);
+        assert_eq!(check("DedeUserID", ""), [CookieIssue::Format]);
+        assert_eq!(check("DedeUserID", "\"123\""), [CookieIssue::Quoted]);
+    }
+
+    #[test]
+    fn report_lists_missing_cookies() {
+        let stored = BTreeMap::from([("SESSDATA".into(), SESSDATA.into())]);
+        let partial = report(&stored, vec![]);
+        assert!(!partial.complete);
+        assert_eq!(partial.missing, ["bili_jct", "DedeUserID"]);
+
+        let stored = REQUIRED
+            .iter()
+            .map(|v| (String::from(*v), String::from("1")))
+            .collect();
+        let warning = CookieWarning {
+            name: "bili_jct".into(),
+            issue: CookieIssue::Format,
+        };
+        let full = report(&stored, vec![warning]);
+        assert!(full.complete);
+        assert!(full.missing.is_empty());
+        assert_eq!(full.warnings.len(), 1);
+    }
+
+    // The cookies table as version 1 created it, before the indexes
+    const V1_SCHEMA: &str = "CREATE TABLE cookies (
+        name TEXT NOT NULL PRIMARY KEY,
+        value TEXT NOT NULL,
+        path TEXT NULL,
+        domain TEXT NULL,
+        expires INTEGER NULL,
+        httponly BOOLEAN NOT NULL,
+        secure BOOLEAN NOT NULL
+    )";
+
+    #[tokio::test]
+    async fn upgrade_from_v1_adds_indexes_and_keeps_rows() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(V1_SCHEMA).execute(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO cookies VALUES ('SESSDATA', 'v', '/', '.bilibili.com', NULL, 1, 1)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // The same steps `check_latest` takes, against this pool instead of the app's
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("ALTER TABLE cookies RENAME TO cookies_1")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let create = CookiesTable::create_stmt().to_string(SqliteQueryBuilder);
+        sqlx::query(&create).execute(&mut *tx).await.unwrap();
+        CookiesTable::migrate_data("cookies_1", &mut tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let indexes: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'cookies' \
+             AND name LIKE 'idx_%' ORDER BY name",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(indexes, ["idx_cookies_domain", "idx_cookies_expires"]);
+
+        let names: Vec<String> = sqlx::query_scalar("SELECT name FROM cookies")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(names, ["SESSDATA"]);
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use sea_query::{
+    ColumnDef, Expr, Iden, OnConflict, Query, SqliteQueryBuilder, Table, TableCreateStatement,
+};
+use sea_query_binder::SqlxBinder;
+use sqlx::Row;
+
+use crate::{services::subscribe::SubscriptionItem, shared::get_ts};
+
+use super::db::{get_db, TableSpec};
+
+/// How far an interrupted enumeration got, so the next run picks up after `page`
+/// with the items already found instead of starting over.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    pub page: usize,
+    pub items: Vec<SubscriptionItem>,
+}
+
+#[derive(Iden)]
+pub enum Cursors {
+    Table,
+    Source,
+    Page,
+    Items,
+    UpdatedAt,
+}
+
+pub struct CursorsTable;
+
+impl TableSpec for CursorsTable {
+    const NAME: &'static str = "cursors";
+    const LATEST: i32 = 1;
+
+    fn create_stmt() -> TableCreateStatement {
+        Table::create()
+            .table(Cursors::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Cursors::Source)
+                    .text()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Cursors::Page).integer().not_null())
+            .col(ColumnDef::new(Cursors::Items).text().not_null())
+            .col(ColumnDef::new(Cursors::UpdatedAt).integer().not_null())
+            .to_owned()
+    }
+}
+
+pub async fn get(source: &str) -> Result<Option<Cursor>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns([Cursors::Page, Cursors::Items])
+        .from(Cursors::Table)
+        .and_where(Expr::col(Cursors::Source).eq(source))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let Some(row) = sqlx::query_with(&sql, values).fetch_optional(&pool).await? else {
+        return Ok(None);
+    };
+    let page: i64 = row.try_get("page")?;
+    let items: String = row.try_get("items")?;
+    Ok(Some(Cursor {
+        page: page as usize,
+        items: serde_json::from_str(&items)?,
+    }))
+}
+
+pub async fn save(source: &str, cursor: &Cursor) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::insert()
+        .into_table(Cursors::Table)
+        .columns([
+            Cursors::Source,
+            Cursors::Page,
+            Cursors::Items,
+            Cursors::UpdatedAt,
+        ])
+        .values_panic([
+            source.into(),
+            (cursor.page as i64).into(),
+            serde_json::to_string(&cursor.items)?.into(),
+            get_ts(true).into(),
+        ])
+        .on_conflict(
+            OnConflict::column(Cursors::Source)
+                .update_columns([Cursors::Page, Cursors::Items, Cursors::UpdatedAt])
+                .to_owned(),
+        )
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+pub async fn delete(source: &str) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::delete()
+        .from_table(Cursors::Table)
+        .cond_where(Expr::col(Cursors::Source).eq(source))
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
@@ -1,26 +1,107 @@
 use anyhow::{anyhow, Result};
+use arc_swap::ArcSwapOption;
 use sea_query::{
     Alias, ColumnDef, Expr, Iden, OnConflict, Query, SqliteQueryBuilder, Table,
     TableCreateStatement,
 };
 use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use specta::Type;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
-    Row, SqlitePool, Transaction,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    Connection, Row, SqliteConnection, SqlitePool, Transaction,
 };
 use std::{
+    collections::BTreeMap,
     future::Future,
-    path::PathBuf,
+    io::{BufReader, BufWriter},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::OnceLock,
+    sync::Arc,
     time::Duration,
 };
-use tokio::fs;
+use tokio::{fs, io::AsyncReadExt};
 
-use crate::shared::{get_ts, DATABASE_URL, STORAGE_PATH};
+use super::{
+    archive::ArchiveTable,
+    config::{Config, ConfigTable},
+    cookies::CookiesTable,
+};
+use crate::shared::{get_ts, DbSync, CONFIG, DATABASE_URL, STORAGE_PATH};
+
+// Swapped out when an imported database replaces the live one
+static DB: ArcSwapOption<SqlitePool> = ArcSwapOption::const_empty();
+
+// SQLite has a single writer, past a handful of connections they only wait on each other
+pub const POOL_SIZE: RangeInclusive<u32> = 2..=16;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
 
-// 使用 OnceLock 替代 RwLock+Option 组合
-static DB: OnceLock<SqlitePool> = OnceLock::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    async fn detect(path: &Path) -> Result<Option<Self>> {
+        let mut magic = [0u8; 4];
+        let mut file = fs::File::open(path).await?;
+        let len = file.read(&mut magic).await?;
+        let magic = &magic[..len];
+        Ok(if magic.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        })
+    }
+
+    async fn compress(self, input: PathBuf, output: PathBuf) -> Result<()> {
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut reader = BufReader::new(std::fs::File::open(input)?);
+            let writer = BufWriter::new(std::fs::File::create(output)?);
+            match self {
+                Compression::Gzip => {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+                    std::io::copy(&mut reader, &mut encoder)?;
+                    encoder.finish()?;
+                }
+                Compression::Zstd => {
+                    let mut encoder = zstd::Encoder::new(writer, 0)?;
+                    std::io::copy(&mut reader, &mut encoder)?;
+                    encoder.finish()?;
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn decompress(self, input: PathBuf, output: PathBuf) -> Result<()> {
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let reader = BufReader::new(std::fs::File::open(input)?);
+            let mut writer = BufWriter::new(std::fs::File::create(output)?);
+            match self {
+                Compression::Gzip => {
+                    let mut decoder = flate2::read::GzDecoder::new(reader);
+                    std::io::copy(&mut decoder, &mut writer)?;
+                }
+                Compression::Zstd => {
+                    let mut decoder = zstd::Decoder::new(reader)?;
+                    std::io::copy(&mut decoder, &mut writer)?;
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
 
 #[derive(Iden)]
 enum Meta {
@@ -70,29 +151,121 @@ pub trait TableSpec: Send + Sync + 'static {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStats {
+    pub max: u32,
+    pub open: u32,
+    pub idle: u32,
+    pub in_use: u32,
+}
+
+/// Settings the pool is opened with, read from the config table directly since settings
+/// are only loaded once the pool exists. Missing on a first launch, giving the defaults.
+async fn stored(opts: &SqliteConnectOptions, names: &[&str]) -> BTreeMap<String, String> {
+    let (sql, values) = Query::select()
+        .columns([Config::Name, Config::Value])
+        .from(Config::Table)
+        .and_where(Expr::col(Config::Name).is_in(names.iter().copied()))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let Ok(mut conn) = SqliteConnection::connect_with(opts).await else {
+        return BTreeMap::new();
+    };
+    let rows = sqlx::query_as_with::<_, (String, String), _>(&sql, values)
+        .fetch_all(&mut conn)
+        .await;
+    let _ = conn.close().await;
+    rows.unwrap_or_default().into_iter().collect()
+}
+
+fn pool_size(value: Option<&String>) -> u32 {
+    let default = CONFIG.load().db_pool_size;
+    let size = value
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(default as i64);
+    let (min, max) = (*POOL_SIZE.start(), *POOL_SIZE.end());
+    let clamped = size.clamp(min as i64, max as i64) as u32;
+    if clamped as i64 != size {
+        log::warn!("db_pool_size {size} is out of {min}..={max}, using {clamped}");
+    }
+    clamped
+}
+
+fn synchronous(value: Option<&String>) -> SqliteSynchronous {
+    let level = value
+        .and_then(|v| serde_json::from_str(v).ok())
+        .unwrap_or(CONFIG.load().db_synchronous);
+    if level == DbSync::Off {
+        log::warn!("db_synchronous is off, a crash may corrupt the database");
+    }
+    match level {
+        DbSync::Full => SqliteSynchronous::Full,
+        DbSync::Normal => SqliteSynchronous::Normal,
+        DbSync::Off => SqliteSynchronous::Off,
+    }
+}
+
 pub async fn init_db() -> Result<()> {
-    let opts = SqliteConnectOptions::from_str(&DATABASE_URL)?
+    open(SqliteConnectOptions::from_str(&DATABASE_URL)?).await
+}
+
+/// Opens a fresh database in the temp folder as the live one, in place of the app's.
+#[cfg(test)]
+pub async fn init_scratch() -> Result<PathBuf> {
+    let name = format!("bilitools-{}.db", crate::shared::random_string(8));
+    let path = std::env::temp_dir().join(name);
+    open(SqliteConnectOptions::new().filename(&path)).await?;
+    Ok(path)
+}
+
+async fn open(opts: SqliteConnectOptions) -> Result<()> {
+    let opts = opts
         .create_if_missing(true)
         .journal_mode(SqliteJournalMode::Wal)
         .foreign_keys(true)
         .busy_timeout(Duration::from_secs(3));
 
+    let stored = stored(&opts, &["db_pool_size", "db_synchronous"]).await;
+    let opts = opts.synchronous(synchronous(stored.get("db_synchronous")));
+
     let pool = SqlitePoolOptions::new()
-        .max_connections(6)
+        .max_connections(pool_size(stored.get("db_pool_size")))
         .min_connections(1)
         .connect_with(opts)
         .await?;
 
-    DB.set(pool).map_err(|_| anyhow!("Database already initialized"))?;
+    if DB
+        .compare_and_swap(&None::<Arc<_>>, Some(Arc::new(pool)))
+        .is_some()
+    {
+        return Err(anyhow!("Database already initialized"));
+    }
     Ok(())
 }
 
-pub fn get_db() -> Result<&'static SqlitePool> {
-    DB.get().ok_or(anyhow!("Database not initialized"))
+/// The live pool. Cloning it is cheap, and a clone keeps working until `close_db`.
+pub fn get_db() -> Result<SqlitePool> {
+    DB.load()
+        .as_deref()
+        .cloned()
+        .ok_or(anyhow!("Database not initialized"))
+}
+
+pub fn pool_stats() -> Result<PoolStats> {
+    let pool = get_db()?;
+    let open = pool.size();
+    let idle = pool.num_idle() as u32;
+    Ok(PoolStats {
+        max: pool.options().get_max_connections(),
+        open,
+        idle,
+        in_use: open.saturating_sub(idle),
+    })
 }
 
 pub async fn close_db() -> Result<()> {
-    if let Some(pool) = DB.get() {
+    if let Some(pool) = DB.swap(None) {
         pool.close().await;
     }
     Ok(())
@@ -112,7 +285,7 @@ pub async fn init_meta() -> Result<()> {
         .to_string(SqliteQueryBuilder);
 
     let pool = get_db()?;
-    sqlx::query(&sql).execute(pool).await?;
+    sqlx::query(&sql).execute(&pool).await?;
     Ok(())
 }
 
@@ -124,13 +297,29 @@ pub async fn get_version(name: &str) -> Result<i32> {
         .build_sqlx(SqliteQueryBuilder);
 
     let pool = get_db()?;
-    if let Some(row) = sqlx::query_with(&sql, values).fetch_optional(pool).await? {
+    if let Some(row) = sqlx::query_with(&sql, values).fetch_optional(&pool).await? {
         Ok(row.try_get::<i32, _>("version")?)
     } else {
         Ok(0)
     }
 }
 
+/// Schema version of every table that has been migrated so far.
+pub async fn versions() -> Result<BTreeMap<String, i32>> {
+    let (sql, values) = Query::select()
+        .columns([Meta::Name, Meta::Version])
+        .from(Meta::Table)
+        .build_sqlx(SqliteQueryBuilder);
+
+    let pool = get_db()?;
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    let mut result = BTreeMap::new();
+    for r in rows {
+        result.insert(r.try_get("name")?, r.try_get("version")?);
+    }
+    Ok(result)
+}
+
 pub async fn set_version(name: &str, value: i32) -> Result<()> {
     let (sql, values) = Query::insert()
         .into_table(Meta::Table)
@@ -144,11 +333,99 @@ pub async fn set_version(name: &str, value: i32) -> Result<()> {
         .build_sqlx(SqliteQueryBuilder);
 
     let pool = get_db()?;
-    sqlx::query_with(&sql, values).execute(pool).await?;
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+// Tables an imported database must have, with the newest version this build can read
+const REQUIRED: &[(&str, i32)] = &[
+    (ConfigTable::NAME, ConfigTable::LATEST),
+    (CookiesTable::NAME, CookiesTable::LATEST),
+    (ArchiveTable::NAME, ArchiveTable::LATEST),
+];
+
+/// Opens `path` read-only and checks it is an intact BiliTools database this version can
+/// migrate, so a wrong or damaged file is rejected before it replaces the live one.
+async fn validate(path: &Path) -> Result<()> {
+    let opts = SqliteConnectOptions::new().filename(path).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&opts)
+        .await
+        .map_err(|e| anyhow!("Not a SQLite database: {e}"))?;
+
+    let problems: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check;")
+        .fetch_all(&mut conn)
+        .await
+        .map_err(|e| anyhow!("Not a SQLite database: {e}"))?;
+    if problems.first().map(String::as_str) != Some("ok") {
+        return Err(anyhow!("Database is damaged: {}", problems.join("; ")));
+    }
+
+    let tables: Vec<String> =
+        sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(&mut conn)
+            .await?;
+    let missing = REQUIRED
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(["meta"])
+        .filter(|name| !tables.iter().any(|v| v == name))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Not a BiliTools database, missing tables: {}",
+            missing.join(", ")
+        ));
+    }
+
+    let (sql, values) = Query::select()
+        .columns([Meta::Name, Meta::Version])
+        .from(Meta::Table)
+        .build_sqlx(SqliteQueryBuilder);
+    let rows = sqlx::query_with(&sql, values).fetch_all(&mut conn).await?;
+    for r in rows {
+        let name: String = r.try_get("name")?;
+        let version: i32 = r.try_get("version")?;
+        if let Some((_, latest)) = REQUIRED.iter().find(|(v, _)| *v == name) {
+            if version > *latest {
+                return Err(anyhow!(
+                    "Table {name} is at version {version}, newer than supported ({latest})"
+                ));
+            }
+        }
+    }
+    conn.close().await?;
+    Ok(())
+}
+
+/// Puts `input` in place of the live database file.
+async fn swap(input: &Path) -> Result<()> {
+    let target = STORAGE_PATH.to_string_lossy();
+    let _ = fs::remove_file(&*target).await;
+    let _ = fs::remove_file(&format!("{target}-wal")).await;
+    let _ = fs::remove_file(&format!("{target}-shm")).await;
+    fs::copy(input, &*target).await?;
     Ok(())
 }
 
 pub async fn import(input: PathBuf) -> Result<()> {
+    // 压缩的备份先解压到临时文件
+    let decompressed = match Compression::detect(&input).await? {
+        Some(compression) => {
+            let temp = STORAGE_PATH.with_extension("import");
+            compression.decompress(input.clone(), temp.clone()).await?;
+            Some(temp)
+        }
+        None => None,
+    };
+    let input = decompressed.clone().unwrap_or(input);
+
+    if let Err(e) = validate(&input).await {
+        if let Some(temp) = decompressed {
+            let _ = fs::remove_file(temp).await;
+        }
+        return Err(e.context("Refusing to import"));
+    }
+
     // 备份当前数据库
     let backup_path = STORAGE_PATH.with_extension("bak");
     if fs::metadata(&*STORAGE_PATH).await.is_ok() {
@@ -158,20 +435,32 @@ pub async fn import(input: PathBuf) -> Result<()> {
     // 关闭当前连接
     close_db().await?;
     
-    // 替换数据库文件
-    let target = STORAGE_PATH.to_string_lossy();
-    let _ = fs::remove_file(&*target).await;
-    let _ = fs::remove_file(&format!("{target}-wal")).await;
-    let _ = fs::remove_file(&format!("{target}-shm")).await;
-    fs::copy(&input, &*target).await?;
-    
-    // 重新初始化
-    init_db().await?;
+    // 替换数据库文件并重新初始化，失败时换回备份
+    let swapped = swap(&input).await;
+    if let Some(temp) = decompressed {
+        let _ = fs::remove_file(temp).await;
+    }
+    let result = match swapped {
+        Ok(_) => init_db().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        if fs::metadata(&backup_path).await.is_ok() {
+            swap(&backup_path).await?;
+        }
+        init_db().await?;
+        return Err(e.context("Failed to replace the database, restored the backup"));
+    }
     Ok(())
 }
 
-pub async fn export(output: PathBuf) -> Result<()> {
+pub async fn export(output: PathBuf, compression: Option<Compression>) -> Result<()> {
     let pool = get_db()?;
+    let target = match compression {
+        Some(_) => STORAGE_PATH.with_extension("export"),
+        None => output.clone(),
+    };
+    let _ = fs::remove_file(&target).await;
     let mut conn = pool.acquire().await?;
     
     // 确保所有数据写入磁盘
@@ -180,10 +469,116 @@ pub async fn export(output: PathBuf) -> Result<()> {
         .await?;
     
     // 执行VACUUM导出
-    let output_str = output.to_string_lossy().replace('\'', "''");
+    let output_str = target.to_string_lossy().replace('\'', "''");
     sqlx::query(&format!("VACUUM INTO '{output_str}';"))
         .execute(&mut *conn)
         .await?;
-    
+    drop(conn);
+
+    if let Some(compression) = compression {
+        let result = compression.compress(target.clone(), output).await;
+        let _ = fs::remove_file(&target).await;
+        result?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::random_string;
+
+    fn scratch(ext: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bilitools-{}.{ext}", random_string(8)))
+    }
+
+    /// A database as `export` writes it, with every required table at `version`.
+    async fn export_fixture(version: i32, skip: Option<&str>) -> PathBuf {
+        let path = scratch("db");
+        let opts = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+        let mut conn = SqliteConnection::connect_with(&opts).await.unwrap();
+        let tables = [
+            (ConfigTable::NAME, ConfigTable::create_stmt()),
+            (CookiesTable::NAME, CookiesTable::create_stmt()),
+            (ArchiveTable::NAME, ArchiveTable::create_stmt()),
+        ];
+        for (name, stmt) in tables {
+            if skip == Some(name) {
+                continue;
+            }
+            let sql = stmt.to_string(SqliteQueryBuilder);
+            sqlx::query(&sql).execute(&mut conn).await.unwrap();
+        }
+        sqlx::query("CREATE TABLE meta (name TEXT NOT NULL PRIMARY KEY, version INTEGER NOT NULL)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        for (name, _) in REQUIRED {
+            sqlx::query("INSERT INTO meta (name, version) VALUES (?, ?)")
+                .bind(name)
+                .bind(version)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        conn.close().await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn valid_export_passes() {
+        let path = export_fixture(1, None).await;
+        let result = validate(&path).await;
+        let _ = fs::remove_file(&path).await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn bogus_file_is_rejected() {
+        let path = scratch("db");
+        fs::write(&path, b"definitely not a database")
+            .await
+            .unwrap();
+        let result = validate(&path).await;
+        let _ = fs::remove_file(&path).await;
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("Not a SQLite database"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn foreign_database_is_rejected() {
+        let path = export_fixture(1, Some(CookiesTable::NAME)).await;
+        let result = validate(&path).await;
+        let _ = fs::remove_file(&path).await;
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("missing tables: cookies"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn newer_schema_is_rejected() {
+        let path = export_fixture(ArchiveTable::LATEST + 1, None).await;
+        let result = validate(&path).await;
+        let _ = fs::remove_file(&path).await;
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("newer than supported"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn compressed_export_is_detected() {
+        let path = export_fixture(1, None).await;
+        let packed = scratch("db.zst");
+        Compression::Zstd
+            .compress(path.clone(), packed.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            Compression::detect(&packed).await.unwrap(),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::detect(&path).await.unwrap(), None);
+        let _ = fs::remove_file(&path).await;
+        let _ = fs::remove_file(&packed).await;
+    }
+}
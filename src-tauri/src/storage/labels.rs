@@ -0,0 +1,119 @@
+use anyhow::Result;
+use sea_query::{
+    ColumnDef, Expr, Func, Iden, Index, OnConflict, Query, SqliteQueryBuilder, Table,
+    TableCreateStatement,
+};
+use sea_query_binder::SqlxBinder;
+use sqlx::{Row, Sqlite, Transaction};
+use std::collections::BTreeMap;
+
+use super::db::{get_db, TableSpec};
+
+/// One row per label of a task, so filtering doesn't have to parse every archived task.
+/// The labels in the archive's task JSON are what gets kept, this follows them.
+#[derive(Iden)]
+pub enum Labels {
+    Table,
+    Label,
+    Task,
+}
+
+pub struct LabelsTable;
+
+impl TableSpec for LabelsTable {
+    const NAME: &'static str = "labels";
+    const LATEST: i32 = 1;
+
+    fn create_stmt() -> TableCreateStatement {
+        Table::create()
+            .table(Labels::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Labels::Label).text().not_null())
+            .col(ColumnDef::new(Labels::Task).text().not_null())
+            // Label first, which is what lookups filter on
+            .primary_key(Index::create().col(Labels::Label).col(Labels::Task))
+            .to_owned()
+    }
+}
+
+/// Replaces the labels stored for `task`.
+pub async fn set(task: &str, labels: &[String]) -> Result<()> {
+    let pool = get_db()?;
+    let mut tx = pool.begin().await?;
+    let (sql, values) = Query::delete()
+        .from_table(Labels::Table)
+        .cond_where(Expr::col(Labels::Task).eq(task))
+        .build_sqlx(SqliteQueryBuilder);
+    sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+    if !labels.is_empty() {
+        let mut query = Query::insert();
+        query
+            .into_table(Labels::Table)
+            .columns([Labels::Label, Labels::Task]);
+        for label in labels {
+            query.values_panic([label.into(), task.into()]);
+        }
+        let (sql, values) = query
+            .on_conflict(
+                OnConflict::columns([Labels::Label, Labels::Task])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+        sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Drops every label of `tasks` as part of a larger transaction.
+pub async fn clear(tx: &mut Transaction<'_, Sqlite>, tasks: &[String]) -> Result<()> {
+    let (sql, values) = Query::delete()
+        .from_table(Labels::Table)
+        .cond_where(Expr::col(Labels::Task).is_in(tasks.iter().cloned()))
+        .build_sqlx(SqliteQueryBuilder);
+    sqlx::query_with(&sql, values).execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// Tasks carrying every one of `labels`.
+pub async fn find(labels: &[String]) -> Result<Vec<String>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .column(Labels::Task)
+        .from(Labels::Table)
+        .and_where(Expr::col(Labels::Label).is_in(labels.iter().cloned()))
+        .group_by_col(Labels::Task)
+        .and_having(Expr::expr(Func::count(Expr::col(Labels::Label))).eq(labels.len() as i64))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    let mut result = Vec::with_capacity(rows.len());
+    for r in rows {
+        result.push(r.try_get("task")?);
+    }
+    Ok(result)
+}
+
+/// Every label in use with how many tasks carry it.
+pub async fn counts() -> Result<BTreeMap<String, usize>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .column(Labels::Label)
+        .expr_as(Func::count(Expr::col(Labels::Task)), CountAlias)
+        .from(Labels::Table)
+        .group_by_col(Labels::Label)
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    let mut result = BTreeMap::new();
+    for r in rows {
+        let count: i64 = r.try_get("count")?;
+        result.insert(r.try_get("label")?, count as usize);
+    }
+    Ok(result)
+}
+
+#[derive(Iden)]
+#[iden = "count"]
+struct CountAlias;
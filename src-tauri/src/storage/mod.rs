@@ -1,13 +1,24 @@
 pub mod archive;
+pub mod bundle;
 pub mod config;
 pub mod cookies;
+pub mod cursors;
 pub mod db;
+pub mod labels;
 mod migrate;
+pub mod presets;
 pub mod schedulers;
+pub mod subscriptions;
 
 use crate::shared::process_err as err;
 use db::TableSpec;
 
+/// Started with `--safe-mode`: skips loading the queue and settings so a broken install
+/// can still be opened and repaired.
+pub fn safe_mode() -> bool {
+    std::env::args().any(|v| v == "--safe-mode")
+}
+
 pub async fn init() -> anyhow::Result<()> {
     migrate::try_migrate()
         .await
@@ -25,7 +36,29 @@ pub async fn init() -> anyhow::Result<()> {
     config::ConfigTable::check_latest()
         .await
         .map_err(|e| err(e, "config"))?;
+    subscriptions::SubscriptionsTable::check_latest()
+        .await
+        .map_err(|e| err(e, "subscriptions"))?;
+    subscriptions::SeenTable::check_latest()
+        .await
+        .map_err(|e| err(e, "subscription_seen"))?;
+    archive::QuarantineTable::check_latest()
+        .await
+        .map_err(|e| err(e, "archive_quarantine"))?;
+    cursors::CursorsTable::check_latest()
+        .await
+        .map_err(|e| err(e, "cursors"))?;
+    labels::LabelsTable::check_latest()
+        .await
+        .map_err(|e| err(e, "labels"))?;
+    presets::PresetsTable::check_latest()
+        .await
+        .map_err(|e| err(e, "presets"))?;
 
+    if safe_mode() {
+        log::warn!("Safe mode: skipping queue and config loading");
+        return Ok(());
+    }
     archive::load().await.map_err(|e| err(e, "archive"))?;
     schedulers::load().await.map_err(|e| err(e, "schedulers"))?;
     config::load().await.map_err(|e| err(e, "config"))?;
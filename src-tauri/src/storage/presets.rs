@@ -0,0 +1,140 @@
+use anyhow::Result;
+use sea_query::{
+    ColumnDef, Expr, Iden, OnConflict, Order, Query, SqliteQueryBuilder, Table,
+    TableCreateStatement,
+};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use sqlx::{sqlite::SqliteRow, Row};
+
+use crate::queue::types::TaskOverrides;
+
+use super::db::{get_db, TableSpec};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PresetItem {
+    /// Url or id, anything search accepts.
+    pub source: String,
+    #[serde(default)]
+    pub overrides: TaskOverrides,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Preset {
+    pub name: String,
+    pub items: Vec<PresetItem>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Iden)]
+pub enum Presets {
+    Table,
+    Name,
+    Items,
+    CreatedAt,
+    UpdatedAt,
+}
+
+pub struct PresetsTable;
+
+impl TableSpec for PresetsTable {
+    const NAME: &'static str = "presets";
+    const LATEST: i32 = 1;
+
+    fn create_stmt() -> TableCreateStatement {
+        Table::create()
+            .table(Presets::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Presets::Name)
+                    .text()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Presets::Items).text().not_null())
+            .col(ColumnDef::new(Presets::CreatedAt).integer().not_null())
+            .col(ColumnDef::new(Presets::UpdatedAt).integer().not_null())
+            .to_owned()
+    }
+}
+
+fn from_row(r: &SqliteRow) -> Result<Preset> {
+    let items: String = r.try_get("items")?;
+    Ok(Preset {
+        name: r.try_get("name")?,
+        items: serde_json::from_str(&items)?,
+        created_at: r.try_get("created_at")?,
+        updated_at: r.try_get("updated_at")?,
+    })
+}
+
+const COLUMNS: [Presets; 4] = [
+    Presets::Name,
+    Presets::Items,
+    Presets::CreatedAt,
+    Presets::UpdatedAt,
+];
+
+pub async fn load() -> Result<Vec<Preset>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns(COLUMNS)
+        .from(Presets::Table)
+        .order_by(Presets::Name, Order::Asc)
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    rows.iter().map(from_row).collect()
+}
+
+pub async fn get(name: &str) -> Result<Option<Preset>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns(COLUMNS)
+        .from(Presets::Table)
+        .and_where(Expr::col(Presets::Name).eq(name))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let row = sqlx::query_with(&sql, values).fetch_optional(&pool).await?;
+    row.as_ref().map(from_row).transpose()
+}
+
+/// Saves `preset`, replacing the items of one by the same name but keeping when it was
+/// first created.
+pub async fn upsert(preset: &Preset) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::insert()
+        .into_table(Presets::Table)
+        .columns(COLUMNS)
+        .values_panic([
+            preset.name.clone().into(),
+            serde_json::to_string(&preset.items)?.into(),
+            preset.created_at.into(),
+            preset.updated_at.into(),
+        ])
+        .on_conflict(
+            OnConflict::column(Presets::Name)
+                .update_columns([Presets::Items, Presets::UpdatedAt])
+                .to_owned(),
+        )
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+/// Whether there was a preset by that name.
+pub async fn delete(name: &str) -> Result<bool> {
+    let pool = get_db()?;
+    let (sql, values) = Query::delete()
+        .from_table(Presets::Table)
+        .cond_where(Expr::col(Presets::Name).eq(name))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let result = sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(result.rows_affected() > 0)
+}
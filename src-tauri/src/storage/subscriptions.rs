@@ -0,0 +1,370 @@
+use anyhow::{anyhow, Result};
+use sea_query::{
+    ColumnDef, Expr, Func, Iden, Index, OnConflict, Order, Query, SqliteQueryBuilder, Table,
+    TableCreateStatement,
+};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use sqlx::{sqlite::SqliteRow, Row, Sqlite, Transaction};
+use std::collections::HashSet;
+
+use crate::{services::subscribe::SubscriptionItem, shared::get_ts};
+
+use super::db::{get_db, TableSpec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionKind {
+    Favorite,
+    Uploader,
+    Collection,
+}
+
+impl SubscriptionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionKind::Favorite => "favorite",
+            SubscriptionKind::Uploader => "uploader",
+            SubscriptionKind::Collection => "collection",
+        }
+    }
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "favorite" => Some(SubscriptionKind::Favorite),
+            "uploader" => Some(SubscriptionKind::Uploader),
+            "collection" => Some(SubscriptionKind::Collection),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub id: String,
+    pub kind: SubscriptionKind,
+    /// Favorite folder id, uploader mid, or `mid:season_id` for collections.
+    pub target: String,
+    pub title: String,
+    /// Newest item time already seen, in seconds.
+    pub marker: i64,
+    pub last_run: i64,
+    pub created_at: i64,
+}
+
+#[derive(Iden)]
+pub enum Subscriptions {
+    Table,
+    Id,
+    Kind,
+    Target,
+    Title,
+    Marker,
+    LastRun,
+    CreatedAt,
+}
+
+pub struct SubscriptionsTable;
+
+impl TableSpec for SubscriptionsTable {
+    const NAME: &'static str = "subscriptions";
+    const LATEST: i32 = 1;
+
+    fn create_stmt() -> TableCreateStatement {
+        Table::create()
+            .table(Subscriptions::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Subscriptions::Id)
+                    .text()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Subscriptions::Kind).text().not_null())
+            .col(ColumnDef::new(Subscriptions::Target).text().not_null())
+            .col(ColumnDef::new(Subscriptions::Title).text().not_null())
+            .col(ColumnDef::new(Subscriptions::Marker).integer().not_null())
+            .col(ColumnDef::new(Subscriptions::LastRun).integer().not_null())
+            .col(
+                ColumnDef::new(Subscriptions::CreatedAt)
+                    .integer()
+                    .not_null(),
+            )
+            .to_owned()
+    }
+}
+
+fn from_row(r: &SqliteRow) -> Result<Subscription> {
+    let kind: String = r.try_get("kind")?;
+    Ok(Subscription {
+        id: r.try_get("id")?,
+        kind: SubscriptionKind::from_str(&kind).ok_or(anyhow!("Unknown kind: {kind}"))?,
+        target: r.try_get("target")?,
+        title: r.try_get("title")?,
+        marker: r.try_get("marker")?,
+        last_run: r.try_get("last_run")?,
+        created_at: r.try_get("created_at")?,
+    })
+}
+
+const COLUMNS: [Subscriptions; 7] = [
+    Subscriptions::Id,
+    Subscriptions::Kind,
+    Subscriptions::Target,
+    Subscriptions::Title,
+    Subscriptions::Marker,
+    Subscriptions::LastRun,
+    Subscriptions::CreatedAt,
+];
+
+pub async fn load() -> Result<Vec<Subscription>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns(COLUMNS)
+        .from(Subscriptions::Table)
+        .order_by(Subscriptions::CreatedAt, Order::Asc)
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    rows.iter().map(from_row).collect()
+}
+
+pub async fn get(id: &str) -> Result<Option<Subscription>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns(COLUMNS)
+        .from(Subscriptions::Table)
+        .and_where(Expr::col(Subscriptions::Id).eq(id))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let row = sqlx::query_with(&sql, values).fetch_optional(&pool).await?;
+    row.as_ref().map(from_row).transpose()
+}
+
+pub async fn upsert(sub: &Subscription) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::insert()
+        .into_table(Subscriptions::Table)
+        .columns(COLUMNS)
+        .values_panic([
+            sub.id.clone().into(),
+            sub.kind.as_str().into(),
+            sub.target.clone().into(),
+            sub.title.clone().into(),
+            sub.marker.into(),
+            sub.last_run.into(),
+            sub.created_at.into(),
+        ])
+        .on_conflict(
+            OnConflict::column(Subscriptions::Id)
+                .update_columns([
+                    Subscriptions::Title,
+                    Subscriptions::Marker,
+                    Subscriptions::LastRun,
+                ])
+                .to_owned(),
+        )
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+pub async fn update_marker(id: &str, marker: i64) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::update()
+        .table(Subscriptions::Table)
+        .values([
+            (Subscriptions::Marker, marker.into()),
+            (Subscriptions::LastRun, get_ts(true).into()),
+        ])
+        .and_where(Expr::col(Subscriptions::Id).eq(id))
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+pub async fn delete(id: &str) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::delete()
+        .from_table(Subscriptions::Table)
+        .cond_where(Expr::col(Subscriptions::Id).eq(id))
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    let (sql, values) = Query::delete()
+        .from_table(Seen::Table)
+        .cond_where(Expr::col(Seen::Subscription).eq(id))
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+/// Items a subscription already returned, by bvid. Collections are matched against it
+/// alone since episodes can be added with any publish time, the other kinds only keep
+/// what's newer than their marker, and what the user was only notified about.
+#[derive(Iden)]
+pub enum Seen {
+    #[iden = "subscription_seen"]
+    Table,
+    Subscription,
+    Bvid,
+    Aid,
+    Title,
+    Ts,
+    /// Unset for items returned by a notify-only run, offered again by the next one
+    /// that enqueues.
+    Enqueued,
+}
+
+pub struct SeenTable;
+
+impl TableSpec for SeenTable {
+    const NAME: &'static str = "subscription_seen";
+    const LATEST: i32 = 2;
+
+    fn create_stmt() -> TableCreateStatement {
+        Table::create()
+            .table(Seen::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Seen::Subscription).text().not_null())
+            .col(ColumnDef::new(Seen::Bvid).text().not_null())
+            .col(ColumnDef::new(Seen::Aid).integer().not_null().default(0))
+            .col(ColumnDef::new(Seen::Title).text().not_null().default(""))
+            .col(ColumnDef::new(Seen::Ts).integer().not_null())
+            .col(
+                ColumnDef::new(Seen::Enqueued)
+                    .boolean()
+                    .not_null()
+                    .default(true),
+            )
+            .primary_key(Index::create().col(Seen::Subscription).col(Seen::Bvid))
+            .to_owned()
+    }
+
+    // Version 1 didn't tell notified items apart, they count as enqueued
+    async fn migrate_data(old_table: &str, tx: &mut Transaction<'_, Sqlite>) -> Result<()> {
+        let exists: Option<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+                .bind(old_table)
+                .fetch_optional(&mut **tx)
+                .await?;
+        if exists.is_some() {
+            let cols = "subscription, bvid, ts";
+            let sql = format!(
+                "INSERT OR IGNORE INTO {} ({cols}) SELECT {cols} FROM \"{old_table}\"",
+                Self::NAME
+            );
+            sqlx::query(&sql).execute(&mut **tx).await?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn seen(id: &str) -> Result<HashSet<String>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .column(Seen::Bvid)
+        .from(Seen::Table)
+        .and_where(Expr::col(Seen::Subscription).eq(id))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows: Vec<String> = sqlx::query_scalar_with(&sql, values)
+        .fetch_all(&pool)
+        .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Items returned by notify-only runs that weren't enqueued since.
+pub async fn notified(id: &str) -> Result<Vec<SubscriptionItem>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .columns([Seen::Aid, Seen::Bvid, Seen::Title, Seen::Ts])
+        .from(Seen::Table)
+        .and_where(Expr::col(Seen::Subscription).eq(id))
+        .and_where(Expr::col(Seen::Enqueued).eq(false))
+        .build_sqlx(SqliteQueryBuilder);
+
+    let rows = sqlx::query_with(&sql, values).fetch_all(&pool).await?;
+    rows.iter()
+        .map(|r| {
+            let aid: i64 = r.try_get("aid")?;
+            Ok(SubscriptionItem {
+                aid: aid as u64,
+                bvid: r.try_get("bvid")?,
+                title: r.try_get("title")?,
+                ts: r.try_get("ts")?,
+            })
+        })
+        .collect()
+}
+
+pub async fn mark_seen(id: &str, items: &[SubscriptionItem], enqueued: bool) -> Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    let pool = get_db()?;
+    let mut query = Query::insert();
+    query.into_table(Seen::Table).columns([
+        Seen::Subscription,
+        Seen::Bvid,
+        Seen::Aid,
+        Seen::Title,
+        Seen::Ts,
+        Seen::Enqueued,
+    ]);
+    for item in items {
+        query.values_panic([
+            id.into(),
+            item.bvid.clone().into(),
+            (item.aid as i64).into(),
+            item.title.clone().into(),
+            item.ts.into(),
+            enqueued.into(),
+        ]);
+    }
+    let (sql, values) = query
+        .on_conflict(
+            OnConflict::columns([Seen::Subscription, Seen::Bvid])
+                .update_columns([Seen::Ts, Seen::Enqueued])
+                .to_owned(),
+        )
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
+
+/// Time of the newest item the subscription returned, across runs cut short.
+pub async fn newest_seen(id: &str) -> Result<Option<i64>> {
+    let pool = get_db()?;
+    let (sql, values) = Query::select()
+        .expr(Func::max(Expr::col(Seen::Ts)))
+        .from(Seen::Table)
+        .and_where(Expr::col(Seen::Subscription).eq(id))
+        .build_sqlx(SqliteQueryBuilder);
+
+    Ok(sqlx::query_scalar_with(&sql, values)
+        .fetch_one(&pool)
+        .await?)
+}
+
+/// Forgets the enqueued items at or older than `marker`, which enumeration skips anyway.
+pub async fn prune_seen(id: &str, marker: i64) -> Result<()> {
+    let pool = get_db()?;
+    let (sql, values) = Query::delete()
+        .from_table(Seen::Table)
+        .cond_where(
+            Expr::col(Seen::Subscription)
+                .eq(id)
+                .and(Expr::col(Seen::Ts).lte(marker))
+                .and(Expr::col(Seen::Enqueued).eq(true)),
+        )
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&sql, values).execute(&pool).await?;
+    Ok(())
+}
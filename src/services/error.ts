@@ -24,6 +24,7 @@ async function getConsumer(url: string) {
 
 export class AppError extends Error {
   original?: Error;
+  code?: number | string;
   constructor(
     input: unknown,
     options?: { code?: number | string; name?: string },
@@ -40,6 +41,7 @@ export class AppError extends Error {
       return {
         message: input.message,
         name: input.name,
+        code: input.code,
         original: input.original ?? input,
       };
     }
@@ -47,6 +49,7 @@ export class AppError extends Error {
       return {
         message: input.message,
         name: options?.name ?? input.name ?? 'AppError',
+        code: options?.code,
         original: input,
       };
     }
@@ -54,6 +57,7 @@ export class AppError extends Error {
       return {
         message: options?.code ? `${input} (${options.code})` : input,
         name: options?.name ?? 'AppError',
+        code: options?.code,
       };
     }
     // fallback
@@ -62,6 +66,7 @@ export class AppError extends Error {
     return {
       message,
       name: options?.name ?? 'AppError',
+      code: err.code ?? options?.code,
     };
   }
   async handle() {
@@ -360,7 +360,10 @@ export async function handleEvent(event: backend.QueueEvent) {
     try {
       result = await handleTask(task, event.action, subtask);
     } catch (e) {
-      new AppError(e).handle();
+      const err = new AppError(e);
+      err.handle();
+      // Keeps the code so the backend can tell rate limits and expired logins apart
+      result = { failed: { message: err.message, code: err.code ?? null } };
     }
     const app = getCurrentWindow();
     app.emit(`${event.action}_${event.subtask ?? event.parent}`, result);